@@ -1,4 +1,28 @@
-document.querySelector('input[name="code"]').addEventListener('paste', function(ev) {
-  ev.preventDefault();
-  this.value = ev.clipboardData.getData('text/plain').trim()
-});
+var code = document.querySelector('input[name="code"]');
+if (code) {
+  code.addEventListener('paste', function(ev) {
+    ev.preventDefault();
+    this.value = ev.clipboardData.getData('text/plain').trim()
+  });
+}
+
+// Poll for confirmation on another device, then complete the login in this tab.
+var complete = document.getElementById('complete');
+if (complete) {
+  var body = new URLSearchParams();
+  body.set('session', complete.elements.session.value);
+  var poll = function() {
+    fetch('/confirm/status', { method: 'POST', body: body })
+      .then(function(res) { return res.json(); })
+      .then(function(res) {
+        if (res.status === 'confirmed') {
+          complete.submit();
+        } else if (res.status === 'pending') {
+          setTimeout(poll, 2000);
+        }
+      }, function() {
+        setTimeout(poll, 5000);
+      });
+  };
+  setTimeout(poll, 2000);
+}
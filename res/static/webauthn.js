@@ -0,0 +1,79 @@
+document.addEventListener('DOMContentLoaded', function() {
+  var form = document.getElementById('form');
+  var button = document.getElementById('passkey');
+  var data = form.dataset;
+
+  if (!window.PublicKeyCredential) {
+    button.disabled = true;
+    return;
+  }
+
+  function decode(value) {
+    var str = atob(value.replace(/-/g, '+').replace(/_/g, '/'));
+    var bytes = new Uint8Array(str.length);
+    for (var i = 0; i < str.length; i++) {
+      bytes[i] = str.charCodeAt(i);
+    }
+    return bytes.buffer;
+  }
+
+  function encode(buffer) {
+    var bytes = new Uint8Array(buffer);
+    var str = '';
+    for (var i = 0; i < bytes.length; i++) {
+      str += String.fromCharCode(bytes[i]);
+    }
+    return btoa(str).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+  }
+
+  function set(name, value) {
+    form.elements[name].value = value;
+  }
+
+  var credentials = data.credentials ? data.credentials.split(' ').map(function(id) {
+    return { type: 'public-key', id: decode(id) };
+  }) : [];
+
+  function register() {
+    return navigator.credentials.create({ publicKey: {
+      rp: { id: data.rpId, name: 'Portier' },
+      user: { id: decode(data.userId), name: data.userName, displayName: data.userName },
+      challenge: decode(data.challenge),
+      pubKeyCredParams: [
+        { type: 'public-key', alg: -7 },
+        { type: 'public-key', alg: -8 },
+        { type: 'public-key', alg: -257 }
+      ],
+      excludeCredentials: credentials,
+      authenticatorSelection: { residentKey: 'required', requireResidentKey: true, userVerification: 'required' },
+      attestation: 'none'
+    }}).then(function(credential) {
+      set('public_key', encode(credential.response.getPublicKey()));
+      set('alg', credential.response.getPublicKeyAlgorithm());
+      set('authenticator_data', encode(credential.response.getAuthenticatorData()));
+      return credential;
+    });
+  }
+
+  function login() {
+    return navigator.credentials.get({ publicKey: {
+      rpId: data.rpId,
+      challenge: decode(data.challenge),
+      userVerification: 'required'
+    }}).then(function(credential) {
+      set('authenticator_data', encode(credential.response.authenticatorData));
+      set('signature', encode(credential.response.signature));
+      return credential;
+    });
+  }
+
+  button.addEventListener('click', function() {
+    (data.stage === 'register' ? register() : login()).then(function(credential) {
+      set('id', encode(credential.rawId));
+      set('client_data', encode(credential.response.clientDataJSON));
+      form.submit();
+    }, function() {
+      // Cancelled or failed. The user can try again, or use the alternative.
+    });
+  });
+});
@@ -2,7 +2,7 @@ use crate::agents::*;
 use crate::crypto::SigningAlgorithm;
 use crate::utils::{
     agent::*,
-    keys::{NamedKeyPair, SignError},
+    keys::{EcdsaP256KeyPair, EcdsaP384KeyPair, NamedKeyPair, SignError},
     pem::{self, ParsedKeyPair},
     SecureRandom,
 };
@@ -24,6 +24,8 @@ pub enum ManualKeysError {
 /// A `KeyManager` where the use provided keys to us manually.
 pub struct ManualKeys {
     ed25519_keys: Vec<NamedKeyPair<Ed25519KeyPair>>,
+    ecdsa_p256_keys: Vec<NamedKeyPair<EcdsaP256KeyPair>>,
+    ecdsa_p384_keys: Vec<NamedKeyPair<EcdsaP384KeyPair>>,
     rsa_keys: Vec<NamedKeyPair<RsaKeyPair>>,
     rng: SecureRandom,
 }
@@ -63,6 +65,8 @@ impl ManualKeys {
         }
 
         let mut ed25519_keys = vec![];
+        let mut ecdsa_p256_keys = vec![];
+        let mut ecdsa_p384_keys = vec![];
         let mut rsa_keys = vec![];
         for (source, entries) in parsed {
             if entries.is_empty() {
@@ -80,14 +84,24 @@ impl ManualKeys {
                 };
 
                 let alg = entry.key_pair.signing_alg();
-                if !signing_algs.contains(&alg) {
+                if !signing_algs.iter().any(|enabled| enabled.key_alg() == alg) {
                     warn!("{} #{}: ignoring, disabled signing algorithm", source, idx);
                     continue;
                 }
 
                 match entry.key_pair {
-                    ParsedKeyPair::Ed25519(key_pair) => ed25519_keys.push(key_pair.into()),
-                    ParsedKeyPair::Rsa(key_pair) => rsa_keys.push(key_pair.into()),
+                    ParsedKeyPair::Ed25519(key_pair) => {
+                        ed25519_keys.push(NamedKeyPair::new(key_pair, signing_algs));
+                    }
+                    ParsedKeyPair::EcdsaP256(key_pair) => {
+                        ecdsa_p256_keys.push(NamedKeyPair::new(key_pair, signing_algs));
+                    }
+                    ParsedKeyPair::EcdsaP384(key_pair) => {
+                        ecdsa_p384_keys.push(NamedKeyPair::new(key_pair, signing_algs));
+                    }
+                    ParsedKeyPair::Rsa(key_pair) => {
+                        rsa_keys.push(NamedKeyPair::new(key_pair, signing_algs));
+                    }
                 }
 
                 let fp = entry.raw.fingerprint();
@@ -99,9 +113,11 @@ impl ManualKeys {
         }
 
         for signing_alg in signing_algs {
-            if match signing_alg {
+            if match signing_alg.key_alg() {
                 SigningAlgorithm::EdDsa => ed25519_keys.is_empty(),
-                SigningAlgorithm::Rs256 => rsa_keys.is_empty(),
+                SigningAlgorithm::Es256 => ecdsa_p256_keys.is_empty(),
+                SigningAlgorithm::Es384 => ecdsa_p384_keys.is_empty(),
+                _ => rsa_keys.is_empty(),
             } {
                 return Err(ManualKeysError::MissingKeys {
                     signing_alg: *signing_alg,
@@ -111,6 +127,8 @@ impl ManualKeys {
 
         Ok(Self {
             ed25519_keys,
+            ecdsa_p256_keys,
+            ecdsa_p384_keys,
             rsa_keys,
             rng,
         })
@@ -125,11 +143,22 @@ impl Handler<SignJws> for ManualKeys {
             SigningAlgorithm::EdDsa => self
                 .ed25519_keys
                 .last()
-                .map(|key| key.sign_jws(&message.payload, &self.rng)),
-            SigningAlgorithm::Rs256 => self
+                .map(|key| key.sign_jws(message.signing_alg, &message.payload, &self.rng)),
+            SigningAlgorithm::Es256 => self
+                .ecdsa_p256_keys
+                .last()
+                .map(|key| key.sign_jws(message.signing_alg, &message.payload, &self.rng)),
+            SigningAlgorithm::Es384 => self
+                .ecdsa_p384_keys
+                .last()
+                .map(|key| key.sign_jws(message.signing_alg, &message.payload, &self.rng)),
+            SigningAlgorithm::Rs256
+            | SigningAlgorithm::Ps256
+            | SigningAlgorithm::Ps384
+            | SigningAlgorithm::Ps512 => self
                 .rsa_keys
                 .last()
-                .map(|key| key.sign_jws(&message.payload, &self.rng)),
+                .map(|key| key.sign_jws(message.signing_alg, &message.payload, &self.rng)),
         };
         cx.reply(maybe_jws.unwrap_or(Err(SignError::UnsupportedAlgorithm(message.signing_alg))));
     }
@@ -137,10 +166,21 @@ impl Handler<SignJws> for ManualKeys {
 
 impl Handler<GetPublicJwks> for ManualKeys {
     fn handle(&mut self, _message: GetPublicJwks, cx: Context<Self, GetPublicJwks>) {
-        let ed25519_jwks = self.ed25519_keys.iter().map(NamedKeyPair::public_jwk);
-        let rsa_jwks = self.rsa_keys.iter().map(NamedKeyPair::public_jwk);
+        let mut jwks = vec![];
+        for key in &self.ed25519_keys {
+            key.append_public_jwks(&mut jwks);
+        }
+        for key in &self.ecdsa_p256_keys {
+            key.append_public_jwks(&mut jwks);
+        }
+        for key in &self.ecdsa_p384_keys {
+            key.append_public_jwks(&mut jwks);
+        }
+        for key in &self.rsa_keys {
+            key.append_public_jwks(&mut jwks);
+        }
         cx.reply(GetPublicJwksReply {
-            jwks: ed25519_jwks.chain(rsa_jwks).collect(),
+            jwks,
             expires: None,
         });
     }
@@ -1,6 +1,6 @@
 use crate::agents::*;
 use crate::crypto::SigningAlgorithm;
-use crate::utils::keys::GenerateRsaConfig;
+use crate::utils::keys::{EcdsaP256KeyPair, EcdsaP384KeyPair, GenerateRsaConfig};
 use crate::utils::{
     agent::*,
     keys::{GeneratedKeyPair, KeyPairExt, NamedKeyPair, SignError},
@@ -96,21 +96,19 @@ struct ActiveKeySet<T: KeyPairExt + GeneratedKeyPair> {
 }
 
 impl<T: KeyPairExt + GeneratedKeyPair> ActiveKeySet<T> {
-    fn parse(key_set: &KeySet) -> Self {
+    fn parse(key_set: &KeySet, signing_algs: &[SigningAlgorithm]) -> Self {
+        let parse_one = |pem: &str| NamedKeyPair::new(Self::parse_one(pem), signing_algs);
         let (current, expires) = key_set
             .current
             .as_ref()
-            .map(|entry| (Self::parse_one(&entry.value).into(), entry.expires))
+            .map(|entry| (parse_one(&entry.value), entry.expires))
             .expect("Provided key set does not have a current key");
         let next = key_set
             .next
             .as_ref()
-            .map(|entry| Self::parse_one(&entry.value).into())
+            .map(|entry| parse_one(&entry.value))
             .expect("Provided key set does not have a next key");
-        let previous = key_set
-            .previous
-            .as_ref()
-            .map(|value| Self::parse_one(value).into());
+        let previous = key_set.previous.as_ref().map(|value| parse_one(value));
         Self {
             current,
             next,
@@ -127,10 +125,10 @@ impl<T: KeyPairExt + GeneratedKeyPair> ActiveKeySet<T> {
     }
 
     fn append_public_jwks(&self, vec: &mut Vec<serde_json::Value>) {
-        vec.push(self.current.public_jwk());
-        vec.push(self.next.public_jwk());
+        self.current.append_public_jwks(vec);
+        self.next.append_public_jwks(vec);
         if let Some(previous) = self.previous.as_ref() {
-            vec.push(previous.public_jwk());
+            previous.append_public_jwks(vec);
         }
     }
 }
@@ -139,11 +137,15 @@ impl<T: KeyPairExt + GeneratedKeyPair> ActiveKeySet<T> {
 pub struct RotatingKeys {
     store: Arc<dyn StoreSender>,
     keys_ttl: Duration,
-    signing_algs: HashSet<SigningAlgorithm>,
+    signing_algs: Vec<SigningAlgorithm>,
+    /// Algorithms identifying the key sets we need, see `SigningAlgorithm::key_alg`.
+    key_algs: HashSet<SigningAlgorithm>,
     rsa_modulus_bits: usize,
     generate_rsa_command: Vec<String>,
     rng: SecureRandom,
     ed25519_keys: Option<ActiveKeySet<Ed25519KeyPair>>,
+    ecdsa_p256_keys: Option<ActiveKeySet<EcdsaP256KeyPair>>,
+    ecdsa_p384_keys: Option<ActiveKeySet<EcdsaP384KeyPair>>,
     rsa_keys: Option<ActiveKeySet<RsaKeyPair>>,
     delays: Option<DelayQueueTask<SigningAlgorithm>>,
 }
@@ -165,11 +167,14 @@ impl RotatingKeys {
         RotatingKeys {
             store,
             keys_ttl,
-            signing_algs: signing_algs.iter().copied().collect(),
+            signing_algs: signing_algs.to_vec(),
+            key_algs: signing_algs.iter().map(|alg| alg.key_alg()).collect(),
             rsa_modulus_bits,
             generate_rsa_command,
             rng,
             ed25519_keys: None,
+            ecdsa_p256_keys: None,
+            ecdsa_p384_keys: None,
             rsa_keys: None,
             delays: None,
         }
@@ -179,7 +184,9 @@ impl RotatingKeys {
         use SigningAlgorithm::*;
         match signing_alg {
             EdDsa => Ed25519KeyPair::generate(self.rng.clone()),
-            Rs256 => RsaKeyPair::generate(GenerateRsaConfig {
+            Es256 => EcdsaP256KeyPair::generate(self.rng.clone()),
+            Es384 => EcdsaP384KeyPair::generate(self.rng.clone()),
+            Rs256 | Ps256 | Ps384 | Ps512 => RsaKeyPair::generate(GenerateRsaConfig {
                 rng: self.rng.clone(),
                 modulus_bits: self.rsa_modulus_bits,
                 command: self.generate_rsa_command.clone(),
@@ -205,7 +212,7 @@ impl Agent for RotatingKeys {
         let store = self.store.clone();
         let enable_msg = EnableRotatingKeys {
             key_manager: cx.addr().clone(),
-            signing_algs: self.signing_algs.clone(),
+            signing_algs: self.key_algs.clone(),
         };
         cx.reply_later(async move {
             store.send(enable_msg).await;
@@ -217,11 +224,13 @@ impl Agent for RotatingKeys {
 impl Handler<Check> for RotatingKeys {
     fn handle(&mut self, _message: Check, cx: Context<Self, Check>) {
         // Make sure key sets are present for all algorithms.
-        for signing_alg in &self.signing_algs {
+        for signing_alg in &self.key_algs {
             use SigningAlgorithm::*;
             if match signing_alg {
                 EdDsa => self.ed25519_keys.is_none(),
-                Rs256 => self.rsa_keys.is_none(),
+                Es256 => self.ecdsa_p256_keys.is_none(),
+                Es384 => self.ecdsa_p384_keys.is_none(),
+                Rs256 | Ps256 | Ps384 | Ps512 => self.rsa_keys.is_none(),
             } {
                 panic!("Store did not provide a key set for {signing_alg}");
             }
@@ -259,9 +268,14 @@ impl Handler<UpdateKeys> for RotatingKeys {
         }
 
         // Parse and activate keys. After this, we can be sure usable keys are loaded.
+        let signing_algs = &self.signing_algs;
         match key_set.signing_alg {
-            Rs256 => self.rsa_keys = Some(ActiveKeySet::parse(&key_set)),
-            EdDsa => self.ed25519_keys = Some(ActiveKeySet::parse(&key_set)),
+            Rs256 | Ps256 | Ps384 | Ps512 => {
+                self.rsa_keys = Some(ActiveKeySet::parse(&key_set, signing_algs));
+            }
+            EdDsa => self.ed25519_keys = Some(ActiveKeySet::parse(&key_set, signing_algs)),
+            Es256 => self.ecdsa_p256_keys = Some(ActiveKeySet::parse(&key_set, signing_algs)),
+            Es384 => self.ecdsa_p384_keys = Some(ActiveKeySet::parse(&key_set, signing_algs)),
         }
 
         // Sanity checks.
@@ -342,14 +356,22 @@ impl Handler<SignJws> for RotatingKeys {
     fn handle(&mut self, message: SignJws, cx: Context<Self, SignJws>) {
         use SigningAlgorithm::*;
         let maybe_jws = match message.signing_alg {
-            EdDsa => self
-                .ed25519_keys
-                .as_ref()
-                .map(|set| set.current.sign_jws(&message.payload, &self.rng)),
-            Rs256 => self
-                .rsa_keys
-                .as_ref()
-                .map(|set| set.current.sign_jws(&message.payload, &self.rng)),
+            EdDsa => self.ed25519_keys.as_ref().map(|set| {
+                set.current
+                    .sign_jws(message.signing_alg, &message.payload, &self.rng)
+            }),
+            Es256 => self.ecdsa_p256_keys.as_ref().map(|set| {
+                set.current
+                    .sign_jws(message.signing_alg, &message.payload, &self.rng)
+            }),
+            Es384 => self.ecdsa_p384_keys.as_ref().map(|set| {
+                set.current
+                    .sign_jws(message.signing_alg, &message.payload, &self.rng)
+            }),
+            Rs256 | Ps256 | Ps384 | Ps512 => self.rsa_keys.as_ref().map(|set| {
+                set.current
+                    .sign_jws(message.signing_alg, &message.payload, &self.rng)
+            }),
         };
         cx.reply(maybe_jws.unwrap_or(Err(SignError::UnsupportedAlgorithm(message.signing_alg))));
     }
@@ -365,6 +387,18 @@ impl Handler<GetPublicJwks> for RotatingKeys {
                 expires = key_set.expires;
             }
         }
+        if let Some(ref key_set) = self.ecdsa_p256_keys {
+            key_set.append_public_jwks(&mut jwks);
+            if key_set.expires < expires {
+                expires = key_set.expires;
+            }
+        }
+        if let Some(ref key_set) = self.ecdsa_p384_keys {
+            key_set.append_public_jwks(&mut jwks);
+            if key_set.expires < expires {
+                expires = key_set.expires;
+            }
+        }
         if let Some(ref key_set) = self.rsa_keys {
             key_set.append_public_jwks(&mut jwks);
             if key_set.expires < expires {
@@ -64,14 +64,30 @@ pub struct MemoryStore {
     key_manager: Option<Addr<RotatingKeys>>,
     /// Session storage.
     sessions: HashMap<String, Expiring<Session>>,
+    /// Failed attempts to verify sessions.
+    session_attempts: HashMap<String, Expiring<u32>>,
     /// Auth code storage.
     auth_codes: HashMap<String, Expiring<SessionData>>,
+    /// Pushed authorization request storage.
+    pushed_requests: HashMap<String, Expiring<HashMap<String, String>>>,
+    /// Device authorization storage.
+    device_authorizations: HashMap<String, Expiring<DeviceAuthorization>>,
+    /// Device codes indexed by user code.
+    device_user_codes: HashMap<String, Expiring<String>>,
+    /// Device authorization polling state.
+    device_polls: HashMap<String, Expiring<DevicePoll>>,
+    /// Device authorization results.
+    device_results: HashMap<String, Expiring<SessionData>>,
+    /// Passkeys indexed by email address.
+    webauthn_credentials: HashMap<String, Vec<WebauthnCredential>>,
     /// Cache storage.
     cache: HashMap<Url, CacheSlot>,
     /// Rate limit storage.
     limits: HashMap<String, Expiring<usize>>,
     /// Keys storage.
     keys: HashMap<SigningAlgorithm, KeysSlot>,
+    /// Secret for pairwise subject identifiers.
+    pairwise_secret: Option<Vec<u8>>,
 }
 
 impl MemoryStore {
@@ -93,10 +109,18 @@ impl MemoryStore {
             fetcher,
             key_manager: None,
             sessions: HashMap::new(),
+            session_attempts: HashMap::new(),
             auth_codes: HashMap::new(),
+            pushed_requests: HashMap::new(),
+            device_authorizations: HashMap::new(),
+            device_user_codes: HashMap::new(),
+            device_polls: HashMap::new(),
+            device_results: HashMap::new(),
+            webauthn_credentials: HashMap::new(),
             cache: HashMap::new(),
             limits: HashMap::new(),
             keys: HashMap::new(),
+            pairwise_secret: None,
         }
     }
 }
@@ -125,11 +149,41 @@ impl Handler<Gc> for MemoryStore {
             .drain()
             .filter(|(_, ref entry)| entry.is_alive())
             .collect();
+        self.session_attempts = self
+            .session_attempts
+            .drain()
+            .filter(|(_, ref entry)| entry.is_alive())
+            .collect();
         self.auth_codes = self
             .auth_codes
             .drain()
             .filter(|(_, ref entry)| entry.is_alive())
             .collect();
+        self.pushed_requests = self
+            .pushed_requests
+            .drain()
+            .filter(|(_, ref entry)| entry.is_alive())
+            .collect();
+        self.device_authorizations = self
+            .device_authorizations
+            .drain()
+            .filter(|(_, ref entry)| entry.is_alive())
+            .collect();
+        self.device_user_codes = self
+            .device_user_codes
+            .drain()
+            .filter(|(_, ref entry)| entry.is_alive())
+            .collect();
+        self.device_polls = self
+            .device_polls
+            .drain()
+            .filter(|(_, ref entry)| entry.is_alive())
+            .collect();
+        self.device_results = self
+            .device_results
+            .drain()
+            .filter(|(_, ref entry)| entry.is_alive())
+            .collect();
         self.cache = self
             .cache
             .drain()
@@ -180,10 +234,26 @@ impl Handler<GetSession> for MemoryStore {
 impl Handler<DeleteSession> for MemoryStore {
     fn handle(&mut self, message: DeleteSession, cx: Context<Self, DeleteSession>) {
         self.sessions.remove(&message.session_id);
+        self.session_attempts.remove(&message.session_id);
         cx.reply(Ok(()));
     }
 }
 
+impl Handler<IncrSessionAttempts> for MemoryStore {
+    fn handle(&mut self, message: IncrSessionAttempts, cx: Context<Self, IncrSessionAttempts>) {
+        let ttl = self.expire_sessions;
+        let entry = self
+            .session_attempts
+            .entry(message.session_id)
+            .or_insert_with(|| Expiring::from_duration(0, ttl));
+        if !entry.is_alive() {
+            *entry = Expiring::from_duration(0, ttl);
+        }
+        entry.value += 1;
+        cx.reply(Ok(entry.value));
+    }
+}
+
 impl Handler<SaveAuthCode> for MemoryStore {
     fn handle(&mut self, message: SaveAuthCode, cx: Context<Self, SaveAuthCode>) {
         self.auth_codes.insert(
@@ -204,6 +274,166 @@ impl Handler<ConsumeAuthCode> for MemoryStore {
     }
 }
 
+impl Handler<SavePushedRequest> for MemoryStore {
+    fn handle(&mut self, message: SavePushedRequest, cx: Context<Self, SavePushedRequest>) {
+        self.pushed_requests.insert(
+            message.request_uri,
+            Expiring::from_duration(message.params, message.ttl),
+        );
+        cx.reply(Ok(()));
+    }
+}
+
+impl Handler<ConsumePushedRequest> for MemoryStore {
+    fn handle(&mut self, message: ConsumePushedRequest, cx: Context<Self, ConsumePushedRequest>) {
+        cx.reply(Ok(self
+            .pushed_requests
+            .remove(&message.request_uri)
+            .filter(Expiring::is_alive)
+            .map(|entry| entry.value)));
+    }
+}
+
+impl Handler<SaveDeviceAuthorization> for MemoryStore {
+    fn handle(
+        &mut self,
+        message: SaveDeviceAuthorization,
+        cx: Context<Self, SaveDeviceAuthorization>,
+    ) {
+        if self
+            .device_user_codes
+            .get(&message.data.user_code)
+            .is_some_and(Expiring::is_alive)
+        {
+            return cx.reply(Ok(false));
+        }
+        self.device_user_codes.insert(
+            message.data.user_code.clone(),
+            Expiring::from_duration(message.device_code.clone(), message.ttl),
+        );
+        self.device_authorizations.insert(
+            message.device_code,
+            Expiring::from_duration(message.data, message.ttl),
+        );
+        cx.reply(Ok(true));
+    }
+}
+
+impl Handler<GetDeviceAuthorization> for MemoryStore {
+    fn handle(
+        &mut self,
+        message: GetDeviceAuthorization,
+        cx: Context<Self, GetDeviceAuthorization>,
+    ) {
+        let data = self
+            .device_authorizations
+            .get(&message.device_code)
+            .filter(|entry| entry.is_alive())
+            .map(|entry| entry.value.clone());
+        cx.reply(Ok(data));
+    }
+}
+
+impl Handler<FindDeviceCode> for MemoryStore {
+    fn handle(&mut self, message: FindDeviceCode, cx: Context<Self, FindDeviceCode>) {
+        let device_code = self
+            .device_user_codes
+            .get(&message.user_code)
+            .filter(|entry| entry.is_alive())
+            .map(|entry| entry.value.clone());
+        cx.reply(Ok(device_code));
+    }
+}
+
+impl Handler<SaveDevicePoll> for MemoryStore {
+    fn handle(&mut self, message: SaveDevicePoll, cx: Context<Self, SaveDevicePoll>) {
+        self.device_polls.insert(
+            message.device_code,
+            Expiring::from_duration(message.poll, message.ttl),
+        );
+        cx.reply(Ok(()));
+    }
+}
+
+impl Handler<GetDevicePoll> for MemoryStore {
+    fn handle(&mut self, message: GetDevicePoll, cx: Context<Self, GetDevicePoll>) {
+        let poll = self
+            .device_polls
+            .get(&message.device_code)
+            .filter(|entry| entry.is_alive())
+            .map(|entry| entry.value.clone());
+        cx.reply(Ok(poll));
+    }
+}
+
+impl Handler<CompleteDeviceAuthorization> for MemoryStore {
+    fn handle(
+        &mut self,
+        message: CompleteDeviceAuthorization,
+        cx: Context<Self, CompleteDeviceAuthorization>,
+    ) {
+        if self
+            .device_results
+            .get(&message.device_code)
+            .is_some_and(Expiring::is_alive)
+        {
+            return cx.reply(Ok(false));
+        }
+        self.device_user_codes.remove(&message.user_code);
+        self.device_results.insert(
+            message.device_code,
+            Expiring::from_duration(message.data, message.ttl),
+        );
+        cx.reply(Ok(true));
+    }
+}
+
+impl Handler<ConsumeDeviceResult> for MemoryStore {
+    fn handle(&mut self, message: ConsumeDeviceResult, cx: Context<Self, ConsumeDeviceResult>) {
+        let data = self
+            .device_results
+            .remove(&message.device_code)
+            .filter(Expiring::is_alive)
+            .map(|entry| entry.value);
+        if data.is_some() {
+            self.device_authorizations.remove(&message.device_code);
+            self.device_polls.remove(&message.device_code);
+        }
+        cx.reply(Ok(data));
+    }
+}
+
+impl Handler<SaveWebauthnCredential> for MemoryStore {
+    fn handle(
+        &mut self,
+        message: SaveWebauthnCredential,
+        cx: Context<Self, SaveWebauthnCredential>,
+    ) {
+        let credentials = self
+            .webauthn_credentials
+            .entry(message.email_addr.into_string())
+            .or_default();
+        credentials.retain(|credential| credential.id != message.credential.id);
+        credentials.push(message.credential);
+        cx.reply(Ok(()));
+    }
+}
+
+impl Handler<GetWebauthnCredentials> for MemoryStore {
+    fn handle(
+        &mut self,
+        message: GetWebauthnCredentials,
+        cx: Context<Self, GetWebauthnCredentials>,
+    ) {
+        let credentials = self
+            .webauthn_credentials
+            .get(message.email_addr.as_str())
+            .cloned()
+            .unwrap_or_default();
+        cx.reply(Ok(credentials));
+    }
+}
+
 impl Handler<FetchUrlCached> for MemoryStore {
     fn handle(&mut self, message: FetchUrlCached, cx: Context<Self, FetchUrlCached>) {
         let fetcher = self.fetcher.clone();
@@ -224,10 +454,40 @@ impl Handler<FetchUrlCached> for MemoryStore {
     }
 }
 
+impl Handler<GetCacheEntry> for MemoryStore {
+    fn handle(&mut self, message: GetCacheEntry, cx: Context<Self, GetCacheEntry>) {
+        let Some(slot) = self.cache.get(&message.key).cloned() else {
+            return cx.reply(Ok(None));
+        };
+        cx.reply_later(async move {
+            let slot = slot.lock().await;
+            Ok(slot
+                .as_ref()
+                .filter(|entry| entry.is_alive())
+                .map(|entry| entry.value.clone()))
+        });
+    }
+}
+
+impl Handler<SaveCacheEntry> for MemoryStore {
+    fn handle(&mut self, message: SaveCacheEntry, cx: Context<Self, SaveCacheEntry>) {
+        let slot = self.cache.entry(message.key).or_default().clone();
+        let ttl = std::cmp::max(self.expire_cache, message.ttl);
+        cx.reply_later(async move {
+            *slot.lock().await = Some(Expiring::from_duration(message.data, ttl));
+            Ok(())
+        });
+    }
+}
+
 impl Handler<IncrAndTestLimits> for MemoryStore {
     fn handle(&mut self, message: IncrAndTestLimits, cx: Context<Self, IncrAndTestLimits>) {
         let mut ok = true;
-        for config in &self.limit_configs {
+        for config in self
+            .limit_configs
+            .iter()
+            .filter(|config| config.kind == message.kind)
+        {
             let key = message.input.build_key(config, "", "|");
             let count = match self.limits.entry(key) {
                 Entry::Occupied(mut entry) => {
@@ -322,4 +582,11 @@ impl Handler<ExportKeySet> for MemoryStore {
     }
 }
 
+impl Handler<GetPairwiseSecret> for MemoryStore {
+    fn handle(&mut self, message: GetPairwiseSecret, cx: Context<Self, GetPairwiseSecret>) {
+        let secret = self.pairwise_secret.get_or_insert(message.candidate);
+        cx.reply(Ok(secret.clone()));
+    }
+}
+
 impl StoreSender for Addr<MemoryStore> {}
@@ -1,11 +1,14 @@
 use crate::agents::key_manager::rotating::{KeySet, RotatingKeys};
-use crate::config::LimitInput;
+use crate::config::{LimitInput, LimitKind};
 use crate::crypto::SigningAlgorithm;
+use crate::email_address::EmailAddress;
 use crate::utils::agent::{Addr, Message, Sender};
 use crate::utils::BoxError;
 use crate::web::{Session, SessionData};
 use prometheus::Histogram;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use url::Url;
 
 /// Message requesting a session be saved.
@@ -37,6 +40,19 @@ impl Message for DeleteSession {
     type Reply = Result<(), BoxError>;
 }
 
+/// Message requesting the attempts to verify a session be incremented.
+///
+/// Attempts are counted before the code is compared, so concurrent requests cannot exceed the
+/// limit. Replies with the new number of attempts. The counter expires with the session, and is removed
+/// when the session is deleted.
+pub struct IncrSessionAttempts {
+    /// The session ID.
+    pub session_id: String,
+}
+impl Message for IncrSessionAttempts {
+    type Reply = Result<u32, BoxError>;
+}
+
 /// Message requesting an authorization code by saved.
 pub struct SaveAuthCode {
     /// The authorization code.
@@ -57,6 +73,178 @@ impl Message for ConsumeAuthCode {
     type Reply = Result<Option<SessionData>, BoxError>;
 }
 
+/// Message requesting a pushed authorization request be saved. (RFC 9126)
+pub struct SavePushedRequest {
+    /// The `request_uri` handle.
+    pub request_uri: String,
+    /// The authorization request parameters.
+    pub params: HashMap<String, String>,
+    /// Time until the request expires.
+    pub ttl: Duration,
+}
+impl Message for SavePushedRequest {
+    type Reply = Result<(), BoxError>;
+}
+
+/// Message requesting a pushed authorization request be retrieved and deleted.
+pub struct ConsumePushedRequest {
+    /// The `request_uri` handle.
+    pub request_uri: String,
+}
+impl Message for ConsumePushedRequest {
+    type Reply = Result<Option<HashMap<String, String>>, BoxError>;
+}
+
+/// A device authorization request. (RFC 8628)
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DeviceAuthorization {
+    /// The client that started the request.
+    pub client_id: String,
+    /// The code the user enters on the verification page.
+    pub user_code: String,
+    /// Nonce to include in the ID token.
+    pub nonce: Option<String>,
+    /// Algorithm used to sign the ID token.
+    pub signing_alg: SigningAlgorithm,
+    /// UNIX timestamp when the request expires.
+    pub expires: u64,
+}
+
+/// Polling state of a device authorization request.
+///
+/// This is stored separately from the request, so that saving it can never overwrite the result.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DevicePoll {
+    /// Minimum time in seconds between polls of the client.
+    pub interval: u64,
+    /// UNIX timestamp of the last poll of the client.
+    pub last_poll: u64,
+}
+
+/// Message requesting a device authorization request be saved.
+///
+/// The store should also make the device code retrievable using the user code. If the user code
+/// is already in use by another request, nothing is saved and the reply is `false`.
+pub struct SaveDeviceAuthorization {
+    /// The device code.
+    pub device_code: String,
+    /// Device authorization data to save.
+    pub data: DeviceAuthorization,
+    /// Time until the request expires.
+    pub ttl: Duration,
+}
+impl Message for SaveDeviceAuthorization {
+    type Reply = Result<bool, BoxError>;
+}
+
+/// Message requesting a device authorization request be fetched.
+pub struct GetDeviceAuthorization {
+    /// The device code.
+    pub device_code: String,
+}
+impl Message for GetDeviceAuthorization {
+    type Reply = Result<Option<DeviceAuthorization>, BoxError>;
+}
+
+/// Message requesting the device code belonging to a user code.
+pub struct FindDeviceCode {
+    /// The user code.
+    pub user_code: String,
+}
+impl Message for FindDeviceCode {
+    type Reply = Result<Option<String>, BoxError>;
+}
+
+/// Message requesting the polling state of a device authorization request be saved.
+pub struct SaveDevicePoll {
+    /// The device code.
+    pub device_code: String,
+    /// Polling state to save.
+    pub poll: DevicePoll,
+    /// Time until the request expires.
+    pub ttl: Duration,
+}
+impl Message for SaveDevicePoll {
+    type Reply = Result<(), BoxError>;
+}
+
+/// Message requesting the polling state of a device authorization request be fetched.
+pub struct GetDevicePoll {
+    /// The device code.
+    pub device_code: String,
+}
+impl Message for GetDevicePoll {
+    type Reply = Result<Option<DevicePoll>, BoxError>;
+}
+
+/// Message requesting the result of a device authorization request be saved.
+///
+/// The store should also remove the user code, so it cannot be entered again. If a result was
+/// already saved, nothing is changed and the reply is `false`.
+pub struct CompleteDeviceAuthorization {
+    /// The device code.
+    pub device_code: String,
+    /// The user code.
+    pub user_code: String,
+    /// Session data of the authenticated user.
+    pub data: SessionData,
+    /// Time until the request expires.
+    pub ttl: Duration,
+}
+impl Message for CompleteDeviceAuthorization {
+    type Reply = Result<bool, BoxError>;
+}
+
+/// Message requesting the result of a device authorization request be retrieved.
+///
+/// If there is a result, the store should delete the request along with its polling state, so
+/// that the result can only be retrieved once.
+pub struct ConsumeDeviceResult {
+    /// The device code.
+    pub device_code: String,
+}
+impl Message for ConsumeDeviceResult {
+    type Reply = Result<Option<SessionData>, BoxError>;
+}
+
+/// A passkey registered for an email address.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebauthnCredential {
+    /// The credential ID, base64url encoded.
+    pub id: String,
+    /// The COSE algorithm identifier of the key.
+    pub alg: i64,
+    /// The public key, base64url encoded, in the format `ring` expects for the algorithm.
+    pub public_key: String,
+    /// The signature counter last reported by the authenticator.
+    pub sign_count: u32,
+    /// UNIX timestamp when the credential was registered.
+    pub created: u64,
+}
+
+/// Message requesting a passkey be saved.
+///
+/// A credential with the same ID for the same email address is replaced. Credentials never
+/// expire.
+pub struct SaveWebauthnCredential {
+    /// The normalized email address the credential is bound to.
+    pub email_addr: EmailAddress,
+    /// The credential to save.
+    pub credential: WebauthnCredential,
+}
+impl Message for SaveWebauthnCredential {
+    type Reply = Result<(), BoxError>;
+}
+
+/// Message requesting the passkeys registered for an email address.
+pub struct GetWebauthnCredentials {
+    /// The normalized email address.
+    pub email_addr: EmailAddress,
+}
+impl Message for GetWebauthnCredentials {
+    type Reply = Result<Vec<WebauthnCredential>, BoxError>;
+}
+
 /// Message requesting a URL be fetched, possibly from cache.
 pub struct FetchUrlCached {
     /// The URL to fetch.
@@ -68,13 +256,38 @@ impl Message for FetchUrlCached {
     type Reply = Result<String, BoxError>;
 }
 
+/// Message requesting a cache entry, for lookups that are not plain HTTP fetches.
+///
+/// Keys share the cache with `FetchUrlCached`, so should use a distinct URL scheme.
+pub struct GetCacheEntry {
+    pub key: Url,
+}
+impl Message for GetCacheEntry {
+    type Reply = Result<Option<String>, BoxError>;
+}
+
+/// Message requesting a cache entry be saved.
+///
+/// The entry is kept for at least the configured cache TTL, or longer if `ttl` is longer.
+pub struct SaveCacheEntry {
+    pub key: Url,
+    pub data: String,
+    pub ttl: Duration,
+}
+impl Message for SaveCacheEntry {
+    type Reply = Result<(), BoxError>;
+}
+
 /// Message requesting rate limits be increased and tested.
 ///
 /// The configured rate limits are passed to the store when it is created. The store should always
 /// increment all rate limits, even if only the first one fails, for example. The result is `true`
 /// if none of the rate limits were hit.
+///
+/// Only the rate limits for the type of request are applied.
 pub struct IncrAndTestLimits {
     pub input: LimitInput,
+    pub kind: LimitKind,
 }
 impl Message for IncrAndTestLimits {
     type Reply = Result<bool, BoxError>;
@@ -135,6 +348,17 @@ impl Message for ExportKeySet {
     type Reply = KeySet;
 }
 
+/// Message requesting the secret used to derive pairwise subject identifiers.
+///
+/// The secret must persist across restarts and be shared by all workers. If no secret is stored
+/// yet, the store should atomically save `candidate` and return it.
+pub struct GetPairwiseSecret {
+    pub candidate: Vec<u8>,
+}
+impl Message for GetPairwiseSecret {
+    type Reply = Result<Vec<u8>, BoxError>;
+}
+
 /// Store abstraction. Combines all message types.
 ///
 /// Downside of this is that it needs to be implemented on the agent side as:
@@ -143,15 +367,30 @@ pub trait StoreSender:
     Sender<SaveSession>
     + Sender<GetSession>
     + Sender<DeleteSession>
+    + Sender<IncrSessionAttempts>
     + Sender<SaveAuthCode>
     + Sender<ConsumeAuthCode>
+    + Sender<SavePushedRequest>
+    + Sender<ConsumePushedRequest>
+    + Sender<SaveDeviceAuthorization>
+    + Sender<GetDeviceAuthorization>
+    + Sender<FindDeviceCode>
+    + Sender<SaveDevicePoll>
+    + Sender<GetDevicePoll>
+    + Sender<CompleteDeviceAuthorization>
+    + Sender<ConsumeDeviceResult>
+    + Sender<SaveWebauthnCredential>
+    + Sender<GetWebauthnCredentials>
     + Sender<FetchUrlCached>
+    + Sender<GetCacheEntry>
+    + Sender<SaveCacheEntry>
     + Sender<IncrAndTestLimits>
     + Sender<DecrLimits>
     + Sender<EnableRotatingKeys>
     + Sender<RotateKeysLocked>
     + Sender<ImportKeySet>
     + Sender<ExportKeySet>
+    + Sender<GetPairwiseSecret>
 {
 }
 
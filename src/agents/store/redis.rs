@@ -1,6 +1,7 @@
 use crate::agents::*;
 use crate::config::LimitConfig;
 use crate::crypto::SigningAlgorithm;
+use crate::email_address::EmailAddress;
 use crate::utils::{
     agent::*,
     redis::{locking, pubsub},
@@ -132,9 +133,37 @@ impl RedisStore {
         format!("session:{session_id}")
     }
 
+    fn format_session_attempts_key(session_id: &str) -> String {
+        format!("session_attempts:{session_id}")
+    }
+
     fn format_auth_code_key(code: &str) -> String {
         format!("auth_code:{code}")
     }
+
+    fn format_pushed_request_key(request_uri: &str) -> String {
+        format!("pushed_request:{request_uri}")
+    }
+
+    fn format_device_key(device_code: &str) -> String {
+        format!("device:{device_code}")
+    }
+
+    fn format_device_user_code_key(user_code: &str) -> String {
+        format!("device_user_code:{user_code}")
+    }
+
+    fn format_device_poll_key(device_code: &str) -> String {
+        format!("device_poll:{device_code}")
+    }
+
+    fn format_device_result_key(device_code: &str) -> String {
+        format!("device_result:{device_code}")
+    }
+
+    fn format_webauthn_key(email_addr: &EmailAddress) -> String {
+        format!("webauthn:{email_addr}")
+    }
 }
 
 impl Agent for RedisStore {
@@ -190,12 +219,31 @@ impl Handler<DeleteSession> for RedisStore {
         let mut conn = self.conn.clone();
         cx.reply_later(async move {
             let key = Self::format_session_key(&message.session_id);
-            conn.del(&key).await?;
+            let attempts_key = Self::format_session_attempts_key(&message.session_id);
+            conn.del(&[key, attempts_key]).await?;
             Ok(())
         });
     }
 }
 
+impl Handler<IncrSessionAttempts> for RedisStore {
+    fn handle(&mut self, message: IncrSessionAttempts, cx: Context<Self, IncrSessionAttempts>) {
+        let mut conn = self.conn.clone();
+        let ttl = self.expire_sessions;
+        cx.reply_later(async move {
+            let key = Self::format_session_attempts_key(&message.session_id);
+            let (attempts,): (u32,) = pipe()
+                .atomic()
+                .incr(&key, 1)
+                .expire(&key, ttl.as_secs() as usize)
+                .ignore()
+                .query_async(&mut conn)
+                .await?;
+            Ok(attempts)
+        });
+    }
+}
+
 impl Handler<SaveAuthCode> for RedisStore {
     fn handle(&mut self, message: SaveAuthCode, cx: Context<Self, SaveAuthCode>) {
         let mut conn = self.conn.clone();
@@ -230,6 +278,215 @@ impl Handler<ConsumeAuthCode> for RedisStore {
     }
 }
 
+impl Handler<SavePushedRequest> for RedisStore {
+    fn handle(&mut self, message: SavePushedRequest, cx: Context<Self, SavePushedRequest>) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let key = Self::format_pushed_request_key(&message.request_uri);
+            let data = serde_json::to_string(&message.params)?;
+            let () = conn
+                .set_ex(&key, data, message.ttl.as_secs() as usize)
+                .await?;
+            Ok(())
+        });
+    }
+}
+
+impl Handler<ConsumePushedRequest> for RedisStore {
+    fn handle(&mut self, message: ConsumePushedRequest, cx: Context<Self, ConsumePushedRequest>) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let key = Self::format_pushed_request_key(&message.request_uri);
+            let data: (Option<String>,) = pipe()
+                .atomic()
+                .get(&key)
+                .del(&key)
+                .ignore()
+                .query_async(&mut conn)
+                .await?;
+            if let (Some(data),) = data {
+                Ok(Some(serde_json::from_str(&data)?))
+            } else {
+                Ok(None)
+            }
+        });
+    }
+}
+
+impl Handler<SaveDeviceAuthorization> for RedisStore {
+    fn handle(
+        &mut self,
+        message: SaveDeviceAuthorization,
+        cx: Context<Self, SaveDeviceAuthorization>,
+    ) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let key = Self::format_device_key(&message.device_code);
+            let user_code_key = Self::format_device_user_code_key(&message.data.user_code);
+            let data = serde_json::to_string(&message.data)?;
+            let ttl = message.ttl.as_secs() as usize;
+            let claimed: Option<String> = ::redis::cmd("SET")
+                .arg(&user_code_key)
+                .arg(&message.device_code)
+                .arg("NX")
+                .arg("EX")
+                .arg(ttl)
+                .query_async(&mut conn)
+                .await?;
+            if claimed.is_none() {
+                return Ok(false);
+            }
+            let () = conn.set_ex(&key, data, ttl).await?;
+            Ok(true)
+        });
+    }
+}
+
+impl Handler<GetDeviceAuthorization> for RedisStore {
+    fn handle(
+        &mut self,
+        message: GetDeviceAuthorization,
+        cx: Context<Self, GetDeviceAuthorization>,
+    ) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let key = Self::format_device_key(&message.device_code);
+            let data: Option<String> = conn.get(&key).await?;
+            if let Some(data) = data {
+                Ok(Some(serde_json::from_str(&data)?))
+            } else {
+                Ok(None)
+            }
+        });
+    }
+}
+
+impl Handler<FindDeviceCode> for RedisStore {
+    fn handle(&mut self, message: FindDeviceCode, cx: Context<Self, FindDeviceCode>) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let key = Self::format_device_user_code_key(&message.user_code);
+            Ok(conn.get(&key).await?)
+        });
+    }
+}
+
+impl Handler<SaveDevicePoll> for RedisStore {
+    fn handle(&mut self, message: SaveDevicePoll, cx: Context<Self, SaveDevicePoll>) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let key = Self::format_device_poll_key(&message.device_code);
+            let data = serde_json::to_string(&message.poll)?;
+            let () = conn
+                .set_ex(&key, data, message.ttl.as_secs() as usize)
+                .await?;
+            Ok(())
+        });
+    }
+}
+
+impl Handler<GetDevicePoll> for RedisStore {
+    fn handle(&mut self, message: GetDevicePoll, cx: Context<Self, GetDevicePoll>) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let key = Self::format_device_poll_key(&message.device_code);
+            let data: Option<String> = conn.get(&key).await?;
+            if let Some(data) = data {
+                Ok(Some(serde_json::from_str(&data)?))
+            } else {
+                Ok(None)
+            }
+        });
+    }
+}
+
+impl Handler<CompleteDeviceAuthorization> for RedisStore {
+    fn handle(
+        &mut self,
+        message: CompleteDeviceAuthorization,
+        cx: Context<Self, CompleteDeviceAuthorization>,
+    ) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let key = Self::format_device_result_key(&message.device_code);
+            let user_code_key = Self::format_device_user_code_key(&message.user_code);
+            let data = serde_json::to_string(&message.data)?;
+            let saved: Option<String> = ::redis::cmd("SET")
+                .arg(&key)
+                .arg(data)
+                .arg("NX")
+                .arg("EX")
+                .arg(message.ttl.as_secs() as usize)
+                .query_async(&mut conn)
+                .await?;
+            if saved.is_none() {
+                return Ok(false);
+            }
+            let () = conn.del(&user_code_key).await?;
+            Ok(true)
+        });
+    }
+}
+
+impl Handler<ConsumeDeviceResult> for RedisStore {
+    fn handle(&mut self, message: ConsumeDeviceResult, cx: Context<Self, ConsumeDeviceResult>) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let key = Self::format_device_result_key(&message.device_code);
+            let data: (Option<String>,) = pipe()
+                .atomic()
+                .get(&key)
+                .del(&key)
+                .ignore()
+                .query_async(&mut conn)
+                .await?;
+            if let (Some(data),) = data {
+                let keys = [
+                    Self::format_device_key(&message.device_code),
+                    Self::format_device_poll_key(&message.device_code),
+                ];
+                let () = conn.del(&keys).await?;
+                Ok(Some(serde_json::from_str(&data)?))
+            } else {
+                Ok(None)
+            }
+        });
+    }
+}
+
+impl Handler<SaveWebauthnCredential> for RedisStore {
+    fn handle(
+        &mut self,
+        message: SaveWebauthnCredential,
+        cx: Context<Self, SaveWebauthnCredential>,
+    ) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let key = Self::format_webauthn_key(&message.email_addr);
+            let data = serde_json::to_string(&message.credential)?;
+            let () = conn.hset(&key, &message.credential.id, data).await?;
+            Ok(())
+        });
+    }
+}
+
+impl Handler<GetWebauthnCredentials> for RedisStore {
+    fn handle(
+        &mut self,
+        message: GetWebauthnCredentials,
+        cx: Context<Self, GetWebauthnCredentials>,
+    ) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let key = Self::format_webauthn_key(&message.email_addr);
+            let data: Vec<String> = conn.hvals(&key).await?;
+            data.iter()
+                .map(|data| Ok(serde_json::from_str(data)?))
+                .collect()
+        });
+    }
+}
+
 impl Handler<FetchUrlCached> for RedisStore {
     fn handle(&mut self, message: FetchUrlCached, cx: Context<Self, FetchUrlCached>) {
         let mut conn = self.conn.clone();
@@ -255,6 +512,30 @@ impl Handler<FetchUrlCached> for RedisStore {
     }
 }
 
+impl Handler<GetCacheEntry> for RedisStore {
+    fn handle(&mut self, message: GetCacheEntry, cx: Context<Self, GetCacheEntry>) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let key = format!("cache:{}", message.key);
+            Ok(conn.get(key).await?)
+        });
+    }
+}
+
+impl Handler<SaveCacheEntry> for RedisStore {
+    fn handle(&mut self, message: SaveCacheEntry, cx: Context<Self, SaveCacheEntry>) {
+        let mut conn = self.conn.clone();
+        let ttl = std::cmp::max(self.expire_cache, message.ttl);
+        cx.reply_later(async move {
+            let key = format!("cache:{}", message.key);
+            let () = conn
+                .set_ex(key, message.data, ttl.as_secs() as usize)
+                .await?;
+            Ok(())
+        });
+    }
+}
+
 impl Handler<IncrAndTestLimits> for RedisStore {
     fn handle(&mut self, message: IncrAndTestLimits, cx: Context<Self, IncrAndTestLimits>) {
         let conn = self.conn.clone();
@@ -262,6 +543,7 @@ impl Handler<IncrAndTestLimits> for RedisStore {
         let ops: Vec<_> = self
             .limit_configs
             .iter()
+            .filter(|config| config.kind == message.kind)
             .map(|config| {
                 let key = message.input.build_key(config, "rate-limit:", "|");
                 (config.clone(), key)
@@ -447,4 +729,20 @@ impl Handler<UpdateKeysLocked> for RedisStore {
     }
 }
 
+impl Handler<GetPairwiseSecret> for RedisStore {
+    fn handle(&mut self, message: GetPairwiseSecret, cx: Context<Self, GetPairwiseSecret>) {
+        let mut conn = self.conn.clone();
+        cx.reply_later(async move {
+            let (secret,): (Vec<u8>,) = pipe()
+                .atomic()
+                .set_nx("pairwise_secret", message.candidate)
+                .ignore()
+                .get("pairwise_secret")
+                .query_async(&mut conn)
+                .await?;
+            Ok(secret)
+        });
+    }
+}
+
 impl StoreSender for Addr<RedisStore> {}
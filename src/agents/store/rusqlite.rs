@@ -120,7 +120,13 @@ impl RusqliteStore {
             match user_version {
                 0 => Self::init_schema_1(conn)?,
                 1 => Self::init_schema_2(conn)?,
-                2 => return Ok(()),
+                2 => Self::init_schema_3(conn)?,
+                3 => Self::init_schema_4(conn)?,
+                4 => Self::init_schema_5(conn)?,
+                5 => Self::init_schema_6(conn)?,
+                6 => Self::init_schema_7(conn)?,
+                7 => Self::init_schema_8(conn)?,
+                8 => return Ok(()),
                 _ => panic!("The SQLite database has an unknown version: {user_version}"),
             }
         }
@@ -183,6 +189,114 @@ impl RusqliteStore {
         Ok(())
     }
 
+    fn init_schema_3(conn: &Connection) -> Result<(), SqlError> {
+        conn.execute_batch(
+            "
+            BEGIN;
+
+            CREATE TABLE pushed_requests (
+                request_uri TEXT NOT NULL PRIMARY KEY,
+                data TEXT NOT NULL,
+                expires INTEGER NOT NULL
+            );
+            CREATE INDEX pushed_requests_expires ON pushed_requests (expires);
+
+            PRAGMA user_version = 3;
+            COMMIT;
+            ",
+        )?;
+        Ok(())
+    }
+
+    fn init_schema_4(conn: &Connection) -> Result<(), SqlError> {
+        conn.execute_batch(
+            "
+            BEGIN;
+
+            CREATE TABLE device_authorizations (
+                device_code TEXT NOT NULL PRIMARY KEY,
+                user_code TEXT NOT NULL UNIQUE,
+                data TEXT NOT NULL,
+                expires INTEGER NOT NULL
+            );
+            CREATE INDEX device_authorizations_expires ON device_authorizations (expires);
+
+            PRAGMA user_version = 4;
+            COMMIT;
+            ",
+        )?;
+        Ok(())
+    }
+
+    fn init_schema_5(conn: &Connection) -> Result<(), SqlError> {
+        conn.execute_batch(
+            "
+            BEGIN;
+
+            CREATE TABLE secrets (
+                name TEXT NOT NULL PRIMARY KEY,
+                value BLOB NOT NULL
+            );
+
+            PRAGMA user_version = 5;
+            COMMIT;
+            ",
+        )?;
+        Ok(())
+    }
+
+    fn init_schema_6(conn: &Connection) -> Result<(), SqlError> {
+        conn.execute_batch(
+            "
+            BEGIN;
+
+            CREATE TABLE webauthn_credentials (
+                email TEXT NOT NULL,
+                id TEXT NOT NULL,
+                data TEXT NOT NULL,
+                PRIMARY KEY (email, id)
+            );
+
+            PRAGMA user_version = 6;
+            COMMIT;
+            ",
+        )?;
+        Ok(())
+    }
+
+    fn init_schema_7(conn: &Connection) -> Result<(), SqlError> {
+        conn.execute_batch(
+            "
+            BEGIN;
+
+            CREATE TABLE session_attempts (
+                id TEXT NOT NULL PRIMARY KEY,
+                attempts INTEGER NOT NULL,
+                expires INTEGER NOT NULL
+            );
+
+            PRAGMA user_version = 7;
+            COMMIT;
+            ",
+        )?;
+        Ok(())
+    }
+
+    fn init_schema_8(conn: &Connection) -> Result<(), SqlError> {
+        conn.execute_batch(
+            "
+            BEGIN;
+
+            ALTER TABLE device_authorizations ADD COLUMN poll TEXT;
+            ALTER TABLE device_authorizations ADD COLUMN result TEXT;
+
+            PRAGMA user_version = 8;
+            COMMIT;
+            ",
+        )?;
+        Ok(())
+    }
+
     fn get_key_set(&mut self, signing_alg: SigningAlgorithm) -> KeySet {
         self.conn
             .query_row(
@@ -220,9 +334,21 @@ impl Handler<Gc> for RusqliteStore {
         self.conn
             .execute("DELETE FROM sessions WHERE expires <= ?1", [now])
             .expect("session cleanup failed");
+        self.conn
+            .execute("DELETE FROM session_attempts WHERE expires <= ?1", [now])
+            .expect("session attempts cleanup failed");
         self.conn
             .execute("DELETE FROM auth_codes WHERE expires <= ?1", [now])
             .expect("auth codes cleanup failed");
+        self.conn
+            .execute("DELETE FROM pushed_requests WHERE expires <= ?1", [now])
+            .expect("pushed requests cleanup failed");
+        self.conn
+            .execute(
+                "DELETE FROM device_authorizations WHERE expires <= ?1",
+                [now],
+            )
+            .expect("device authorizations cleanup failed");
         self.conn
             .execute("DELETE FROM cache_entries WHERE expires <= ?1", [now])
             .expect("cache cleanup failed");
@@ -274,11 +400,41 @@ impl Handler<DeleteSession> for RusqliteStore {
         cx.reply_with(move || {
             self.conn
                 .execute("DELETE FROM sessions WHERE id = ?1", [&message.session_id])?;
+            self.conn.execute(
+                "DELETE FROM session_attempts WHERE id = ?1",
+                [&message.session_id],
+            )?;
             Ok(())
         });
     }
 }
 
+impl Handler<IncrSessionAttempts> for RusqliteStore {
+    fn handle(&mut self, message: IncrSessionAttempts, cx: Context<Self, IncrSessionAttempts>) {
+        cx.reply_with(move || {
+            let now = unix_timestamp() as i64;
+            let expires = now + self.expire_sessions.as_secs() as i64;
+            let tx = self.conn.transaction()?;
+            tx.execute(
+                "DELETE FROM session_attempts WHERE id = ?1 AND expires <= ?2",
+                params![&message.session_id, &now],
+            )?;
+            tx.execute(
+                "INSERT INTO session_attempts (id, attempts, expires) VALUES (?1, 1, ?2)
+                ON CONFLICT(id) DO UPDATE SET attempts = attempts + 1",
+                params![&message.session_id, &expires],
+            )?;
+            let attempts: u32 = tx.query_row(
+                "SELECT attempts FROM session_attempts WHERE id = ?1 LIMIT 1",
+                params![&message.session_id],
+                |row| row.get(0),
+            )?;
+            tx.commit()?;
+            Ok(attempts)
+        });
+    }
+}
+
 impl Handler<SaveAuthCode> for RusqliteStore {
     fn handle(&mut self, message: SaveAuthCode, cx: Context<Self, SaveAuthCode>) {
         cx.reply_with(move || {
@@ -320,6 +476,244 @@ impl Handler<ConsumeAuthCode> for RusqliteStore {
     }
 }
 
+impl Handler<SavePushedRequest> for RusqliteStore {
+    fn handle(&mut self, message: SavePushedRequest, cx: Context<Self, SavePushedRequest>) {
+        cx.reply_with(move || {
+            let expires = (unix_timestamp() + message.ttl.as_secs()) as i64;
+            let data = serde_json::to_string(&message.params)?;
+            self.conn.execute(
+                "REPLACE INTO pushed_requests (request_uri, data, expires) VALUES (?1, ?2, ?3)",
+                params![&message.request_uri, &data, &expires],
+            )?;
+            Ok(())
+        });
+    }
+}
+
+impl Handler<ConsumePushedRequest> for RusqliteStore {
+    fn handle(&mut self, message: ConsumePushedRequest, cx: Context<Self, ConsumePushedRequest>) {
+        cx.reply_with(move || {
+            let now = unix_timestamp() as i64;
+            let tx = self.conn.transaction()?;
+            let data: Option<String> = tx
+                .query_row(
+                    "SELECT data FROM pushed_requests WHERE request_uri = ?1 AND expires > ?2 LIMIT 1",
+                    params![&message.request_uri, &now],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(data) = data {
+                tx.execute(
+                    "DELETE FROM pushed_requests WHERE request_uri = ?1",
+                    params![&message.request_uri],
+                )?;
+                tx.commit()?;
+                let data = serde_json::from_str(&data)?;
+                Ok(Some(data))
+            } else {
+                Ok(None)
+            }
+        });
+    }
+}
+
+impl Handler<SaveDeviceAuthorization> for RusqliteStore {
+    fn handle(
+        &mut self,
+        message: SaveDeviceAuthorization,
+        cx: Context<Self, SaveDeviceAuthorization>,
+    ) {
+        cx.reply_with(move || {
+            let now = unix_timestamp() as i64;
+            let expires = now + message.ttl.as_secs() as i64;
+            let data = serde_json::to_string(&message.data)?;
+            let tx = self.conn.transaction()?;
+            tx.execute(
+                "DELETE FROM device_authorizations WHERE user_code = ?1 AND expires <= ?2",
+                params![&message.data.user_code, &now],
+            )?;
+            let inserted = tx.execute(
+                "INSERT INTO device_authorizations (device_code, user_code, data, expires) \
+                 VALUES (?1, ?2, ?3, ?4) ON CONFLICT DO NOTHING",
+                params![
+                    &message.device_code,
+                    &message.data.user_code,
+                    &data,
+                    &expires
+                ],
+            )?;
+            tx.commit()?;
+            Ok(inserted == 1)
+        });
+    }
+}
+
+impl Handler<GetDeviceAuthorization> for RusqliteStore {
+    fn handle(
+        &mut self,
+        message: GetDeviceAuthorization,
+        cx: Context<Self, GetDeviceAuthorization>,
+    ) {
+        cx.reply_with(move || {
+            let now = unix_timestamp() as i64;
+            let data: Option<String> = self
+                .conn
+                .query_row(
+                    "SELECT data FROM device_authorizations \
+                     WHERE device_code = ?1 AND expires > ?2 LIMIT 1",
+                    params![&message.device_code, &now],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(data) = data {
+                let data = serde_json::from_str(&data)?;
+                Ok(Some(data))
+            } else {
+                Ok(None)
+            }
+        });
+    }
+}
+
+impl Handler<FindDeviceCode> for RusqliteStore {
+    fn handle(&mut self, message: FindDeviceCode, cx: Context<Self, FindDeviceCode>) {
+        cx.reply_with(move || {
+            let now = unix_timestamp() as i64;
+            Ok(self
+                .conn
+                .query_row(
+                    "SELECT device_code FROM device_authorizations \
+                     WHERE user_code = ?1 AND expires > ?2 AND result IS NULL LIMIT 1",
+                    params![&message.user_code, &now],
+                    |row| row.get(0),
+                )
+                .optional()?)
+        });
+    }
+}
+
+impl Handler<SaveDevicePoll> for RusqliteStore {
+    fn handle(&mut self, message: SaveDevicePoll, cx: Context<Self, SaveDevicePoll>) {
+        cx.reply_with(move || {
+            let poll = serde_json::to_string(&message.poll)?;
+            self.conn.execute(
+                "UPDATE device_authorizations SET poll = ?2 WHERE device_code = ?1",
+                params![&message.device_code, &poll],
+            )?;
+            Ok(())
+        });
+    }
+}
+
+impl Handler<GetDevicePoll> for RusqliteStore {
+    fn handle(&mut self, message: GetDevicePoll, cx: Context<Self, GetDevicePoll>) {
+        cx.reply_with(move || {
+            let now = unix_timestamp() as i64;
+            let poll: Option<Option<String>> = self
+                .conn
+                .query_row(
+                    "SELECT poll FROM device_authorizations \
+                     WHERE device_code = ?1 AND expires > ?2 LIMIT 1",
+                    params![&message.device_code, &now],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(Some(poll)) = poll {
+                let poll = serde_json::from_str(&poll)?;
+                Ok(Some(poll))
+            } else {
+                Ok(None)
+            }
+        });
+    }
+}
+
+impl Handler<CompleteDeviceAuthorization> for RusqliteStore {
+    fn handle(
+        &mut self,
+        message: CompleteDeviceAuthorization,
+        cx: Context<Self, CompleteDeviceAuthorization>,
+    ) {
+        cx.reply_with(move || {
+            let now = unix_timestamp() as i64;
+            let data = serde_json::to_string(&message.data)?;
+            // The user code can no longer be found once there is a result.
+            let updated = self.conn.execute(
+                "UPDATE device_authorizations SET result = ?2 \
+                 WHERE device_code = ?1 AND expires > ?3 AND result IS NULL",
+                params![&message.device_code, &data, &now],
+            )?;
+            Ok(updated == 1)
+        });
+    }
+}
+
+impl Handler<ConsumeDeviceResult> for RusqliteStore {
+    fn handle(&mut self, message: ConsumeDeviceResult, cx: Context<Self, ConsumeDeviceResult>) {
+        cx.reply_with(move || {
+            let now = unix_timestamp() as i64;
+            let tx = self.conn.transaction()?;
+            let data: Option<String> = tx
+                .query_row(
+                    "SELECT result FROM device_authorizations \
+                     WHERE device_code = ?1 AND expires > ?2 AND result IS NOT NULL LIMIT 1",
+                    params![&message.device_code, &now],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(data) = data {
+                tx.execute(
+                    "DELETE FROM device_authorizations WHERE device_code = ?1",
+                    params![&message.device_code],
+                )?;
+                tx.commit()?;
+                let data = serde_json::from_str(&data)?;
+                Ok(Some(data))
+            } else {
+                Ok(None)
+            }
+        });
+    }
+}
+
+impl Handler<SaveWebauthnCredential> for RusqliteStore {
+    fn handle(
+        &mut self,
+        message: SaveWebauthnCredential,
+        cx: Context<Self, SaveWebauthnCredential>,
+    ) {
+        cx.reply_with(move || {
+            let data = serde_json::to_string(&message.credential)?;
+            self.conn.execute(
+                "REPLACE INTO webauthn_credentials (email, id, data) VALUES (?1, ?2, ?3)",
+                params![&message.email_addr.as_str(), &message.credential.id, &data],
+            )?;
+            Ok(())
+        });
+    }
+}
+
+impl Handler<GetWebauthnCredentials> for RusqliteStore {
+    fn handle(
+        &mut self,
+        message: GetWebauthnCredentials,
+        cx: Context<Self, GetWebauthnCredentials>,
+    ) {
+        cx.reply_with(move || {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT data FROM webauthn_credentials WHERE email = ?1")?;
+            let rows =
+                stmt.query_map([message.email_addr.as_str()], |row| row.get::<_, String>(0))?;
+            let mut credentials = vec![];
+            for data in rows {
+                credentials.push(serde_json::from_str(&data?)?);
+            }
+            Ok(credentials)
+        });
+    }
+}
+
 impl Handler<FetchUrlCached> for RusqliteStore {
     fn handle(&mut self, message: FetchUrlCached, cx: Context<Self, FetchUrlCached>) {
         // TODO: Add locking to coordinate multiple fetches for the same resource.
@@ -368,11 +762,48 @@ impl Handler<SaveCache> for RusqliteStore {
     }
 }
 
+impl Handler<GetCacheEntry> for RusqliteStore {
+    fn handle(&mut self, message: GetCacheEntry, cx: Context<Self, GetCacheEntry>) {
+        cx.reply_with(move || {
+            let now = unix_timestamp() as i64;
+            Ok(self
+                .conn
+                .query_row(
+                    "SELECT data FROM cache_entries WHERE url = ?1 AND expires > ?2 LIMIT 1",
+                    params![&message.key.as_str(), &now],
+                    |row| row.get(0),
+                )
+                .optional()?)
+        });
+    }
+}
+
+impl Handler<SaveCacheEntry> for RusqliteStore {
+    fn handle(&mut self, message: SaveCacheEntry, cx: Context<Self, SaveCacheEntry>) {
+        let ttl = std::cmp::max(self.expire_cache, message.ttl);
+        cx.reply_with(move || {
+            self.conn.execute(
+                "REPLACE INTO cache_entries (url, data, expires) VALUES (?1, ?2, ?3)",
+                params![
+                    &message.key.as_str(),
+                    &message.data,
+                    &((unix_timestamp() + ttl.as_secs()) as i64)
+                ],
+            )?;
+            Ok(())
+        });
+    }
+}
+
 impl Handler<IncrAndTestLimits> for RusqliteStore {
     fn handle(&mut self, message: IncrAndTestLimits, cx: Context<Self, IncrAndTestLimits>) {
         cx.reply_with(move || {
             let mut ok = true;
-            for config in &self.limit_configs {
+            for config in self
+                .limit_configs
+                .iter()
+                .filter(|config| config.kind == message.kind)
+            {
                 let id = message.input.build_key(config, "", "|");
                 let now = unix_timestamp() as i64;
                 let window = config.window.as_secs() as i64;
@@ -495,4 +926,23 @@ impl Handler<SaveKeys> for RusqliteStore {
     }
 }
 
+impl Handler<GetPairwiseSecret> for RusqliteStore {
+    fn handle(&mut self, message: GetPairwiseSecret, cx: Context<Self, GetPairwiseSecret>) {
+        cx.reply_with(move || {
+            let tx = self.conn.transaction()?;
+            tx.execute(
+                "INSERT OR IGNORE INTO secrets (name, value) VALUES ('pairwise', ?1)",
+                params![&message.candidate],
+            )?;
+            let secret = tx.query_row(
+                "SELECT value FROM secrets WHERE name = 'pairwise' LIMIT 1",
+                [],
+                |row| row.get(0),
+            )?;
+            tx.commit()?;
+            Ok(secret)
+        });
+    }
+}
+
 impl StoreSender for Addr<RusqliteStore> {}
@@ -1,20 +1,175 @@
 use crate::agents::mailer::SendMail;
-use crate::bridges::{complete_auth, BridgeData};
-use crate::crypto::random_zbase32;
+use crate::agents::{DeleteSession, GetSession, IncrAndTestLimits, IncrSessionAttempts};
+use crate::bridges::{complete_auth, webauthn, BridgeData};
+use crate::config::{LimitInput, LimitKind};
+use crate::crypto::{self, random_code, ZBASE32_CHARSET};
 use crate::email_address::EmailAddress;
 use crate::error::BrokerError;
 use crate::metrics;
-use crate::web::{html_response, json_response, Context, HandlerResult};
+use crate::utils::http::ResponseExt;
+use crate::web::{
+    html_response, json_response, Context, HandlerResult, Response, Session, CSP_SAME_ORIGIN,
+};
+use headers::{Cookie, HeaderMapExt};
 use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use ring::constant_time;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::str::FromStr;
 
 const QUERY_ESCAPE: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'<').add(b'>');
 
+/// The highest allowed probability of guessing the code of a login, using all attempts: 2^-20.
+pub const MAX_GUESS_PROBABILITY: f64 = 1.0 / 1_048_576.0;
+
+/// Name of the cookie that identifies the browser that started a login.
+const ORIGINAL_COOKIE: &str = "portier_original";
+
 /// Data we store in the session.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct EmailBridgeData {
+    /// Secret in the link of the email, separate from the code so one cannot be used as the other.
+    #[serde(default)]
+    pub link_token: String,
     pub code: String,
+    /// Set once the code was confirmed, when the original tab completes the login.
+    #[serde(default)]
+    pub confirmed: bool,
+    /// Value of the cookie of the browser that started the login, when the original tab completes
+    /// the login.
+    #[serde(default)]
+    pub original: Option<String>,
+}
+
+/// Which device completes the login, when the email link is opened on another device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfirmDevice {
+    /// The device that opened the link receives the result.
+    Confirming,
+    /// The tab that started the login receives the result, once the link is opened.
+    Original,
+}
+
+impl FromStr for ConfirmDevice {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<ConfirmDevice, &'static str> {
+        match s {
+            "confirming" => Ok(ConfirmDevice::Confirming),
+            "original" => Ok(ConfirmDevice::Original),
+            _ => Err("unsupported value"),
+        }
+    }
+}
+
+serde_from_str!(ConfirmDevice);
+
+/// Which ways of confirming are offered in the email.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmailMode {
+    /// Both the link and the code.
+    Both,
+    /// Only the link, for users that cannot easily copy a code.
+    Link,
+    /// Only the code, for mail systems that follow links to scan them.
+    Code,
+}
+
+impl EmailMode {
+    /// Whether the email contains a link to confirm.
+    pub fn has_link(self) -> bool {
+        self != EmailMode::Code
+    }
+
+    /// Whether the email contains a code to enter on the login page.
+    pub fn has_code(self) -> bool {
+        self != EmailMode::Link
+    }
+}
+
+impl FromStr for EmailMode {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<EmailMode, &'static str> {
+        match s {
+            "both" => Ok(EmailMode::Both),
+            "link" => Ok(EmailMode::Link),
+            "code" => Ok(EmailMode::Code),
+            _ => Err("unsupported value"),
+        }
+    }
+}
+
+serde_from_str!(EmailMode);
+
+/// The characters one-time codes are made of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CodeAlphabet {
+    /// Digits only, which are easy to type on mobile devices.
+    Numeric,
+    /// The z-base-32 set of lowercase letters and digits.
+    ZBase32,
+}
+
+impl CodeAlphabet {
+    /// The characters in the alphabet.
+    fn charset(self) -> &'static [u8] {
+        match self {
+            CodeAlphabet::Numeric => b"0123456789",
+            CodeAlphabet::ZBase32 => ZBASE32_CHARSET,
+        }
+    }
+
+    /// The probability of guessing a code of `length` characters within `attempts` tries.
+    ///
+    /// This must not exceed `MAX_GUESS_PROBABILITY`. Rate limits on starting logins further limit
+    /// guessing across sessions.
+    pub fn guess_probability(self, length: usize, attempts: u32) -> f64 {
+        let size = u32::try_from(self.charset().len()).expect("alphabet is small");
+        let length = i32::try_from(length).unwrap_or(i32::MAX);
+        f64::from(attempts) / f64::from(size).powi(length)
+    }
+
+    /// The value for the `inputmode` attribute of the code input field.
+    fn input_mode(self) -> &'static str {
+        match self {
+            CodeAlphabet::Numeric => "numeric",
+            CodeAlphabet::ZBase32 => "text",
+        }
+    }
+}
+
+impl FromStr for CodeAlphabet {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<CodeAlphabet, &'static str> {
+        match s {
+            "numeric" => Ok(CodeAlphabet::Numeric),
+            "zbase32" => Ok(CodeAlphabet::ZBase32),
+            _ => Err("unsupported value"),
+        }
+    }
+}
+
+serde_from_str!(CodeAlphabet);
+
+/// Generate a one-time code in the configured format.
+async fn generate_code(ctx: &Context) -> String {
+    random_code(
+        ctx.app.code_alphabet.charset(),
+        ctx.app.code_length,
+        &ctx.app.rng,
+    )
+    .await
+}
+
+/// Format a code for display, in groups separated by spaces.
+fn format_code(code: &str, group_size: usize) -> String {
+    if group_size == 0 {
+        return code.to_owned();
+    }
+    code.as_bytes()
+        .chunks(group_size)
+        .map(|group| std::str::from_utf8(group).expect("code must be ASCII"))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 /// Provide authentication through an email loop.
@@ -26,30 +181,139 @@ pub struct EmailBridgeData {
 ///
 /// A form is rendered as an alternative way to confirm, without following the link. Submitting the
 /// form results in the same callback as the email link.
+///
+/// Depending on the `email_mode`, the email may contain only the link or only the code, and the
+/// other way to confirm is rejected.
 pub async fn auth(ctx: &mut Context, email_addr: EmailAddress) -> HandlerResult {
     metrics::AUTH_EMAIL_REQUESTS.inc();
 
-    // Generate a 12-character one-time pad.
-    let code = random_zbase32(12, &ctx.app.rng).await;
-    // For display, we split it in two groups of 6.
-    let code_fmt = [&code[0..6], &code[6..12]].join(" ");
+    // Generate a one-time pad, and a separate secret for the link.
+    let code = generate_code(ctx).await;
+    let link_token = crypto::nonce(&ctx.app.rng).await;
+
+    // Remember the browser, so only it can complete the login after confirmation elsewhere.
+    let original = if ctx.app.confirm_device == ConfirmDevice::Original {
+        Some(match original_cookie(ctx) {
+            Some(value) => value,
+            None => crypto::nonce(&ctx.app.rng).await,
+        })
+    } else {
+        None
+    };
+
+    // Store the code in the session for use in the verify handler. We should never fail to claim
+    // the session, because we only get here after all other options have failed.
+    let bridge_data = EmailBridgeData {
+        link_token,
+        code,
+        confirmed: false,
+        original: original.clone(),
+    };
+    if !ctx
+        .save_session(BridgeData::Email(bridge_data.clone()))
+        .await?
+    {
+        return Err(BrokerError::Internal(
+            "email fallback failed to claim session".to_owned(),
+        ));
+    }
+
+    send_code(ctx, email_addr, &bridge_data).await?;
+
+    let catalog = ctx.catalog();
+    let mut res = confirm_page(
+        ctx,
+        catalog.gettext("We've sent you an email to confirm your address."),
+    );
+    if let Some(value) = original {
+        let secure = if ctx.app.public_url.starts_with("https://") {
+            "; Secure"
+        } else {
+            ""
+        };
+        res.header(
+            hyper::header::SET_COOKIE,
+            format!("{ORIGINAL_COOKIE}={value}; Path=/confirm; HttpOnly; SameSite=Strict{secure}"),
+        );
+    }
+    Ok(res)
+}
+
+/// The value of the cookie identifying the browser, if set.
+fn original_cookie(ctx: &Context) -> Option<String> {
+    ctx.headers
+        .typed_get::<Cookie>()
+        .and_then(|cookie| cookie.get(ORIGINAL_COOKIE).map(ToOwned::to_owned))
+}
+
+/// Whether the request is from the browser that started the login.
+fn is_original(ctx: &Context, bridge_data: &EmailBridgeData) -> bool {
+    match (&bridge_data.original, original_cookie(ctx)) {
+        (Some(expected), Some(actual)) => secret_matches(expected, &actual),
+        _ => false,
+    }
+}
+
+/// Compare a presented secret with the expected one, in constant time.
+///
+/// An empty expected secret never matches, for sessions created before the secret existed.
+fn secret_matches(expected: &str, presented: &str) -> bool {
+    !expected.is_empty()
+        && constant_time::verify_slices_are_equal(expected.as_bytes(), presented.as_bytes()).is_ok()
+}
+
+/// Render a page asking the user to approve a login they confirm on another device.
+///
+/// The original tab receives the result, so the user must be sure they started the login.
+///
+/// The presented secret is carried over in the form, under the same name.
+fn approve_page(ctx: &Context, secret_name: &str, secret: &str) -> Response {
+    let display_origin = display_origin(ctx);
+    if ctx.want_json {
+        return json_response(&json!({
+            "result": "approval_required",
+            "origin": display_origin,
+        }));
+    }
+
+    let catalog = ctx.catalog();
+    html_response(ctx.app.templates.approve.render(&[
+        ("display_origin", &display_origin),
+        ("session_id", &ctx.session_id),
+        ("secret_name", secret_name),
+        ("secret", secret),
+        ("title", catalog.gettext("Approve login")),
+        (
+            "explanation",
+            catalog.gettext("A login with your address was started at"),
+        ),
+        (
+            "warning",
+            catalog.gettext("Only approve if you started this login yourself. The window where it was started will be logged in."),
+        ),
+        ("button", catalog.gettext("Approve login")),
+    ]))
+}
+
+/// Send the email containing the code and link for the session.
+async fn send_code(
+    ctx: &Context,
+    email_addr: EmailAddress,
+    bridge_data: &EmailBridgeData,
+) -> Result<(), BrokerError> {
+    // For display, we split it in groups.
+    let code_fmt = format_code(&bridge_data.code, ctx.app.code_group_size);
 
     // Generate the URL used to verify email address ownership.
     let href = format!(
-        "{}/confirm?session={}&code={}",
+        "{}/confirm?session={}&token={}",
         ctx.app.public_url,
         utf8_percent_encode(&ctx.session_id, QUERY_ESCAPE),
-        utf8_percent_encode(&code, QUERY_ESCAPE)
+        utf8_percent_encode(&bridge_data.link_token, QUERY_ESCAPE)
     );
 
-    let display_origin = ctx
-        .return_params
-        .as_ref()
-        .expect("email::request called without redirect_uri set")
-        .redirect_uri
-        .origin()
-        .unicode_serialization();
-
+    let mode = email_mode(ctx);
+    let display_origin = display_origin(ctx);
     let catalog = ctx.catalog();
     let subject = format!(
         "{} {}",
@@ -58,28 +322,23 @@ pub async fn auth(ctx: &mut Context, email_addr: EmailAddress) -> HandlerResult
     );
     let params = &[
         ("display_origin", display_origin.as_str()),
-        ("code", &code_fmt),
-        ("link", &href),
+        ("code", if mode.has_code() { &code_fmt } else { "" }),
+        ("link", if mode.has_link() { &href } else { "" }),
         ("title", catalog.gettext("Finish logging in to")),
         ("explanation", catalog.gettext("You received this email so that we may confirm your email address and finish your login to:")),
         ("click", catalog.gettext("Click here to login")),
-        ("alternate", catalog.gettext("Alternatively, enter the following code on the login page:")),
+        (
+            "alternate",
+            if mode.has_link() {
+                catalog.gettext("Alternatively, enter the following code on the login page:")
+            } else {
+                catalog.gettext("Enter the following code on the login page:")
+            },
+        ),
     ];
     let html_body = ctx.app.templates.email_html.render(params);
     let text_body = ctx.app.templates.email_text.render(params);
 
-    // Store the code in the session for use in the verify handler. We should never fail to claim
-    // the session, because we only get here after all other options have failed.
-    if !ctx
-        .save_session(BridgeData::Email(EmailBridgeData { code }))
-        .await?
-    {
-        return Err(BrokerError::Internal(
-            "email fallback failed to claim session".to_owned(),
-        ));
-    }
-
-    // Send the mail.
     let ok = ctx
         .app
         .mailer
@@ -93,35 +352,176 @@ pub async fn auth(ctx: &mut Context, email_addr: EmailAddress) -> HandlerResult
     if !ok {
         return Err(BrokerError::Internal("Failed to send mail".to_owned()));
     }
+    Ok(())
+}
+
+/// The origin of the relying party, for display.
+fn display_origin(ctx: &Context) -> String {
+    ctx.return_params
+        .as_ref()
+        .expect("email bridge called without redirect_uri set")
+        .redirect_uri
+        .origin()
+        .unicode_serialization()
+}
+
+/// The email mode for the relying party, which may override the global setting.
+fn email_mode(ctx: &Context) -> EmailMode {
+    let origin = ctx
+        .return_params
+        .as_ref()
+        .expect("email bridge called without redirect_uri set")
+        .redirect_uri
+        .origin()
+        .ascii_serialization();
+    ctx.app
+        .clients
+        .get(&origin)
+        .and_then(|client| client.email_mode)
+        .unwrap_or(ctx.app.email_mode)
+}
 
-    // Render a form for the user.
+/// Render a form for the user to enter the code, after sending the email.
+fn confirm_page(ctx: &Context, explanation: &str) -> Response {
     if ctx.want_json {
-        Ok(json_response(&json!({
+        return json_response(&json!({
             "result": "verification_code_sent",
             "session": &ctx.session_id,
-        })))
-    } else {
-        let catalog = ctx.catalog();
-        Ok(html_response(ctx.app.templates.confirm_email.render(&[
-            ("display_origin", display_origin.as_str()),
-            ("session_id", &ctx.session_id),
-            ("title", catalog.gettext("Confirm your address")),
-            (
-                "explanation",
-                catalog.gettext("We've sent you an email to confirm your address."),
-            ),
-            (
-                "use",
-                catalog.gettext("Use the link in that email to login to"),
-            ),
-            (
-                "alternate",
+        }));
+    }
+
+    let origin = ctx
+        .return_params
+        .as_ref()
+        .expect("email bridge called without redirect_uri set")
+        .redirect_uri
+        .origin();
+    let privacy = ctx
+        .app
+        .clients
+        .get(&origin.ascii_serialization())
+        .is_some_and(|client| client.privacy);
+
+    let mode = email_mode(ctx);
+
+    // The original tab polls for confirmation on another device, which requires our origin.
+    let poll = mode.has_link() && ctx.app.confirm_device == ConfirmDevice::Original;
+
+    let catalog = ctx.catalog();
+    let mut res = html_response(ctx.app.templates.confirm_email.render(&[
+        ("display_origin", origin.unicode_serialization().as_str()),
+        ("session_id", &ctx.session_id),
+        ("poll", if poll { "1" } else { "" }),
+        ("code", if mode.has_code() { "1" } else { "" }),
+        ("code_input_mode", ctx.app.code_alphabet.input_mode()),
+        ("code_max_length", &(ctx.app.code_length * 2).to_string()),
+        (
+            "privacy",
+            if privacy {
+                catalog.gettext(
+                    "This site will only receive the domain of your email address, not the address itself.",
+                )
+            } else {
+                ""
+            },
+        ),
+        ("title", catalog.gettext("Confirm your address")),
+        ("explanation", explanation),
+        (
+            "use",
+            if mode.has_link() {
+                catalog.gettext("Use the link in that email to login to")
+            } else {
+                catalog.gettext("Enter the code from that email to login to")
+            },
+        ),
+        (
+            "alternate",
+            if mode.has_link() {
                 catalog.gettext(
                     "Alternatively, enter the code from the email to continue in this browser tab:",
-                ),
-            ),
-        ])))
+                )
+            } else {
+                ""
+            },
+        ),
+        (
+            "resend",
+            catalog.gettext("Didn't receive the email? Check your spam folder, or"),
+        ),
+        ("resend_button", catalog.gettext("send it again")),
+    ]));
+    if poll {
+        res.header(hyper::header::CONTENT_SECURITY_POLICY, CSP_SAME_ORIGIN);
+    }
+    res
+}
+
+/// Request handler to send the confirmation email again.
+///
+/// This reuses the session, and by default the code. Resending has its own rate limits, and does
+/// not reset the count of incorrect codes.
+pub async fn resend(ctx: &mut Context) -> HandlerResult {
+    let mut params = ctx.form_params();
+    let session_id = try_get_provider_param!(params, "session");
+
+    let BridgeData::Email(mut bridge_data) = ctx.load_session(&session_id).await? else {
+        return Err(BrokerError::ProviderInput("invalid session".to_owned()));
+    };
+    let data = ctx.session_data.as_ref().expect("session vanished");
+    let email_addr = data.email_addr.clone();
+
+    // Enforce rate limits.
+    match ctx
+        .app
+        .store
+        .send(IncrAndTestLimits {
+            input: LimitInput {
+                email_addr: email_addr.clone(),
+                origin: data
+                    .return_params
+                    .redirect_uri
+                    .origin()
+                    .ascii_serialization(),
+                ip: ctx.ip,
+            },
+            kind: LimitKind::Resend,
+        })
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            metrics::AUTH_LIMITED.inc();
+            return Err(BrokerError::RateLimited);
+        }
+        Err(e) => {
+            return Err(BrokerError::Internal(format!(
+                "could not test rate limit: {e}"
+            )))
+        }
+    }
+
+    if ctx.app.resend_new_code {
+        bridge_data.code = generate_code(ctx).await;
+        bridge_data.link_token = crypto::nonce(&ctx.app.rng).await;
+        if !ctx
+            .save_session(BridgeData::Email(bridge_data.clone()))
+            .await?
+        {
+            return Err(BrokerError::Internal(
+                "email resend failed to claim session".to_owned(),
+            ));
+        }
     }
+
+    send_code(ctx, email_addr, &bridge_data).await?;
+    metrics::AUTH_EMAIL_RESENT.inc();
+
+    let catalog = ctx.catalog();
+    Ok(confirm_page(
+        ctx,
+        catalog.gettext("We've sent you another email to confirm your address."),
+    ))
 }
 
 /// Request handler for one-time pad email loop confirmation.
@@ -131,19 +531,512 @@ pub async fn auth(ctx: &mut Context, email_addr: EmailAddress) -> HandlerResult
 pub async fn confirmation(ctx: &mut Context) -> HandlerResult {
     let mut params = ctx.form_params();
     let session_id = try_get_provider_param!(params, "session");
-    let code = try_get_provider_param!(params, "code")
-        .replace(char::is_whitespace, "")
-        .to_lowercase();
 
-    let BridgeData::Email(bridge_data) = ctx.load_session(&session_id).await? else {
+    let BridgeData::Email(mut bridge_data) = ctx.load_session(&session_id).await? else {
         return Err(BrokerError::ProviderInput("invalid session".to_owned()));
     };
 
-    if code != bridge_data.code {
-        metrics::AUTH_EMAIL_CODE_INCORRECT.inc();
-        return Err(BrokerError::ProviderInput("incorrect code".to_owned()));
+    // If the code was already confirmed on another device, the original tab may complete.
+    let original = is_original(ctx, &bridge_data);
+    if !(bridge_data.confirmed && original) {
+        // The link carries a token, while the code is entered on the login page. Each only
+        // matches its own secret, and only if that way to confirm is enabled.
+        let mode = email_mode(ctx);
+        let (secret_name, secret, expected) = if let Some(token) = params.remove("token") {
+            if !mode.has_link() {
+                return Err(BrokerError::ProviderInput(
+                    "confirming with a link is disabled".to_owned(),
+                ));
+            }
+            ("token", token, &bridge_data.link_token)
+        } else {
+            if !mode.has_code() {
+                return Err(BrokerError::ProviderInput(
+                    "confirming with a code is disabled".to_owned(),
+                ));
+            }
+            let code = try_get_provider_param!(params, "code")
+                .replace(char::is_whitespace, "")
+                .to_lowercase();
+            ("code", code, &bridge_data.code)
+        };
+
+        // Another browser must approve first, after seeing which site it is logging in to.
+        let elsewhere = ctx.app.confirm_device == ConfirmDevice::Original && !original;
+        if elsewhere && !params.contains_key("approve") {
+            return Ok(approve_page(ctx, secret_name, &secret));
+        }
+
+        let attempts = count_attempt(ctx).await?;
+        if attempts > ctx.app.max_code_attempts {
+            return Err(lock_session(ctx).await);
+        }
+        if !secret_matches(expected, &secret) {
+            metrics::AUTH_EMAIL_CODE_INCORRECT.inc();
+            if attempts == ctx.app.max_code_attempts {
+                return Err(lock_session(ctx).await);
+            }
+            return Err(BrokerError::ProviderInput("incorrect code".to_owned()));
+        }
+
+        metrics::AUTH_EMAIL_COMPLETED.inc();
+
+        // Leave completion to the original tab, if configured.
+        if elsewhere {
+            bridge_data.confirmed = true;
+            return confirmed_elsewhere(ctx, bridge_data).await;
+        }
+    }
+
+    // Offer to register a passkey, so the next login can skip the email loop.
+    if ctx.app.webauthn && !ctx.want_json {
+        return webauthn::offer_registration(ctx).await;
     }
 
-    metrics::AUTH_EMAIL_COMPLETED.inc();
     complete_auth(ctx).await
 }
+
+/// Mark the session confirmed, and ask the user to return to the original tab.
+async fn confirmed_elsewhere(ctx: &mut Context, bridge_data: EmailBridgeData) -> HandlerResult {
+    let display_origin = display_origin(ctx);
+    if !ctx.save_session(BridgeData::Email(bridge_data)).await? {
+        return Err(BrokerError::Internal(
+            "email confirmation failed to claim session".to_owned(),
+        ));
+    }
+
+    let catalog = ctx.catalog();
+    Ok(html_response(ctx.app.templates.confirmed.render(&[
+        ("display_origin", &display_origin),
+        ("title", catalog.gettext("Address confirmed")),
+        ("explanation", catalog.gettext("Your address is confirmed.")),
+        (
+            "use",
+            catalog.gettext("Return to the window where you started, to finish logging in to"),
+        ),
+    ])))
+}
+
+/// Request handler for the status of a session, polled by the original tab.
+///
+/// The status is `pending` until the code is confirmed on another device, then `confirmed`. Once
+/// the session is completed or has expired, the status is `expired`. Only the browser that started
+/// the login receives a status, and polls have their own rate limits.
+pub async fn status(ctx: &mut Context) -> HandlerResult {
+    ctx.want_json = true;
+    let mut params = ctx.form_params();
+    let session_id = try_get_provider_param!(params, "session");
+
+    let session = ctx
+        .app
+        .store
+        .send(GetSession { session_id })
+        .await
+        .map_err(|e| BrokerError::Internal(format!("could not load a session: {e}")))?;
+    let Some(Session {
+        data,
+        bridge_data: BridgeData::Email(bridge_data),
+    }) = session
+    else {
+        return Ok(json_response(&json!({ "status": "expired" })));
+    };
+    if !is_original(ctx, &bridge_data) {
+        return Err(BrokerError::ProviderInput(
+            "the session was started in another browser".to_owned(),
+        ));
+    }
+
+    // Enforce rate limits.
+    match ctx
+        .app
+        .store
+        .send(IncrAndTestLimits {
+            input: LimitInput {
+                email_addr: data.email_addr,
+                origin: data
+                    .return_params
+                    .redirect_uri
+                    .origin()
+                    .ascii_serialization(),
+                ip: ctx.ip,
+            },
+            kind: LimitKind::Status,
+        })
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            metrics::AUTH_LIMITED.inc();
+            return Err(BrokerError::RateLimited);
+        }
+        Err(e) => {
+            return Err(BrokerError::Internal(format!(
+                "could not test rate limit: {e}"
+            )))
+        }
+    }
+
+    let status = if bridge_data.confirmed {
+        "confirmed"
+    } else {
+        "pending"
+    };
+    Ok(json_response(&json!({ "status": status })))
+}
+
+/// Count an attempt to confirm the session, returning the number of attempts so far.
+///
+/// This happens before the code is compared, so parallel requests cannot guess more often.
+async fn count_attempt(ctx: &Context) -> Result<u32, BrokerError> {
+    ctx.app
+        .store
+        .send(IncrSessionAttempts {
+            session_id: ctx.session_id.clone(),
+        })
+        .await
+        .map_err(|e| BrokerError::Internal(format!("could not count attempts: {e}")))
+}
+
+/// Invalidate the session after too many incorrect codes.
+async fn lock_session(ctx: &Context) -> BrokerError {
+    if let Err(e) = ctx
+        .app
+        .store
+        .send(DeleteSession {
+            session_id: ctx.session_id.clone(),
+        })
+        .await
+    {
+        return BrokerError::Internal(format!("could not remove a session: {e}"));
+    }
+    metrics::AUTH_EMAIL_CODE_LOCKED.inc();
+    BrokerError::SessionLocked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_code, CodeAlphabet, ConfirmDevice, EmailMode};
+    use crate::utils::testing::{
+        auth_params, form_request, test_builder, TestBroker, TestResponse,
+    };
+    use http::StatusCode;
+
+    /// Start a login using the email loop, returning the session ID.
+    async fn start(broker: &TestBroker) -> String {
+        let res = broker
+            .post("/auth", &auth_params("id_token", "john.doe@example.com"))
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        res.json()["session"].as_str().unwrap().to_owned()
+    }
+
+    async fn confirm(broker: &TestBroker, session: &str, code: &str) -> Result<(), String> {
+        let res = broker
+            .post("/confirm", &[("session", session), ("code", code)])
+            .await;
+        if res.status == StatusCode::OK {
+            Ok(())
+        } else {
+            Err(res.error())
+        }
+    }
+
+    /// Send a form, optionally asking for JSON, from a browser with the given cookie.
+    async fn send(
+        broker: &TestBroker,
+        path: &str,
+        form: &[(&str, &str)],
+        json: bool,
+        cookie: Option<&str>,
+    ) -> TestResponse {
+        let mut req = form_request(path, form, json.then_some("application/json"));
+        if let Some(cookie) = cookie {
+            req.headers_mut()
+                .insert(hyper::header::COOKIE, cookie.parse().unwrap());
+        }
+        broker.send(req).await
+    }
+
+    /// Poll the status of a session from a browser with the given cookie.
+    async fn status(broker: &TestBroker, session: &str, cookie: Option<&str>) -> TestResponse {
+        send(
+            broker,
+            "/confirm/status",
+            &[("session", session)],
+            true,
+            cookie,
+        )
+        .await
+    }
+
+    /// Start a login in the original tab, returning the session ID and the cookie of the browser.
+    async fn start_original(broker: &TestBroker) -> (String, String) {
+        let res = broker
+            .post("/auth", &auth_params("id_token", "john.doe@example.com"))
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        let cookie = res.headers[hyper::header::SET_COOKIE].to_str().unwrap();
+        let (cookie, _) = cookie.split_once(';').unwrap();
+        let session = res.json()["session"].as_str().unwrap().to_owned();
+        (session, cookie.to_owned())
+    }
+
+    #[test]
+    fn test_format_code() {
+        assert_eq!(format_code("abcdef123456", 6), "abcdef 123456");
+        assert_eq!(format_code("12345678", 3), "123 456 78");
+        assert_eq!(format_code("123456", 0), "123456");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lockout() {
+        let broker = TestBroker::new(|builder| builder.max_code_attempts = 2).await;
+
+        let session = start(&broker).await;
+        let code = broker.mail_code().unwrap();
+        assert!(confirm(&broker, &session, "wrong").await.is_err());
+        assert!(confirm(&broker, &session, &code).await.is_ok());
+
+        let session = start(&broker).await;
+        let code = broker.mail_code().unwrap();
+        assert!(confirm(&broker, &session, "wrong").await.is_err());
+        assert_eq!(
+            confirm(&broker, &session, "wrong").await.unwrap_err(),
+            "access_denied"
+        );
+        // The session is gone, so the correct code no longer works either.
+        assert!(confirm(&broker, &session, &code).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_resend() {
+        // Without a configured resend limit, the default applies.
+        let broker = TestBroker::new(|builder| {
+            builder.limits = vec!["ip:50/s".parse().unwrap()];
+        })
+        .await;
+        let session = start(&broker).await;
+        let code = broker.mail_code().unwrap();
+        for _ in 0..3 {
+            let res = broker
+                .post("/confirm/resend", &[("session", &session)])
+                .await;
+            assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        }
+        assert_eq!(broker.mail_count(), 4);
+        assert_eq!(broker.mail_code().unwrap(), code);
+        let res = broker
+            .post("/confirm/resend", &[("session", &session)])
+            .await;
+        assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(broker.mail_count(), 4);
+        assert!(confirm(&broker, &session, &code).await.is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_resend_new_code() {
+        let broker = TestBroker::new(|builder| builder.resend_new_code = true).await;
+        let session = start(&broker).await;
+        let old_code = broker.mail_code().unwrap();
+        let old_token = broker.mail_token().unwrap();
+        let res = broker
+            .post("/confirm/resend", &[("session", &session)])
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        let new_code = broker.mail_code().unwrap();
+        assert_ne!(new_code, old_code);
+        assert_ne!(broker.mail_token().unwrap(), old_token);
+        assert!(confirm(&broker, &session, &old_code).await.is_err());
+        let res = broker
+            .post("/confirm", &[("session", &session), ("token", &old_token)])
+            .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert!(confirm(&broker, &session, &new_code).await.is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_confirm_link() {
+        let broker = TestBroker::new(|_| {}).await;
+
+        let session = start(&broker).await;
+        let token = broker.mail_token().unwrap();
+        let res = broker
+            .post("/confirm", &[("session", &session), ("token", &token)])
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        assert!(res.json()["id_token"].is_string(), "{}", res.body);
+
+        // The link token and the code are separate secrets.
+        let session = start(&broker).await;
+        let code = broker.mail_code().unwrap();
+        let token = broker.mail_token().unwrap();
+        assert!(confirm(&broker, &session, &token).await.is_err());
+        let res = broker
+            .post("/confirm", &[("session", &session), ("token", &code)])
+            .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        assert!(confirm(&broker, &session, &code).await.is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_mail_translated() {
+        let broker = TestBroker::new(|_| {}).await;
+        let mut req = form_request(
+            "/auth",
+            &auth_params("id_token", "john.doe@example.com"),
+            Some("application/json"),
+        );
+        req.headers_mut()
+            .insert(hyper::header::ACCEPT_LANGUAGE, "de".parse().unwrap());
+        let res = broker.send(req).await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        let mail = broker.last_mail();
+        assert!(mail.contains("Du hast diese Email erhalten"), "{mail}");
+        assert!(mail.contains("Klicke hier"), "{mail}");
+        assert!(!mail.contains("You received"), "{mail}");
+        assert!(!mail.contains("Follow this link"), "{mail}");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_email_mode_link() {
+        let broker = TestBroker::new(|builder| builder.email_mode = EmailMode::Link).await;
+        let session = start(&broker).await;
+        assert!(broker.mail_code().is_none());
+        let token = broker.mail_token().unwrap();
+        let res = broker
+            .post("/confirm", &[("session", &session), ("code", &token)])
+            .await;
+        assert_eq!(
+            res.json()["error_description"],
+            "confirming with a code is disabled"
+        );
+        let res = broker
+            .post("/confirm", &[("session", &session), ("token", &token)])
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_email_mode_code() {
+        let broker = TestBroker::new(|builder| builder.email_mode = EmailMode::Code).await;
+        let session = start(&broker).await;
+        assert!(broker.mail_token().is_none());
+        let code = broker.mail_code().unwrap();
+        let res = broker
+            .post("/confirm", &[("session", &session), ("token", &code)])
+            .await;
+        assert_eq!(
+            res.json()["error_description"],
+            "confirming with a link is disabled"
+        );
+        assert!(confirm(&broker, &session, &code).await.is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_max_code_attempts_required() {
+        let mut builder = test_builder();
+        builder.max_code_attempts = 0;
+        assert!(builder.done().await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_code_guess_probability() {
+        let cases = [
+            (CodeAlphabet::Numeric, 6, 1, false),
+            (CodeAlphabet::Numeric, 6, 5, false),
+            (CodeAlphabet::Numeric, 7, 5, true),
+            (CodeAlphabet::Numeric, 7, 10, false),
+            (CodeAlphabet::ZBase32, 4, 1, true),
+            (CodeAlphabet::ZBase32, 4, 5, false),
+            (CodeAlphabet::ZBase32, 5, 5, true),
+        ];
+        for (alphabet, length, attempts, valid) in cases {
+            let mut builder = test_builder();
+            builder.code_alphabet = alphabet;
+            builder.code_length = length;
+            builder.max_code_attempts = attempts;
+            assert_eq!(
+                builder.done().await.is_ok(),
+                valid,
+                "{alphabet:?}, {length}, {attempts}"
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_confirm_original() {
+        let broker =
+            TestBroker::new(|builder| builder.confirm_device = ConfirmDevice::Original).await;
+        let (session, cookie) = start_original(&broker).await;
+        assert_eq!(
+            status(&broker, &session, Some(&cookie)).await.json()["status"],
+            "pending"
+        );
+        assert_eq!(
+            status(&broker, &session, None).await.status,
+            StatusCode::BAD_REQUEST
+        );
+
+        // Another device must first approve, after seeing the origin.
+        let token = broker.mail_token().unwrap();
+        let form = [("session", session.as_str()), ("token", &token)];
+        let res = send(&broker, "/confirm", &form, false, None).await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        assert!(res.body.contains("Approve login"));
+        assert!(res.body.contains("https://rp.example.com"));
+        assert_eq!(
+            status(&broker, &session, Some(&cookie)).await.json()["status"],
+            "pending"
+        );
+
+        let form = [
+            ("session", session.as_str()),
+            ("token", &token),
+            ("approve", "1"),
+        ];
+        let res = send(&broker, "/confirm", &form, false, None).await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        assert!(res.body.contains("Address confirmed"));
+        assert_eq!(
+            status(&broker, &session, Some(&cookie)).await.json()["status"],
+            "confirmed"
+        );
+
+        // Only the browser that started the login can complete it.
+        let form = [("session", session.as_str())];
+        let res = send(&broker, "/confirm", &form, true, None).await;
+        assert!(res.json().get("id_token").is_none(), "{}", res.body);
+        let res = send(
+            &broker,
+            "/confirm",
+            &form,
+            true,
+            Some("portier_original=forged"),
+        )
+        .await;
+        assert!(res.json().get("id_token").is_none(), "{}", res.body);
+        let res = send(&broker, "/confirm", &form, true, Some(&cookie)).await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        assert!(res.json()["id_token"].is_string(), "{}", res.body);
+        assert_eq!(
+            status(&broker, &session, Some(&cookie)).await.json()["status"],
+            "expired"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_status_limited() {
+        let broker = TestBroker::new(|builder| {
+            builder.confirm_device = ConfirmDevice::Original;
+            builder.limits = vec!["ip:email:status:2/m".parse().unwrap()];
+        })
+        .await;
+        let (session, cookie) = start_original(&broker).await;
+        for _ in 0..2 {
+            let res = status(&broker, &session, Some(&cookie)).await;
+            assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        }
+        let res = status(&broker, &session, Some(&cookie)).await;
+        assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+    }
+}
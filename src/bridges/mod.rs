@@ -1,8 +1,12 @@
 use crate::agents::{DecrLimits, DeleteSession, SaveAuthCode};
-use crate::config::LimitInput;
+use crate::config::{ConfigRc, LimitInput};
 use crate::crypto::{create_jwt, session_id};
 use crate::error::BrokerError;
+use crate::handlers::device;
+use crate::login_cookie;
+use crate::utils::unix_timestamp;
 use crate::web::{json_response, return_to_relier, Context, HandlerResult, ResponseType};
+use crate::webfinger::{Link, Relation};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
@@ -12,15 +16,31 @@ use serde_json::json;
 pub enum BridgeData {
     Email(email::EmailBridgeData),
     Oidc(oidc::OidcBridgeData),
+    Saml(saml::SamlBridgeData),
+    Webauthn(webauthn::WebauthnBridgeData),
+}
+
+/// Fetch whatever the bridge for a webfinger link needs from the network, so it is cached.
+///
+/// This only depends on the app configuration, so can run as a separate task.
+pub async fn prefetch(app: ConfigRc, link: Link) -> Result<(), BrokerError> {
+    match link.rel {
+        Relation::Portier | Relation::Google | Relation::Oidc => oidc::prefetch(app, link).await,
+        Relation::Saml => saml::prefetch(app, link).await,
+    }
 }
 
 /// Once a bridge has authenticated the user, this function can be used to finish up the redirect
 /// to the relying party with an `id_token` or `code` generated by us.
+///
+/// This also remembers the verified email address in the browser, if enabled.
 pub async fn complete_auth(ctx: &mut Context) -> HandlerResult {
-    let data = ctx
+    let mut data = ctx
         .session_data
         .take()
         .expect("complete_auth called without a session");
+    let auth_time = *data.auth_time.get_or_insert_with(unix_timestamp);
+    let email_addr = data.email_addr.clone();
     ctx.app
         .store
         .send(DeleteSession {
@@ -47,6 +67,12 @@ pub async fn complete_auth(ctx: &mut Context) -> HandlerResult {
         .await
         .map_err(|e| BrokerError::Internal(format!("could not decrement rate limits: {e}")))?;
 
+    if let Some(device_code) = data.device_code.clone() {
+        let mut res = device::complete(ctx, &device_code, data).await?;
+        login_cookie::remember(ctx, &mut res, &email_addr, auth_time);
+        return Ok(res);
+    }
+
     let (auth_field, auth_value) = match data.response_type {
         ResponseType::IdToken => {
             let jwt = create_jwt(
@@ -56,6 +82,7 @@ pub async fn complete_auth(ctx: &mut Context) -> HandlerResult {
                 &origin,
                 &data.nonce,
                 data.signing_alg,
+                auth_time,
             )
             .await
             .map_err(|err| BrokerError::Internal(format!("Could not create a JWT: {err:?}")))?;
@@ -81,9 +108,13 @@ pub async fn complete_auth(ctx: &mut Context) -> HandlerResult {
             "state": &ctx.return_params.as_ref().unwrap().state,
         })))
     } else {
-        Ok(return_to_relier(ctx, &[(auth_field, &auth_value)]))
+        let mut res = return_to_relier(ctx, &[(auth_field, &auth_value)]).await?;
+        login_cookie::remember(ctx, &mut res, &email_addr, auth_time);
+        Ok(res)
     }
 }
 
 pub mod email;
 pub mod oidc;
+pub mod saml;
+pub mod webauthn;
@@ -1,16 +1,19 @@
-use crate::agents::FetchUrlCached;
+use crate::agents::{FetchUrl, FetchUrlCached};
 use crate::bridges::{complete_auth, BridgeData};
-use crate::crypto::{self, SigningAlgorithm};
+use crate::config::{issuer_from_href, Config, ConfigRc};
+use crate::crypto::{self, CodeChallenge, SigningAlgorithm};
 use crate::email_address::EmailAddress;
 use crate::error::BrokerError;
 use crate::utils::{http::ResponseExt, unix_timestamp};
 use crate::web::{empty_response, json_response, Context, HandlerResult};
 use crate::webfinger::{Link, Relation};
 use crate::{metrics, validation};
-use http::StatusCode;
+use base64::prelude::*;
+use http::{Request, StatusCode};
+use hyper::Body;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use url::Url;
+use url::{form_urlencoded, Url};
 
 /// The origin of the Google identity provider.
 pub const GOOGLE_IDP_ORIGIN: &str = "https://accounts.google.com";
@@ -21,17 +24,28 @@ pub const LEEWAY: u64 = 30;
 #[derive(Clone, Serialize, Deserialize)]
 pub struct OidcBridgeData {
     pub link: Link,
+    /// The provider origin, or the issuer for generic providers.
     pub origin: String,
     pub client_id: String,
     pub nonce: String,
     pub signing_alg: SigningAlgorithm,
+    /// PKCE code verifier, if we use the authorization code flow with the provider.
+    #[serde(default)]
+    pub code_verifier: Option<String>,
 }
 
 /// OpenID Connect configuration document.
 #[derive(Deserialize)]
 struct ProviderConfig {
     authorization_endpoint: Url,
+    token_endpoint: Option<Url>,
     jwks_uri: Url,
+    #[serde(default)]
+    response_types_supported: Vec<String>,
+    #[serde(default)]
+    code_challenge_methods_supported: Vec<String>,
+    #[serde(default = "default_token_endpoint_auth_methods_supported")]
+    token_endpoint_auth_methods_supported: Vec<String>,
     #[serde(default = "default_response_modes_supported")]
     response_modes_supported: Vec<String>,
     #[serde(default = "default_id_token_signing_alg_values_supported")]
@@ -49,11 +63,69 @@ fn default_id_token_signing_alg_values_supported() -> Vec<String> {
     vec!["RS256".to_owned()]
 }
 
+fn default_token_endpoint_auth_methods_supported() -> Vec<String> {
+    vec!["client_secret_basic".to_owned()]
+}
+
+/// Token endpoint response.
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Methods we can use to authenticate at the token endpoint of a provider.
+#[derive(Clone, Copy)]
+enum TokenAuthMethod {
+    None,
+    Basic,
+    Post,
+}
+
+impl ProviderConfig {
+    /// Select a method to authenticate at the token endpoint, given the client secret we have.
+    fn token_auth_method(&self, client_secret: Option<&str>) -> Option<TokenAuthMethod> {
+        let supports = |method: &str| {
+            self.token_endpoint_auth_methods_supported
+                .iter()
+                .any(|value| value == method)
+        };
+        match client_secret {
+            Some(_) if supports("client_secret_basic") => Some(TokenAuthMethod::Basic),
+            Some(_) if supports("client_secret_post") => Some(TokenAuthMethod::Post),
+            None if supports("none") => Some(TokenAuthMethod::None),
+            _ => None,
+        }
+    }
+
+    /// Whether we can use the authorization code flow with PKCE with this provider.
+    fn supports_code_flow(&self, client_secret: Option<&str>) -> bool {
+        self.token_endpoint.is_some()
+            && self.response_types_supported.iter().any(|v| v == "code")
+            && self
+                .code_challenge_methods_supported
+                .iter()
+                .any(|v| v == "S256")
+            && self.token_auth_method(client_secret).is_some()
+    }
+}
+
+/// The client secret we have for a provider, if any.
+fn client_secret<'a>(ctx: &'a Context, bridge_data: &OidcBridgeData) -> Option<&'a str> {
+    match bridge_data.link.rel {
+        Relation::Oidc => ctx
+            .app
+            .oidc_providers
+            .get(&bridge_data.origin)
+            .and_then(|provider| provider.client_secret.as_deref()),
+        Relation::Portier | Relation::Google | Relation::Saml => None,
+    }
+}
+
 /// OpenID Connect key set document.
 #[derive(Deserialize)]
-struct ProviderKeys {
+pub struct ProviderKeys {
     #[serde(default)]
-    keys: Vec<ProviderKey>,
+    pub keys: Vec<ProviderKey>,
 }
 
 #[derive(Deserialize)]
@@ -73,6 +145,71 @@ pub struct ProviderKey {
     pub e: String,
     #[serde(default)]
     pub x: String,
+    #[serde(default)]
+    pub y: String,
+}
+
+/// Determine the provider origin for a webfinger link, or the issuer for generic providers.
+///
+/// Links for Google or generic providers that are not configured are cancelled.
+fn provider_origin(app: &Config, link: &Link) -> Result<String, BrokerError> {
+    let parse_href = || {
+        validation::parse_oidc_href(&link.href).ok_or_else(|| {
+            BrokerError::Provider(format!("invalid href (validation failed): {}", link.href))
+        })
+    };
+    #[cfg(not(feature = "insecure"))]
+    let require_https = || {
+        if link.href.scheme() == "https" {
+            Ok(())
+        } else {
+            Err(BrokerError::Provider(format!(
+                "invalid href (not HTTPS): {}",
+                link.href
+            )))
+        }
+    };
+    match link.rel {
+        Relation::Portier => {
+            let provider_origin = parse_href()?;
+            #[cfg(not(feature = "insecure"))]
+            require_https()?;
+            Ok(provider_origin)
+        }
+        Relation::Google => {
+            if app.google_client_id.is_none() {
+                return Err(BrokerError::ProviderCancelled);
+            }
+            let provider_origin = parse_href()?;
+            if provider_origin != GOOGLE_IDP_ORIGIN {
+                return Err(BrokerError::Provider(format!(
+                    "invalid href: Google provider only supports {GOOGLE_IDP_ORIGIN}"
+                )));
+            }
+            Ok(provider_origin)
+        }
+        Relation::Oidc => {
+            let issuer = issuer_from_href(&link.href);
+            if !app.oidc_providers.contains_key(&issuer) {
+                return Err(BrokerError::ProviderCancelled);
+            }
+            #[cfg(not(feature = "insecure"))]
+            require_https()?;
+            Ok(issuer)
+        }
+        Relation::Saml => unreachable!("SAML links are handled by the SAML bridge"),
+    }
+}
+
+/// Fetch the configuration and keys of the provider for a webfinger link.
+///
+/// This only depends on the app configuration, so can run as a separate task. The results are
+/// cached by the store, so a subsequent call to `auth` for the same link does not wait on the
+/// network again.
+pub async fn prefetch(app: ConfigRc, link: Link) -> Result<(), BrokerError> {
+    let provider_origin = provider_origin(&app, &link)?;
+    fetch_config(&app, &provider_origin).await?;
+    Ok(())
 }
 
 /// Provide authentication using OpenID Connect.
@@ -82,7 +219,7 @@ pub struct ProviderKey {
 /// to definitively match the callback to this request.
 ///
 /// This function handles both Portier providers, which works without registration, as well as
-/// the Google provider, for which we have a preregistered `client_id`.
+/// the Google provider and generic providers, for which we have a preregistered `client_id`.
 pub async fn auth(
     ctx: &mut Context,
     email_addr: &EmailAddress,
@@ -93,27 +230,17 @@ pub async fn auth(
     let provider_nonce = crypto::nonce(&ctx.app.rng).await;
 
     // Determine the parameters to use, based on the webfinger link.
-    let provider_origin = validation::parse_oidc_href(&link.href).ok_or_else(|| {
-        BrokerError::Provider(format!("invalid href (validation failed): {}", link.href))
-    })?;
+    let provider_origin = provider_origin(&ctx.app, link)?;
     let mut bridge_data = match link.rel {
         Relation::Portier => {
             metrics::AUTH_OIDC_REQUESTS_PORTIER.inc();
-            #[cfg(not(feature = "insecure"))]
-            {
-                if link.href.scheme() != "https" {
-                    return Err(BrokerError::Provider(format!(
-                        "invalid href (not HTTPS): {}",
-                        link.href
-                    )));
-                }
-            }
             OidcBridgeData {
                 link: link.clone(),
                 origin: provider_origin,
                 client_id: ctx.app.public_url.clone(),
                 nonce: provider_nonce,
                 signing_alg: SigningAlgorithm::Rs256,
+                code_verifier: None,
             }
         }
         // Delegate to the OpenID Connect bridge for Google, if configured.
@@ -124,32 +251,56 @@ pub async fn auth(
                 .google_client_id
                 .as_ref()
                 .ok_or(BrokerError::ProviderCancelled)?;
-            if provider_origin != GOOGLE_IDP_ORIGIN {
-                return Err(BrokerError::Provider(format!(
-                    "invalid href: Google provider only supports {GOOGLE_IDP_ORIGIN}"
-                )));
-            }
             OidcBridgeData {
                 link: link.clone(),
                 origin: provider_origin,
                 client_id: client_id.clone(),
                 nonce: provider_nonce,
                 signing_alg: SigningAlgorithm::Rs256,
+                code_verifier: None,
             }
         }
+        // Generic providers are only configured in `domain_overrides`, with credentials.
+        Relation::Oidc => {
+            metrics::AUTH_OIDC_REQUESTS_GENERIC.inc();
+            let provider = ctx
+                .app
+                .oidc_providers
+                .get(&provider_origin)
+                .ok_or(BrokerError::ProviderCancelled)?;
+            OidcBridgeData {
+                link: link.clone(),
+                origin: provider_origin,
+                client_id: provider.client_id.clone(),
+                nonce: provider_nonce,
+                signing_alg: SigningAlgorithm::Rs256,
+                code_verifier: None,
+            }
+        }
+        Relation::Saml => unreachable!("SAML links are handled by the SAML bridge"),
     };
 
     // Retrieve the provider's configuration.
-    let (
-        ProviderConfig {
-            authorization_endpoint: mut auth_url,
-            response_modes_supported: response_modes,
-            id_token_signing_alg_values_supported: signing_algs,
-            accepts_id_token_signing_alg_query_param: accepts_signing_alg,
-            ..
-        },
-        key_set,
-    ) = fetch_config(ctx, &bridge_data).await?;
+    let (provider_config, key_set) = fetch_config(&ctx.app, &bridge_data.origin).await?;
+
+    // Prefer the authorization code flow with PKCE, because many providers disable the implicit
+    // flow. This requires we can authenticate at the token endpoint.
+    let code_challenge = if provider_config.supports_code_flow(client_secret(ctx, &bridge_data)) {
+        let code_verifier = crypto::code_verifier(&ctx.app.rng).await;
+        let code_challenge = CodeChallenge::s256(&code_verifier);
+        bridge_data.code_verifier = Some(code_verifier);
+        Some(code_challenge)
+    } else {
+        None
+    };
+
+    let ProviderConfig {
+        authorization_endpoint: mut auth_url,
+        response_modes_supported: response_modes,
+        id_token_signing_alg_values_supported: signing_algs,
+        accepts_id_token_signing_alg_query_param: accepts_signing_alg,
+        ..
+    } = provider_config;
 
     {
         // Create the URL to redirect to.
@@ -159,24 +310,49 @@ pub async fn auth(
             ("scope", "openid email"),
             ("nonce", &bridge_data.nonce),
             ("state", &ctx.session_id),
-            ("response_type", "id_token"),
             ("client_id", &bridge_data.client_id),
             ("redirect_uri", &format!("{}/callback", &ctx.app.public_url)),
         ]);
+        if let Some(ref code_challenge) = code_challenge {
+            query.extend_pairs(&[
+                ("response_type", "code"),
+                ("code_challenge", &code_challenge.challenge),
+                ("code_challenge_method", code_challenge.method.as_str()),
+            ]);
+        } else {
+            query.append_pair("response_type", "id_token");
+        }
         if !prompt.is_empty() {
             query.append_pair("prompt", prompt);
         }
 
-        // Prefer `form_post` response mode, otherwise use `fragment`.
+        // Prefer `form_post` response mode, otherwise use `fragment`, or the default `query` for
+        // the code flow. Both are rewritten to a POST in the callback.
         if response_modes.iter().any(|mode| mode == "form_post") {
             query.append_pair("response_mode", "form_post");
-        } else if !response_modes.iter().any(|mode| mode == "fragment") {
+        } else if code_challenge.is_none() && !response_modes.iter().any(|mode| mode == "fragment")
+        {
             return Err(BrokerError::Provider(format!(
                 "neither form_post nor fragment response modes supported by {}'s IdP ",
                 email_addr.domain()
             )));
         }
 
+        // Without selecting an algorithm, the provider signs using its default. Expect RS256 if
+        // it is supported, because it is mandatory in OpenID Connect, otherwise the first
+        // algorithm listed that we also support.
+        if !signing_algs.iter().any(|s| s.as_str() == "RS256") {
+            bridge_data.signing_alg = signing_algs
+                .iter()
+                .find_map(|s| s.parse().ok())
+                .ok_or_else(|| {
+                    BrokerError::Provider(format!(
+                        "no supported signing algorithms listed by {}'s IdP",
+                        email_addr.domain()
+                    ))
+                })?;
+        }
+
         // NOTE: This query parameter is non-standard.
         // Prefer `Ed25519`, but there is no standard way to select it, so we introduce extra
         // fields, and take care we don't accidentally break the protocol. On top of this,
@@ -251,10 +427,16 @@ pub async fn callback(ctx: &mut Context) -> HandlerResult {
         }
     }
 
-    let id_token = try_get_provider_param!(params, "id_token");
-
     // Retrieve the provider's configuration.
-    let (_, key_set) = fetch_config(ctx, &bridge_data).await?;
+    let (provider_config, key_set) = fetch_config(&ctx.app, &bridge_data.origin).await?;
+
+    // With the code flow, exchange the code for tokens at the token endpoint.
+    let id_token = if let Some(ref code_verifier) = bridge_data.code_verifier {
+        let code = try_get_provider_param!(params, "code");
+        exchange_code(ctx, &bridge_data, &provider_config, &code, code_verifier).await?
+    } else {
+        try_get_provider_param!(params, "id_token")
+    };
 
     // Verify the signature.
     let jwt_payload = crypto::verify_jws(&id_token, &key_set.keys, bridge_data.signing_alg)
@@ -310,6 +492,29 @@ pub async fn callback(ctx: &mut Context) -> HandlerResult {
             let expected = data.email_addr.normalize_google();
             check_token_field!(google_email_addr == expected, "email", descr);
         }
+        Relation::Oidc => {
+            // Providers may let users choose an unverified email, so check the claim if required.
+            let provider = ctx
+                .app
+                .oidc_providers
+                .get(&bridge_data.origin)
+                .ok_or_else(|| {
+                    BrokerError::Provider(format!(
+                        "provider {} is not configured",
+                        bridge_data.origin
+                    ))
+                })?;
+            if provider.require_email_verified {
+                let email_verified = jwt_payload.get("email_verified").and_then(Value::as_bool);
+                check_token_field!(email_verified == Some(true), "email_verified", descr);
+            }
+            // Compare the normalized form, because the provider may return the original case.
+            let email_addr: EmailAddress = email.parse().map_err(|err| {
+                BrokerError::ProviderInput(format!("failed to parse email in {descr}: {err}"))
+            })?;
+            check_token_field!(email_addr == data.email_addr, "email", descr);
+        }
+        Relation::Saml => unreachable!("SAML links are handled by the SAML bridge"),
     }
 
     // Everything is okay. Build a new identity token and send it to the relying party.
@@ -317,17 +522,108 @@ pub async fn callback(ctx: &mut Context) -> HandlerResult {
     complete_auth(ctx).await
 }
 
+/// Exchange an authorization code for an identity token at the provider's token endpoint.
+async fn exchange_code(
+    ctx: &Context,
+    bridge_data: &OidcBridgeData,
+    provider_config: &ProviderConfig,
+    code: &str,
+    code_verifier: &str,
+) -> Result<String, BrokerError> {
+    let token_endpoint = provider_config.token_endpoint.as_ref().ok_or_else(|| {
+        BrokerError::Provider(format!("{} has no token_endpoint", bridge_data.origin))
+    })?;
+    #[cfg(not(feature = "insecure"))]
+    {
+        if token_endpoint.scheme() != "https" {
+            return Err(BrokerError::Provider(format!(
+                "{}'s token_endpoint is not HTTPS",
+                bridge_data.origin
+            )));
+        }
+    }
+
+    let client_secret = client_secret(ctx, bridge_data);
+    let auth_method = provider_config
+        .token_auth_method(client_secret)
+        .ok_or_else(|| {
+            BrokerError::Provider(format!(
+                "no supported token endpoint authentication method for {}",
+                bridge_data.origin
+            ))
+        })?;
+
+    let request = {
+        let redirect_uri = format!("{}/callback", &ctx.app.public_url);
+        let mut body = form_urlencoded::Serializer::new(String::new());
+        body.extend_pairs(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &redirect_uri),
+            ("code_verifier", code_verifier),
+        ]);
+        let mut request = Request::post(token_endpoint.as_str())
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/x-www-form-urlencoded");
+        match (auth_method, client_secret) {
+            (TokenAuthMethod::Basic, Some(secret)) => {
+                // Per OAuth2, the credentials are form-encoded before Basic encoding.
+                let encode = |value: &str| {
+                    form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>()
+                };
+                let credentials = format!("{}:{}", encode(&bridge_data.client_id), encode(secret));
+                let mut auth = String::from("Basic ");
+                BASE64_STANDARD.encode_string(credentials, &mut auth);
+                request = request.header("Authorization", auth);
+            }
+            (TokenAuthMethod::Post, Some(secret)) => {
+                body.extend_pairs(&[
+                    ("client_id", bridge_data.client_id.as_str()),
+                    ("client_secret", secret),
+                ]);
+            }
+            _ => {
+                body.append_pair("client_id", &bridge_data.client_id);
+            }
+        }
+        request
+            .body(Body::from(body.finish()))
+            .expect("could not build the token request")
+    };
+
+    let res = ctx
+        .app
+        .fetcher
+        .send(FetchUrl {
+            request,
+            metric: &metrics::AUTH_OIDC_FETCH_TOKEN_DURATION,
+        })
+        .await
+        .map_err(|e| {
+            BrokerError::Provider(format!(
+                "could not exchange the code at {}: {e}",
+                bridge_data.origin
+            ))
+        })?;
+    let res: TokenResponse = serde_json::from_str(&res.data).map_err(|e| {
+        BrokerError::Provider(format!(
+            "could not parse {}'s token response: {e}",
+            bridge_data.origin
+        ))
+    })?;
+    Ok(res.id_token)
+}
+
 // Retrieve and verify the provider's configuration.
 async fn fetch_config(
-    ctx: &mut Context,
-    bridge_data: &OidcBridgeData,
+    app: &Config,
+    origin: &str,
 ) -> Result<(ProviderConfig, ProviderKeys), BrokerError> {
-    let config_url = format!("{}/.well-known/openid-configuration", bridge_data.origin)
+    let config_url = format!("{origin}/.well-known/openid-configuration")
         .parse()
         .expect("could not build the OpenID Connect configuration URL");
 
-    let provider_config = ctx
-        .app
+    let provider_config = app
         .store
         .send(FetchUrlCached {
             url: config_url,
@@ -335,52 +631,37 @@ async fn fetch_config(
         })
         .await
         .map_err(|e| {
-            BrokerError::Provider(format!(
-                "could not fetch {}'s configuration: {e}",
-                bridge_data.origin
-            ))
+            BrokerError::Provider(format!("could not fetch {origin}'s configuration: {e}"))
         })?;
     let provider_config: ProviderConfig = serde_json::from_str(&provider_config).map_err(|e| {
-        BrokerError::Provider(format!(
-            "could not parse {}'s configuration: {e}",
-            bridge_data.origin
-        ))
+        BrokerError::Provider(format!("could not parse {origin}'s configuration: {e}"))
     })?;
 
     #[cfg(not(feature = "insecure"))]
     {
         if provider_config.authorization_endpoint.scheme() != "https" {
             return Err(BrokerError::Provider(format!(
-                "{}'s authorization_endpoint is not HTTPS",
-                bridge_data.origin
+                "{origin}'s authorization_endpoint is not HTTPS"
             )));
         }
         if provider_config.jwks_uri.scheme() != "https" {
             return Err(BrokerError::Provider(format!(
-                "{}'s jwks_uri is not HTTPS",
-                bridge_data.origin
+                "{origin}'s jwks_uri is not HTTPS"
             )));
         }
     }
 
     // Grab the keys from the provider.
-    let key_set = ctx
-        .app
+    let key_set = app
         .store
         .send(FetchUrlCached {
             url: provider_config.jwks_uri.clone(),
             metric: &metrics::AUTH_OIDC_FETCH_JWKS_DURATION,
         })
         .await
-        .map_err(|e| {
-            BrokerError::Provider(format!(
-                "could not fetch {}'s keys: {e}",
-                bridge_data.origin
-            ))
-        })?;
-    let key_set: ProviderKeys = serde_json::from_str(&key_set).map_err(|e| {
-        BrokerError::Provider(format!("could not parse{}'s keys: {e}", bridge_data.origin))
-    })?;
+        .map_err(|e| BrokerError::Provider(format!("could not fetch {origin}'s keys: {e}")))?;
+    let key_set: ProviderKeys = serde_json::from_str(&key_set)
+        .map_err(|e| BrokerError::Provider(format!("could not parse {origin}'s keys: {e}")))?;
 
     Ok((provider_config, key_set))
 }
@@ -0,0 +1,866 @@
+use crate::agents::FetchUrlCached;
+use crate::bridges::oidc::LEEWAY;
+use crate::bridges::{complete_auth, BridgeData};
+use crate::config::{Config, ConfigRc};
+use crate::crypto;
+use crate::email_address::EmailAddress;
+use crate::error::BrokerError;
+use crate::metrics;
+use crate::utils::der;
+use crate::utils::xmldsig::{
+    self, children, decode_base64_text, element_text, escape_attribute, find_child, is_element,
+    XmlDsigError,
+};
+use crate::utils::{format_xml_datetime, http::ResponseExt, parse_xml_datetime, unix_timestamp};
+use crate::web::{
+    empty_response, form_post_response, json_response, Context, HandlerResult, Response,
+};
+use crate::webfinger::Link;
+use base64::prelude::*;
+use http::StatusCode;
+use hyper::Body;
+use roxmltree::{Document, Node};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use url::Url;
+
+const NS_METADATA: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+const NS_PROTOCOL: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const NS_ASSERTION: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+
+const BINDING_REDIRECT: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect";
+const BINDING_POST: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const STATUS_NO_PASSIVE: &str = "urn:oasis:names:tc:SAML:2.0:status:NoPassive";
+const NAMEID_EMAIL: &str = "urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress";
+const CONFIRMATION_BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+
+/// Attribute names that identity providers commonly use for the email address.
+const EMAIL_ATTRIBUTES: &[&str] = &[
+    "email",
+    "mail",
+    "urn:oid:0.9.2342.19200300.100.1.3",
+    "http://schemas.xmlsoap.org/ws/2005/05/identity/claims/emailaddress",
+];
+
+/// Data we store in the session.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SamlBridgeData {
+    pub link: Link,
+    /// The entity ID of the identity provider.
+    pub entity_id: String,
+    /// The ID of the `AuthnRequest` we sent.
+    pub request_id: String,
+}
+
+/// The parts of identity provider metadata we use.
+struct IdpMetadata {
+    entity_id: String,
+    sso_redirect: Option<Url>,
+    sso_post: Option<Url>,
+    /// Public keys of signing certificates.
+    keys: Vec<Vec<u8>>,
+}
+
+/// Our entity ID, which is also the URL of our metadata.
+fn sp_entity_id(app: &Config) -> String {
+    format!("{}/saml/metadata", app.public_url)
+}
+
+/// The URL of our assertion consumer service.
+fn acs_url(app: &Config) -> String {
+    format!("{}/saml/acs", app.public_url)
+}
+
+/// Fetch the metadata of the identity provider for a link.
+///
+/// The `href` of a SAML link is the URL of the identity provider metadata. Because the metadata is
+/// fetched over HTTPS from a locally configured URL, its own signature is not verified.
+async fn fetch_metadata(app: &Config, link: &Link) -> Result<IdpMetadata, BrokerError> {
+    #[cfg(not(feature = "insecure"))]
+    {
+        if link.href.scheme() != "https" {
+            return Err(BrokerError::Provider(format!(
+                "invalid href (not HTTPS): {}",
+                link.href
+            )));
+        }
+    }
+
+    let metadata = app
+        .store
+        .send(FetchUrlCached {
+            url: link.href.clone(),
+            metric: &metrics::AUTH_SAML_FETCH_METADATA_DURATION,
+        })
+        .await
+        .map_err(|e| BrokerError::Provider(format!("could not fetch {}: {e}", link.href)))?;
+    parse_metadata(&metadata)
+        .map_err(|e| BrokerError::Provider(format!("could not parse {}: {e}", link.href)))
+}
+
+/// Parse identity provider metadata.
+///
+/// The document may be a single `EntityDescriptor`, or an `EntitiesDescriptor`, in which case the
+/// first entity with an identity provider role is used.
+fn parse_metadata(input: &str) -> Result<IdpMetadata, String> {
+    let doc = Document::parse(input).map_err(|e| e.to_string())?;
+    let (entity, idp) = doc
+        .descendants()
+        .filter(|&node| is_element(node, NS_METADATA, "EntityDescriptor"))
+        .find_map(|entity| {
+            children(entity, NS_METADATA, "IDPSSODescriptor")
+                .find(|idp| {
+                    idp.attribute("protocolSupportEnumeration")
+                        .is_some_and(|value| value.split_whitespace().any(|v| v == NS_PROTOCOL))
+                })
+                .map(|idp| (entity, idp))
+        })
+        .ok_or("no SAML 2.0 identity provider found")?;
+
+    let entity_id = entity
+        .attribute("entityID")
+        .ok_or("the entity has no entityID")?
+        .to_owned();
+
+    let sso_location = |binding: &str| -> Result<Option<Url>, String> {
+        children(idp, NS_METADATA, "SingleSignOnService")
+            .find(|service| service.attribute("Binding") == Some(binding))
+            .and_then(|service| service.attribute("Location"))
+            .map(|location| Url::parse(location).map_err(|e| format!("invalid Location: {e}")))
+            .transpose()
+    };
+    let sso_redirect = sso_location(BINDING_REDIRECT)?;
+    let sso_post = sso_location(BINDING_POST)?;
+    #[cfg(not(feature = "insecure"))]
+    {
+        if [&sso_redirect, &sso_post]
+            .into_iter()
+            .flatten()
+            .any(|url| url.scheme() != "https")
+        {
+            return Err("SingleSignOnService is not HTTPS".to_owned());
+        }
+    }
+    if sso_redirect.is_none() && sso_post.is_none() {
+        return Err("no supported SingleSignOnService binding".to_owned());
+    }
+
+    let mut keys = vec![];
+    for descriptor in children(idp, NS_METADATA, "KeyDescriptor") {
+        if descriptor.attribute("use").is_some_and(|v| v != "signing") {
+            continue;
+        }
+        let certs = descriptor
+            .descendants()
+            .filter(|&node| is_element(node, xmldsig::NS_DSIG, "X509Certificate"));
+        for cert in certs {
+            let key = decode_base64_text(cert.text().unwrap_or_default())
+                .as_deref()
+                .and_then(der::certificate_public_key)
+                .ok_or("invalid X509Certificate")?;
+            keys.push(key);
+        }
+    }
+    if keys.is_empty() {
+        return Err("no signing certificates found".to_owned());
+    }
+
+    Ok(IdpMetadata {
+        entity_id,
+        sso_redirect,
+        sso_post,
+        keys,
+    })
+}
+
+/// Fetch the metadata of the identity provider for a link.
+///
+/// Like `oidc::prefetch`, this can run as a separate task to warm the cache.
+pub async fn prefetch(app: ConfigRc, link: Link) -> Result<(), BrokerError> {
+    fetch_metadata(&app, &link).await?;
+    Ok(())
+}
+
+/// Build an `AuthnRequest` document.
+fn authn_request(app: &Config, request_id: &str, destination: &Url, prompt: &str) -> String {
+    let mut xml = String::from(
+        r#"<samlp:AuthnRequest xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion" Version="2.0""#,
+    );
+    let mut attribute = |name: &str, value: &str| {
+        xml.push(' ');
+        xml.push_str(name);
+        xml.push_str("=\"");
+        escape_attribute(&mut xml, value);
+        xml.push('"');
+    };
+    attribute("ID", request_id);
+    attribute("IssueInstant", &format_xml_datetime(unix_timestamp()));
+    attribute("Destination", destination.as_str());
+    attribute("AssertionConsumerServiceURL", &acs_url(app));
+    attribute("ProtocolBinding", BINDING_POST);
+    // We forward prompt=none and prompt=login.
+    match prompt {
+        "none" => attribute("IsPassive", "true"),
+        "login" => attribute("ForceAuthn", "true"),
+        _ => {}
+    }
+    xml.push_str("><saml:Issuer>");
+    escape_attribute(&mut xml, &sp_entity_id(app));
+    xml.push_str("</saml:Issuer></samlp:AuthnRequest>");
+    xml
+}
+
+/// Provide authentication using SAML 2.0.
+///
+/// Send the user agent to the single sign-on service of the identity provider with an
+/// `AuthnRequest`, using the HTTP-Redirect binding if available, or the HTTP-POST binding
+/// otherwise. The identity provider posts its response to our assertion consumer service.
+pub async fn auth(
+    ctx: &mut Context,
+    _email_addr: &EmailAddress,
+    link: &Link,
+    prompt: &str,
+) -> HandlerResult {
+    metrics::AUTH_SAML_REQUESTS.inc();
+    let metadata = fetch_metadata(&ctx.app, link).await?;
+
+    let request_id = format!("_{}", crypto::nonce(&ctx.app.rng).await);
+    let destination = metadata
+        .sso_redirect
+        .as_ref()
+        .or(metadata.sso_post.as_ref())
+        .expect("metadata without a single sign-on service");
+    let request = authn_request(&ctx.app, &request_id, destination, prompt);
+
+    // Save session data, committing the session to this provider.
+    // If this fails, another auth mechanism has already claimed the session.
+    let bridge_data = SamlBridgeData {
+        link: link.clone(),
+        entity_id: metadata.entity_id,
+        request_id,
+    };
+    if !ctx.save_session(BridgeData::Saml(bridge_data)).await? {
+        return Err(BrokerError::ProviderCancelled);
+    }
+
+    match (metadata.sso_redirect, metadata.sso_post) {
+        (Some(mut url), _) => {
+            let deflated = miniz_oxide::deflate::compress_to_vec(request.as_bytes(), 6);
+            url.query_pairs_mut()
+                .append_pair("SAMLRequest", &BASE64_STANDARD.encode(deflated))
+                .append_pair("RelayState", &ctx.session_id);
+            if ctx.want_json {
+                Ok(json_response(&json!({
+                    "result": "redirect_to_provider",
+                    "url": url.as_str(),
+                })))
+            } else {
+                let mut res = empty_response(StatusCode::SEE_OTHER);
+                res.header(hyper::header::LOCATION, url.as_str());
+                Ok(res)
+            }
+        }
+        (None, Some(url)) => {
+            let request = BASE64_STANDARD.encode(request);
+            if ctx.want_json {
+                Ok(json_response(&json!({
+                    "result": "post_to_provider",
+                    "url": url.as_str(),
+                    "params": {
+                        "SAMLRequest": request,
+                        "RelayState": ctx.session_id,
+                    },
+                })))
+            } else {
+                Ok(form_post_response(
+                    ctx,
+                    url.as_str(),
+                    &[("SAMLRequest", &request), ("RelayState", &ctx.session_id)],
+                ))
+            }
+        }
+        (None, None) => unreachable!("metadata without a single sign-on service"),
+    }
+}
+
+/// Request handler for our SAML metadata.
+///
+/// Identity providers need this to register us as a service provider. Our entity ID is the URL of
+/// this document.
+pub async fn metadata(ctx: &mut Context) -> HandlerResult {
+    let mut xml = String::from(
+        r#"<md:EntityDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata" entityID=""#,
+    );
+    escape_attribute(&mut xml, &sp_entity_id(&ctx.app));
+    xml.push_str(r#""><md:SPSSODescriptor protocolSupportEnumeration="urn:oasis:names:tc:SAML:2.0:protocol"><md:NameIDFormat>"#);
+    xml.push_str(NAMEID_EMAIL);
+    xml.push_str(r#"</md:NameIDFormat><md:AssertionConsumerService Binding=""#);
+    xml.push_str(BINDING_POST);
+    xml.push_str(r#"" Location=""#);
+    escape_attribute(&mut xml, &acs_url(&ctx.app));
+    xml.push_str(r#"" index="0" isDefault="true"/></md:SPSSODescriptor></md:EntityDescriptor>"#);
+
+    let mut res = Response::new(Body::from(xml));
+    res.header(hyper::header::CONTENT_TYPE, "application/samlmetadata+xml");
+    Ok(res)
+}
+
+/// Request handler for SAML responses posted to our assertion consumer service.
+///
+/// Verify the response against the metadata of the identity provider, then match the email address
+/// it asserts against our session data. Return an identity token for the relying party if
+/// successful, or an error message otherwise.
+pub async fn callback(ctx: &mut Context) -> HandlerResult {
+    let mut params = ctx.form_params();
+    let session_id = try_get_provider_param!(params, "RelayState");
+    let response = try_get_provider_param!(params, "SAMLResponse");
+    let BridgeData::Saml(bridge_data) = ctx.load_session(&session_id).await? else {
+        return Err(BrokerError::ProviderInput("invalid session".to_owned()));
+    };
+
+    let metadata = fetch_metadata(&ctx.app, &bridge_data.link).await?;
+    if metadata.entity_id != bridge_data.entity_id {
+        return Err(BrokerError::Provider(format!(
+            "the entity ID of {} has changed",
+            bridge_data.link.href
+        )));
+    }
+
+    let response = decode_base64_text(&response)
+        .and_then(|response| String::from_utf8(response).ok())
+        .ok_or_else(|| BrokerError::ProviderInput("invalid SAMLResponse encoding".to_owned()))?;
+    let doc = Document::parse(&response)
+        .map_err(|e| BrokerError::ProviderInput(format!("could not parse SAMLResponse: {e}")))?;
+    let email = verify_response(
+        &doc,
+        &metadata,
+        &bridge_data,
+        &ResponseContext {
+            sp_entity_id: &sp_entity_id(&ctx.app),
+            acs_url: &acs_url(&ctx.app),
+            now: unix_timestamp(),
+        },
+    )?;
+
+    // Compare the normalized form, because the provider may return the original case.
+    let data = ctx.session_data.as_ref().expect("session vanished");
+    let email_addr: EmailAddress = email.parse().map_err(|err| {
+        BrokerError::ProviderInput(format!("failed to parse email in SAML response: {err}"))
+    })?;
+    if email_addr != data.email_addr {
+        return Err(BrokerError::ProviderInput(
+            "the SAML response is for another email address".to_owned(),
+        ));
+    }
+
+    // Everything is okay. Build a new identity token and send it to the relying party.
+    metrics::AUTH_SAML_COMPLETED.inc();
+    complete_auth(ctx).await
+}
+
+/// What we expect of a response, besides the session data.
+struct ResponseContext<'a> {
+    sp_entity_id: &'a str,
+    acs_url: &'a str,
+    now: u64,
+}
+
+/// Verify a SAML response, and extract the email address from its assertion.
+///
+/// The response must contain exactly one assertion, and either the response or the assertion must
+/// be signed. Signatures are verified on the exact elements we read from, so that signed content
+/// cannot be wrapped in unsigned content.
+fn verify_response(
+    doc: &Document,
+    metadata: &IdpMetadata,
+    bridge_data: &SamlBridgeData,
+    expected: &ResponseContext,
+) -> Result<String, BrokerError> {
+    let invalid = |reason: &str| {
+        BrokerError::ProviderInput(format!(
+            "invalid SAML response from {}: {reason}",
+            metadata.entity_id
+        ))
+    };
+
+    let response = doc.root_element();
+    if !is_element(response, NS_PROTOCOL, "Response") {
+        return Err(invalid("not a Response"));
+    }
+    if response.attribute("InResponseTo") != Some(&bridge_data.request_id) {
+        return Err(invalid("InResponseTo does not match"));
+    }
+    if response
+        .attribute("Destination")
+        .is_some_and(|value| value != expected.acs_url)
+    {
+        return Err(invalid("Destination does not match"));
+    }
+    if let Some(issuer) = find_child(response, NS_ASSERTION, "Issuer") {
+        if element_text(issuer) != Some(&metadata.entity_id) {
+            return Err(invalid("Issuer does not match"));
+        }
+    }
+
+    // Handle errors.
+    let status = find_child(response, NS_PROTOCOL, "Status")
+        .and_then(|status| find_child(status, NS_PROTOCOL, "StatusCode"))
+        .ok_or_else(|| invalid("missing StatusCode"))?;
+    let status_value = status.attribute("Value").unwrap_or_default();
+    if status_value != STATUS_SUCCESS {
+        let sub_status = find_child(status, NS_PROTOCOL, "StatusCode")
+            .and_then(|status| status.attribute("Value"));
+        // We forward prompt=none as IsPassive, and expect this error.
+        if sub_status == Some(STATUS_NO_PASSIVE) {
+            return Err(BrokerError::SpecificInput {
+                error: "interaction_required".to_owned(),
+                error_description: "the identity provider requires interaction".to_owned(),
+            });
+        }
+        return Err(BrokerError::Provider(format!(
+            "provider returned status: {}",
+            sub_status.unwrap_or(status_value)
+        )));
+    }
+
+    if find_child(response, NS_ASSERTION, "EncryptedAssertion").is_some() {
+        return Err(invalid("encrypted assertions are not supported"));
+    }
+    let mut assertions = children(response, NS_ASSERTION, "Assertion");
+    let (Some(assertion), None) = (assertions.next(), assertions.next()) else {
+        return Err(invalid("expected exactly one Assertion"));
+    };
+
+    // Signature references use IDs, so these must be unique in the document.
+    let mut ids = HashSet::new();
+    for node in doc.descendants() {
+        if let Some(id) = node.attribute("ID") {
+            if !ids.insert(id) {
+                return Err(invalid("duplicate ID"));
+            }
+        }
+    }
+
+    // Verify the signature.
+    let mut signed = [false; 2];
+    for (element, signed) in [response, assertion].into_iter().zip(&mut signed) {
+        match xmldsig::verify_enveloped(element, &metadata.keys) {
+            Ok(()) => *signed = true,
+            Err(XmlDsigError::NotSigned) => {}
+            Err(err) => return Err(invalid(&format!("invalid signature: {err}"))),
+        }
+    }
+    let [response_signed, assertion_signed] = signed;
+    if !response_signed && !assertion_signed {
+        return Err(invalid("the response is not signed"));
+    }
+
+    // Verify the assertion.
+    let issuer = find_child(assertion, NS_ASSERTION, "Issuer").and_then(element_text);
+    if issuer != Some(&metadata.entity_id) {
+        return Err(invalid("assertion Issuer does not match"));
+    }
+
+    let now = expected.now;
+    let not_expired = |node: Node| match node.attribute("NotOnOrAfter") {
+        None => true,
+        Some(value) => {
+            parse_xml_datetime(value).is_some_and(|time| now < time.saturating_add(LEEWAY))
+        }
+    };
+    let started = |node: Node| match node.attribute("NotBefore") {
+        None => true,
+        Some(value) => parse_xml_datetime(value).is_some_and(|time| time <= now + LEEWAY),
+    };
+
+    // If only the assertion is signed, the InResponseTo of the response is not covered by the
+    // signature. The assertion must then be bound to our request itself, so that a captured
+    // assertion cannot be replayed in a new response.
+    let subject =
+        find_child(assertion, NS_ASSERTION, "Subject").ok_or_else(|| invalid("missing Subject"))?;
+    let confirmed = children(subject, NS_ASSERTION, "SubjectConfirmation")
+        .filter(|node| node.attribute("Method") == Some(CONFIRMATION_BEARER))
+        .filter_map(|node| find_child(node, NS_ASSERTION, "SubjectConfirmationData"))
+        .any(|data| {
+            data.attribute("Recipient") == Some(expected.acs_url)
+                && match data.attribute("InResponseTo") {
+                    Some(value) => value == bridge_data.request_id,
+                    None => response_signed,
+                }
+                && data.attribute("NotOnOrAfter").is_some()
+                && not_expired(data)
+                && started(data)
+        });
+    if !confirmed {
+        return Err(invalid("no valid bearer SubjectConfirmation"));
+    }
+
+    let conditions = find_child(assertion, NS_ASSERTION, "Conditions")
+        .ok_or_else(|| invalid("missing Conditions"))?;
+    if !started(conditions) || !not_expired(conditions) {
+        return Err(invalid("the assertion is not valid at this time"));
+    }
+    // Bearer assertions must be restricted to us, and every restriction must include us.
+    let mut restrictions = children(conditions, NS_ASSERTION, "AudienceRestriction").peekable();
+    if restrictions.peek().is_none() {
+        return Err(invalid("missing AudienceRestriction"));
+    }
+    for restriction in restrictions {
+        if !children(restriction, NS_ASSERTION, "Audience")
+            .any(|audience| element_text(audience) == Some(expected.sp_entity_id))
+        {
+            return Err(invalid("Audience does not match"));
+        }
+    }
+
+    // Extract the email address. Elements containing anything but text are ignored, because
+    // comments are not covered by the signature.
+    let name_id = find_child(subject, NS_ASSERTION, "NameID")
+        .filter(|node| node.attribute("Format") == Some(NAMEID_EMAIL))
+        .and_then(element_text);
+    let attribute = || {
+        children(assertion, NS_ASSERTION, "AttributeStatement")
+            .flat_map(|statement| children(statement, NS_ASSERTION, "Attribute"))
+            .filter(|attribute| {
+                attribute
+                    .attribute("Name")
+                    .is_some_and(|name| EMAIL_ATTRIBUTES.contains(&name))
+            })
+            .flat_map(|attribute| children(attribute, NS_ASSERTION, "AttributeValue"))
+            .find_map(element_text)
+    };
+    name_id
+        .or_else(attribute)
+        .map(|email| email.trim().to_owned())
+        .ok_or_else(|| invalid("no email address found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_metadata, verify_response, IdpMetadata, ResponseContext, SamlBridgeData};
+    use crate::error::BrokerError;
+    use crate::utils::parse_xml_datetime;
+    use crate::utils::xmldsig::{exc_c14n, find_child, NS_DSIG};
+    use base64::prelude::*;
+    use ring::digest;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use roxmltree::Document;
+
+    const CERTIFICATE: &str = "MIIBjDCCATGgAwIBAgIUDVEmSUkTMhIEgeum7V0jJNoCSoAwCgYIKoZIzj0EAwIwGjEYMBYGA1UEAwwPaWRwLmV4YW1wbGUuY29tMCAXDTI2MTAxNjIwNDgwM1oYDzIxMjYwOTIyMjA0ODAzWjAaMRgwFgYDVQQDDA9pZHAuZXhhbXBsZS5jb20wWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAASd3SQkWioKZ1NWLIb0ncwPIt5j4CqVCudruX4vV3QG0HcKf10DKs7Jd8ysF3nZHNntEQN7OE2Q+oeo+ykK9tf8o1MwUTAdBgNVHQ4EFgQU7CJgjdunw522QmRIOsbr7uDW9bgwHwYDVR0jBBgwFoAU7CJgjdunw522QmRIOsbr7uDW9bgwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNJADBGAiEAxHJAiQttuOPY/cfLSAiDPxKGM/eYAsySNLukMQhYoS4CIQCthBGgG4kVzuJEZ30wpN6zs5r9GyZr5N86PA4mwUYdUQ==";
+
+    const SIGNATURE: &str = r##"<ds:Signature xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><ds:SignedInfo><ds:CanonicalizationMethod Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/><ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha256"/><ds:Reference URI="#_a1"><ds:Transforms><ds:Transform Algorithm="http://www.w3.org/2000/09/xmldsig#enveloped-signature"/><ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/></ds:Transforms><ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/><ds:DigestValue>DIGEST</ds:DigestValue></ds:Reference></ds:SignedInfo><ds:SignatureValue>SIGNATURE</ds:SignatureValue></ds:Signature>"##;
+
+    const RESPONSE: &str = r#"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion" ID="_r1" InResponseTo="_req" Destination="https://broker.example.com/saml/acs" Version="2.0" IssueInstant="2026-10-16T12:00:00Z">
+  <saml:Issuer>https://idp.example.com</saml:Issuer>
+  <samlp:Status><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/></samlp:Status>
+  <saml:Assertion ID="_a1" Version="2.0" IssueInstant="2026-10-16T12:00:00Z">
+    <saml:Issuer>https://idp.example.com</saml:Issuer>
+    SIGNATURE_ELEMENT
+    <saml:Subject>
+      <saml:NameID Format="urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress">John.Doe@example.com</saml:NameID>
+      <saml:SubjectConfirmation Method="urn:oasis:names:tc:SAML:2.0:cm:bearer">
+        <saml:SubjectConfirmationData InResponseTo="_req" Recipient="https://broker.example.com/saml/acs" NotOnOrAfter="2026-10-16T12:05:00Z"/>
+      </saml:SubjectConfirmation>
+    </saml:Subject>
+    <saml:Conditions NotBefore="2026-10-16T11:59:00Z" NotOnOrAfter="2026-10-16T12:05:00Z">
+      <saml:AudienceRestriction><saml:Audience>https://broker.example.com/saml/metadata</saml:Audience></saml:AudienceRestriction>
+    </saml:Conditions>
+  </saml:Assertion>
+</samlp:Response>"#;
+
+    /// An RSA certificate, and a response with an assertion signed using it.
+    ///
+    /// The signature was made with `xmllint --exc-c14n` and `openssl dgst -sha256 -sign`, instead
+    /// of our own canonicalization, so that a bug in it cannot hide on both sides.
+    const RSA_CERTIFICATE: &str = "MIIDFzCCAf+gAwIBAgIUfGAiWDHXPlZ4bnWVJfiNrhV1qEcwDQYJKoZIhvcNAQELBQAwGjEYMBYGA1UEAwwPaWRwLmV4YW1wbGUuY29tMCAXDTI2MTAxNjIzMzMzOFoYDzIxMjYwOTIyMjMzMzM4WjAaMRgwFgYDVQQDDA9pZHAuZXhhbXBsZS5jb20wggEiMA0GCSqGSIb3DQEBAQUAA4IBDwAwggEKAoIBAQCOJ3RToNzd6rG/WlH3HdHf8RFj5V/XmNnqjUYHTJN20Lbgv/hW7WFbbCafGsZiKl+Zky4fnsTseP2pNW+M5Mqsr5tLpNH6kempC9LPHvYSTF0GVWPw49sQ5amB2NzXvQFybukBfAmneASdcY+8UUyL25bgwtYFoqjTspczmc4vS6CcN2XxTxOknBDIfqF+RdB4ShpL7RlxUv5F1qwmhR6jEvrJicDO7uJlBd7Epy6XnPXqYfH7iC/4VbEptNyD5sEdl4aWGBLbu9py1Ra0MwZTqjylTWl6qb9DPV9HySnuEaFfshSfkSiTImiR/1/dfxDmk7CiI76WAFDXeRwMnSavAgMBAAGjUzBRMB0GA1UdDgQWBBQWjGuA6713TsCFx9sXkwytNujG3zAfBgNVHSMEGDAWgBQWjGuA6713TsCFx9sXkwytNujG3zAPBgNVHRMBAf8EBTADAQH/MA0GCSqGSIb3DQEBCwUAA4IBAQATgZROJejdRBBDil58y7O6mHMttujeXrPJzfCshEWSclU6ik1e9wYBBgXQvBOGsAW62pi0W3Sp3TNwFwIVAWTZdIdxquVCapiDo6OmkTNB/maye+4miyj5tPzd8dioArHdMOvXN8JDAdvRchzKy5hjP3+AUyiSWkHbuW0pBRUkMS0k3r+uYfRxusN85Sd0k3dJCKxbaKzWlWR2q6NuSKbur1qUYm7dBwItpqIJv+dHknzYeTEuctisE9WFonxLALfUUSDWD/5Sh8lSMQZ9nIiDIRd5eFKXWMPy9y8QTwC5AU+zanV/Gw7X65jp9P7C95VQcgM+GFfVm8eYgbqB09UU";
+
+    const RSA_RESPONSE: &str = r##"<samlp:Response xmlns:samlp="urn:oasis:names:tc:SAML:2.0:protocol" xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion" ID="_5f1e2d3c4b5a69788796a5b4c3d2e1f0a9b8c7d6e5" Version="2.0" IssueInstant="2026-10-16T12:00:00Z" Destination="https://broker.example.com/saml/acs" InResponseTo="_req">
+  <saml:Issuer>https://idp.example.com</saml:Issuer>
+  <samlp:Status>
+    <samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/>
+  </samlp:Status>
+  <saml:Assertion xmlns:xs="http://www.w3.org/2001/XMLSchema" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion" Version="2.0" IssueInstant="2026-10-16T12:00:00Z" ID="_8e8dc5f69a98cc4c1ff3427e5ce34606fd672f91e6">
+    <saml:Issuer>https://idp.example.com</saml:Issuer>
+    <ds:Signature xmlns:ds="http://www.w3.org/2000/09/xmldsig#">
+      <ds:SignedInfo>
+        <ds:CanonicalizationMethod Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/>
+        <ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#rsa-sha256"/>
+        <ds:Reference URI="#_8e8dc5f69a98cc4c1ff3427e5ce34606fd672f91e6">
+          <ds:Transforms>
+            <ds:Transform Algorithm="http://www.w3.org/2000/09/xmldsig#enveloped-signature"/>
+            <ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/>
+          </ds:Transforms>
+          <ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/>
+          <ds:DigestValue>VJ0MOmw+oJrS/JsuroE4nyhkBE9zOVX/ayyZZs1+9C8=</ds:DigestValue>
+        </ds:Reference>
+      </ds:SignedInfo>
+      <ds:SignatureValue>cJdwur7uqjVVlif9ZvYE15fvAbXNQp1V3SvkyJ8B57GZX+xRIXO95uC3QqBc4QsTO5yQFLye/3KmRMieFFeSwhuL4NjpQJW40EUaw+yoSoakDd0Uo2oYQuKEAZ9mKpSOtwlNSrJiCB8+Q5gjVrsaYI9I/y8C8Lg6Yiw3asJ6Ge9ilElruVTzFAeZ8O0MQepzU8HvyqMTse9lmXWnROYpafzeEpaVI7AmQd7lntx5TyF763domJu/eocmOCseX/jTgB/KebbL/TWg158r0DcjwFwsTV8BXhe2kwt1eInkNswIpu+rKWYw3U4KlhU7JmajshlFK4RO2MbdPts+rFD02A==</ds:SignatureValue>
+    </ds:Signature>
+    <saml:Subject>
+      <saml:NameID SPNameQualifier="https://broker.example.com/saml/metadata" Format="urn:oasis:names:tc:SAML:2.0:nameid-format:transient">_ce3d2948b4cf20146dee0a0b3dd6f69b6cf86f62d7</saml:NameID>
+      <saml:SubjectConfirmation Method="urn:oasis:names:tc:SAML:2.0:cm:bearer">
+        <saml:SubjectConfirmationData NotOnOrAfter="2026-10-16T12:05:00Z" Recipient="https://broker.example.com/saml/acs" InResponseTo="_req"></saml:SubjectConfirmationData>
+      </saml:SubjectConfirmation>
+    </saml:Subject>
+    <saml:Conditions NotOnOrAfter="2026-10-16T12:05:00Z" NotBefore="2026-10-16T11:59:30Z">
+      <saml:AudienceRestriction>
+        <saml:Audience>https://broker.example.com/saml/metadata</saml:Audience>
+      </saml:AudienceRestriction>
+    </saml:Conditions>
+    <saml:AuthnStatement SessionIndex="_be9967abd904ddcae3c0eb4189adbe3f71e327cf93" AuthnInstant="2026-10-16T12:00:00Z">
+      <saml:AuthnContext>
+        <saml:AuthnContextClassRef>urn:oasis:names:tc:SAML:2.0:ac:classes:Password</saml:AuthnContextClassRef>
+      </saml:AuthnContext>
+    </saml:AuthnStatement>
+    <saml:AttributeStatement>
+      <saml:Attribute Name="mail" NameFormat="urn:oasis:names:tc:SAML:2.0:attrname-format:basic">
+        <saml:AttributeValue xsi:type="xs:string">jane.doe@example.com</saml:AttributeValue>
+      </saml:Attribute>
+      <saml:Attribute Name="cn" NameFormat="urn:oasis:names:tc:SAML:2.0:attrname-format:basic">
+        <saml:AttributeValue xsi:type="xs:string">Jane &amp; &quot;J&quot; Doe &gt; &#x9;</saml:AttributeValue>
+      </saml:Attribute>
+    </saml:AttributeStatement>
+  </saml:Assertion>
+</samlp:Response>"##;
+
+    /// Sign the assertion in a response, like an identity provider would.
+    fn sign(key_pair: &EcdsaKeyPair, input: &str) -> String {
+        let input = input.replace("SIGNATURE_ELEMENT", SIGNATURE);
+        let doc = Document::parse(&input).unwrap();
+        let assertion = doc
+            .descendants()
+            .find(|n| n.has_tag_name("Assertion"))
+            .unwrap();
+        let signature = find_child(assertion, NS_DSIG, "Signature").unwrap();
+        let digest = digest::digest(
+            &digest::SHA256,
+            exc_c14n(assertion, Some(signature), &[]).as_bytes(),
+        );
+        let input = input.replace("DIGEST", &BASE64_STANDARD.encode(digest));
+
+        let doc = Document::parse(&input).unwrap();
+        let signed_info = doc
+            .descendants()
+            .find(|n| n.has_tag_name((NS_DSIG, "SignedInfo")))
+            .unwrap();
+        let message = exc_c14n(signed_info, None, &[]);
+        let signature = key_pair
+            .sign(&SystemRandom::new(), message.as_bytes())
+            .unwrap();
+        input.replace("SIGNATURE", &BASE64_STANDARD.encode(signature))
+    }
+
+    struct Fixture {
+        key_pair: EcdsaKeyPair,
+        metadata: IdpMetadata,
+        bridge_data: SamlBridgeData,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+            let key_pair =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                    .unwrap();
+            let metadata = IdpMetadata {
+                entity_id: "https://idp.example.com".to_owned(),
+                sso_redirect: None,
+                sso_post: None,
+                keys: vec![key_pair.public_key().as_ref().to_vec()],
+            };
+            let bridge_data = SamlBridgeData {
+                link: serde_json::from_str(
+                    r#"{"rel":"https://portier.io/specs/auth/1.0/idp/saml","href":"https://idp.example.com/metadata"}"#,
+                )
+                .unwrap(),
+                entity_id: "https://idp.example.com".to_owned(),
+                request_id: "_req".to_owned(),
+            };
+            Fixture {
+                key_pair,
+                metadata,
+                bridge_data,
+            }
+        }
+
+        fn verify_at(&self, input: &str, now: &str) -> Result<String, BrokerError> {
+            let doc = Document::parse(input).unwrap();
+            verify_response(
+                &doc,
+                &self.metadata,
+                &self.bridge_data,
+                &ResponseContext {
+                    sp_entity_id: "https://broker.example.com/saml/metadata",
+                    acs_url: "https://broker.example.com/saml/acs",
+                    now: parse_xml_datetime(now).unwrap(),
+                },
+            )
+        }
+
+        fn verify(&self, input: &str) -> Result<String, BrokerError> {
+            self.verify_at(input, "2026-10-16T12:00:00Z")
+        }
+    }
+
+    #[test]
+    fn test_verify_response() {
+        let fixture = Fixture::new();
+        let signed = sign(&fixture.key_pair, RESPONSE);
+        assert_eq!(fixture.verify(&signed).unwrap(), "John.Doe@example.com");
+
+        // Email as an attribute, instead of the NameID.
+        let input = RESPONSE
+            .replace(
+                r#"<saml:NameID Format="urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress">John.Doe@example.com</saml:NameID>"#,
+                r#"<saml:NameID Format="urn:oasis:names:tc:SAML:2.0:nameid-format:persistent">1234</saml:NameID>"#,
+            )
+            .replace(
+                "</saml:Conditions>",
+                r#"</saml:Conditions><saml:AttributeStatement><saml:Attribute Name="mail"><saml:AttributeValue>john@example.com</saml:AttributeValue></saml:Attribute></saml:AttributeStatement>"#,
+            );
+        let signed = sign(&fixture.key_pair, &input);
+        assert_eq!(fixture.verify(&signed).unwrap(), "john@example.com");
+    }
+
+    #[test]
+    fn test_verify_response_errors() {
+        let fixture = Fixture::new();
+        let signed = sign(&fixture.key_pair, RESPONSE);
+        let is_invalid = |res| matches!(res, Err(BrokerError::ProviderInput(_)));
+
+        assert!(is_invalid(
+            fixture.verify_at(&signed, "2026-10-16T12:10:00Z")
+        ));
+        assert!(is_invalid(
+            fixture.verify_at(&signed, "2026-10-16T11:50:00Z")
+        ));
+        assert!(is_invalid(
+            fixture.verify(&RESPONSE.replace("SIGNATURE_ELEMENT", ""))
+        ));
+        assert!(is_invalid(fixture.verify(
+            &signed.replace("John.Doe@example.com", "evil@example.com")
+        )));
+        assert!(is_invalid(fixture.verify(
+            &signed.replace(r#"InResponseTo="_req""#, r#"InResponseTo="_other""#)
+        )));
+        assert!(is_invalid(fixture.verify(&signed.replace(
+            "https://broker.example.com/saml/metadata",
+            "https://other.example.com/saml/metadata"
+        ))));
+
+        // Comments are not signed, so must not truncate the email address. (CVE-2017-11427)
+        let commented = sign(
+            &fixture.key_pair,
+            &RESPONSE.replace(
+                "John.Doe@example.com</saml:NameID>",
+                "John.Doe@example.com<!---->.evil.com</saml:NameID>",
+            ),
+        );
+        assert!(is_invalid(fixture.verify(&commented)));
+        let commented = sign(
+            &fixture.key_pair,
+            &RESPONSE.replace(
+                "<saml:Issuer>https://idp.example.com</saml:Issuer>\n    SIGNATURE_ELEMENT",
+                "<saml:Issuer>https://idp.example.com<!---->.evil.com</saml:Issuer>\n    SIGNATURE_ELEMENT",
+            ),
+        );
+        assert!(is_invalid(fixture.verify(&commented)));
+
+        // A signed assertion without InResponseTo, wrapped in a new response for another session.
+        let unbound = sign(
+            &fixture.key_pair,
+            &RESPONSE.replace(
+                r#"<saml:SubjectConfirmationData InResponseTo="_req" "#,
+                "<saml:SubjectConfirmationData ",
+            ),
+        );
+        assert!(is_invalid(fixture.verify(&unbound)));
+
+        // Bearer assertions must carry an AudienceRestriction.
+        let unrestricted = sign(
+            &fixture.key_pair,
+            &RESPONSE.replace(
+                "<saml:AudienceRestriction><saml:Audience>https://broker.example.com/saml/metadata</saml:Audience></saml:AudienceRestriction>",
+                "",
+            ),
+        );
+        assert!(is_invalid(fixture.verify(&unrestricted)));
+
+        // Signature wrapping: an unsigned assertion next to the signed one.
+        let wrapped = signed.replace(
+            "</samlp:Response>",
+            r#"<saml:Assertion ID="_a2"><saml:Issuer>https://idp.example.com</saml:Issuer></saml:Assertion></samlp:Response>"#,
+        );
+        assert!(is_invalid(fixture.verify(&wrapped)));
+
+        let no_passive = RESPONSE.replace(
+            r#"<samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/>"#,
+            r#"<samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Responder"><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:NoPassive"/></samlp:StatusCode>"#,
+        );
+        assert!(matches!(
+            fixture.verify(&no_passive),
+            Err(BrokerError::SpecificInput { error, .. }) if error == "interaction_required"
+        ));
+    }
+
+    #[test]
+    fn test_verify_independent_signature() {
+        let metadata = parse_metadata(&format!(
+            r#"<md:EntityDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata" xmlns:ds="http://www.w3.org/2000/09/xmldsig#" entityID="https://idp.example.com">
+              <md:IDPSSODescriptor protocolSupportEnumeration="urn:oasis:names:tc:SAML:2.0:protocol">
+                <md:KeyDescriptor use="signing"><ds:KeyInfo><ds:X509Data><ds:X509Certificate>{RSA_CERTIFICATE}</ds:X509Certificate></ds:X509Data></ds:KeyInfo></md:KeyDescriptor>
+                <md:SingleSignOnService Binding="urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST" Location="https://idp.example.com/sso"/>
+              </md:IDPSSODescriptor>
+            </md:EntityDescriptor>"#
+        ))
+        .unwrap();
+        let fixture = Fixture {
+            metadata,
+            ..Fixture::new()
+        };
+        assert_eq!(
+            fixture.verify(RSA_RESPONSE).unwrap(),
+            "jane.doe@example.com"
+        );
+
+        // Changes to signed content, including escaped characters, break the digest.
+        let tampered = RSA_RESPONSE.replace("&quot;J&quot;", "&quot;Jo&quot;");
+        assert!(matches!(
+            fixture.verify(&tampered),
+            Err(BrokerError::ProviderInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let metadata = parse_metadata(&format!(
+            r#"<md:EntitiesDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata" xmlns:ds="http://www.w3.org/2000/09/xmldsig#">
+              <md:EntityDescriptor entityID="https://sp.example.com">
+                <md:SPSSODescriptor protocolSupportEnumeration="urn:oasis:names:tc:SAML:2.0:protocol"/>
+              </md:EntityDescriptor>
+              <md:EntityDescriptor entityID="https://idp.example.com">
+                <md:IDPSSODescriptor protocolSupportEnumeration="urn:oasis:names:tc:SAML:2.0:protocol">
+                  <md:KeyDescriptor use="encryption"><ds:KeyInfo><ds:X509Data><ds:X509Certificate>invalid</ds:X509Certificate></ds:X509Data></ds:KeyInfo></md:KeyDescriptor>
+                  <md:KeyDescriptor use="signing"><ds:KeyInfo><ds:X509Data><ds:X509Certificate>{CERTIFICATE}</ds:X509Certificate></ds:X509Data></ds:KeyInfo></md:KeyDescriptor>
+                  <md:SingleSignOnService Binding="urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST" Location="https://idp.example.com/sso"/>
+                </md:IDPSSODescriptor>
+              </md:EntityDescriptor>
+            </md:EntitiesDescriptor>"#
+        ))
+        .unwrap();
+        assert_eq!(metadata.entity_id, "https://idp.example.com");
+        assert!(metadata.sso_redirect.is_none());
+        assert_eq!(
+            metadata.sso_post.unwrap().as_str(),
+            "https://idp.example.com/sso"
+        );
+        assert_eq!(metadata.keys.len(), 1);
+        assert_eq!(metadata.keys[0].len(), 65);
+    }
+}
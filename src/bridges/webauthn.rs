@@ -0,0 +1,717 @@
+use crate::agents::{GetWebauthnCredentials, SaveWebauthnCredential, WebauthnCredential};
+use crate::bridges::{complete_auth, email, BridgeData};
+use crate::crypto;
+use crate::email_address::EmailAddress;
+use crate::error::{BrokerError, BrokerResult};
+use crate::metrics;
+use crate::utils::{base64url, der, http::ResponseExt, unix_timestamp};
+use crate::web::{html_response, Context, HandlerResult, Response, CSP_SAME_ORIGIN};
+use ring::{digest, signature};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use url::Url;
+
+/// COSE algorithm identifiers of the keys we accept.
+const COSE_ES256: i64 = -7;
+const COSE_EDDSA: i64 = -8;
+const COSE_RS256: i64 = -257;
+
+/// Authenticator data flags.
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// The step of the passkey flow a session is in.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebauthnStage {
+    /// The user may authenticate using a registered passkey.
+    Login,
+    /// The user has confirmed their address by email, and may register a passkey.
+    Register,
+}
+
+/// Data we store in the session.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WebauthnBridgeData {
+    pub stage: WebauthnStage,
+    /// The challenge for the authenticator, base64url encoded.
+    pub challenge: String,
+}
+
+/// What we expect of the responses of an authenticator.
+struct Expected<'a> {
+    rp_id: &'a str,
+    origin: &'a str,
+    challenge: &'a str,
+}
+
+/// Client data collected by the browser.
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    type_: String,
+    challenge: String,
+    origin: String,
+    #[serde(default, rename = "crossOrigin")]
+    cross_origin: bool,
+}
+
+/// The parts of authenticator data we use.
+struct AuthenticatorData<'a> {
+    sign_count: u32,
+    credential_id: Option<&'a [u8]>,
+}
+
+/// Verify the client data of a response, for the given ceremony type.
+fn verify_client_data(input: &[u8], type_: &str, expected: &Expected) -> Result<(), String> {
+    let client_data: ClientData =
+        serde_json::from_slice(input).map_err(|e| format!("invalid client data: {e}"))?;
+    if client_data.type_ != type_ {
+        return Err("invalid client data type".to_owned());
+    }
+    if client_data.challenge != expected.challenge {
+        return Err("invalid challenge".to_owned());
+    }
+    if client_data.origin != expected.origin || client_data.cross_origin {
+        return Err("invalid origin".to_owned());
+    }
+    Ok(())
+}
+
+/// Parse and verify authenticator data.
+fn parse_authenticator_data<'a>(
+    input: &'a [u8],
+    expected: &Expected,
+) -> Result<AuthenticatorData<'a>, String> {
+    if input.len() < 37 {
+        return Err("authenticator data is too short".to_owned());
+    }
+    let rp_id_hash = digest::digest(&digest::SHA256, expected.rp_id.as_bytes());
+    if &input[..32] != rp_id_hash.as_ref() {
+        return Err("invalid relying party ID".to_owned());
+    }
+    let flags = input[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        return Err("user was not present".to_owned());
+    }
+    // A passkey replaces the email loop, so must be a second factor by itself.
+    if flags & FLAG_USER_VERIFIED == 0 {
+        return Err("user was not verified".to_owned());
+    }
+    let sign_count = u32::from_be_bytes(input[33..37].try_into().unwrap());
+
+    // Attested credential data starts with a 16 byte AAGUID and the length of the ID.
+    let credential_id = if flags & FLAG_ATTESTED_CREDENTIAL == 0 {
+        None
+    } else {
+        let len = input
+            .get(53..55)
+            .map(|len| usize::from(u16::from_be_bytes([len[0], len[1]])))
+            .ok_or("attested credential data is too short")?;
+        Some(
+            input
+                .get(55..55 + len)
+                .ok_or("attested credential data is too short")?,
+        )
+    };
+
+    Ok(AuthenticatorData {
+        sign_count,
+        credential_id,
+    })
+}
+
+/// Verify a registration response, returning the new credential.
+///
+/// We request no attestation, so only the client data and authenticator data are checked. The
+/// browser extracts the public key for us, as a `SubjectPublicKeyInfo`.
+fn verify_registration(
+    id: &str,
+    client_data: &[u8],
+    authenticator_data: &[u8],
+    public_key: &[u8],
+    alg: i64,
+    expected: &Expected,
+) -> Result<WebauthnCredential, String> {
+    verify_client_data(client_data, "webauthn.create", expected)?;
+    let authenticator_data = parse_authenticator_data(authenticator_data, expected)?;
+    let credential_id = authenticator_data
+        .credential_id
+        .ok_or("missing attested credential data")?;
+    if base64url::encode(credential_id) != id {
+        return Err("credential ID does not match".to_owned());
+    }
+    if ![COSE_ES256, COSE_EDDSA, COSE_RS256].contains(&alg) {
+        return Err(format!("unsupported algorithm: {alg}"));
+    }
+    let public_key = der::spki_public_key(public_key).ok_or("invalid public key")?;
+    Ok(WebauthnCredential {
+        id: id.to_owned(),
+        alg,
+        public_key: base64url::encode(&public_key),
+        sign_count: authenticator_data.sign_count,
+        created: unix_timestamp(),
+    })
+}
+
+/// Verify an authentication response, returning the new signature counter.
+fn verify_assertion(
+    credential: &WebauthnCredential,
+    client_data: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+    expected: &Expected,
+) -> Result<u32, String> {
+    verify_client_data(client_data, "webauthn.get", expected)?;
+    let sign_count = parse_authenticator_data(authenticator_data, expected)?.sign_count;
+
+    let alg: &dyn signature::VerificationAlgorithm = match credential.alg {
+        COSE_ES256 => &signature::ECDSA_P256_SHA256_ASN1,
+        COSE_EDDSA => &signature::ED25519,
+        COSE_RS256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+        alg => return Err(format!("unsupported algorithm: {alg}")),
+    };
+    let public_key =
+        base64url::decode(&credential.public_key).map_err(|_err| "invalid stored public key")?;
+    let mut message = authenticator_data.to_vec();
+    message.extend_from_slice(digest::digest(&digest::SHA256, client_data).as_ref());
+    signature::UnparsedPublicKey::new(alg, public_key)
+        .verify(&message, signature)
+        .map_err(|_err| "invalid signature")?;
+
+    // Authenticators that don't count signatures always report zero. Otherwise, the counter must
+    // increase, or the credential may have been cloned.
+    if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+        return Err("signature counter did not increase".to_owned());
+    }
+    Ok(sign_count)
+}
+
+/// The relying party ID, which is the host of our public URL.
+fn rp_id(ctx: &Context) -> String {
+    Url::parse(&ctx.app.public_url)
+        .ok()
+        .and_then(|url| url.host_str().map(ToOwned::to_owned))
+        .expect("public_url must have a host")
+}
+
+/// Our origin, as the browser reports it in client data.
+fn origin(ctx: &Context) -> String {
+    Url::parse(&ctx.app.public_url)
+        .expect("public_url must be a URL")
+        .origin()
+        .ascii_serialization()
+}
+
+/// Fetch the credentials registered for an email address.
+async fn credentials(
+    ctx: &Context,
+    email_addr: &EmailAddress,
+) -> BrokerResult<Vec<WebauthnCredential>> {
+    ctx.app
+        .store
+        .send(GetWebauthnCredentials {
+            email_addr: email_addr.clone(),
+        })
+        .await
+        .map_err(|e| BrokerError::Internal(format!("could not load passkeys: {e}")))
+}
+
+/// Render the passkey page for a stage.
+fn render_page(
+    ctx: &Context,
+    stage: WebauthnStage,
+    challenge: &str,
+    credentials: &[WebauthnCredential],
+) -> Response {
+    let data = ctx.session_data.as_ref().expect("session vanished");
+    let display_origin = data
+        .return_params
+        .redirect_uri
+        .origin()
+        .unicode_serialization();
+    let user_id = base64url::encode(&digest::digest(
+        &digest::SHA256,
+        data.email_addr.as_str().as_bytes(),
+    ));
+    let credential_ids = credentials
+        .iter()
+        .map(|credential| credential.id.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let rp_id = rp_id(ctx);
+
+    let catalog = ctx.catalog();
+    let (stage, action, alternate_action) = match stage {
+        WebauthnStage::Login => ("login", "/webauthn/login", "/webauthn/email"),
+        WebauthnStage::Register => ("register", "/webauthn/register", "/webauthn/register"),
+    };
+    let texts = if stage == "login" {
+        [
+            ("title", catalog.gettext("Login with a passkey")),
+            (
+                "explanation",
+                catalog.gettext("Use your passkey to login to"),
+            ),
+            ("button", catalog.gettext("Use passkey")),
+            ("alternate", catalog.gettext("Send me an email instead")),
+        ]
+    } else {
+        [
+            ("title", catalog.gettext("Create a passkey")),
+            ("explanation", catalog.gettext("Your address is confirmed. Create a passkey to login faster next time, without waiting for an email.")),
+            ("button", catalog.gettext("Create passkey")),
+            ("alternate", catalog.gettext("Not now")),
+        ]
+    };
+    let mut params = vec![
+        ("display_origin", display_origin.as_str()),
+        ("session_id", &ctx.session_id),
+        ("stage", stage),
+        ("action", action),
+        ("alternate_action", alternate_action),
+        ("challenge", challenge),
+        ("rp_id", &rp_id),
+        ("user_id", &user_id),
+        ("user_name", data.email_addr.as_str()),
+        ("credentials", &credential_ids),
+    ];
+    params.extend(texts);
+
+    // WebAuthn does not work in a sandbox without our origin.
+    let mut res = html_response(ctx.app.templates.webauthn.render(&params));
+    res.header(hyper::header::CONTENT_SECURITY_POLICY, CSP_SAME_ORIGIN);
+    res
+}
+
+/// Save the session for a stage, and render the passkey page.
+async fn start_stage(
+    ctx: &mut Context,
+    stage: WebauthnStage,
+    credentials: &[WebauthnCredential],
+) -> HandlerResult {
+    let challenge = crypto::nonce(&ctx.app.rng).await;
+    // Render before saving, because saving consumes the session data.
+    let res = render_page(ctx, stage, &challenge, credentials);
+    if !ctx
+        .save_session(BridgeData::Webauthn(WebauthnBridgeData {
+            stage,
+            challenge,
+        }))
+        .await?
+    {
+        return Err(BrokerError::Internal(
+            "passkey login failed to claim session".to_owned(),
+        ));
+    }
+    Ok(res)
+}
+
+/// Provide authentication using a passkey registered for the email address.
+///
+/// This takes the place of the email loop, so only happens after other options have failed. The
+/// page also offers the email loop as a fallback, in case the passkey is not available.
+///
+/// The page is the same whether or not the address has passkeys, so it cannot be used to find out.
+/// Passkeys are discoverable, so the browser finds them without us listing them.
+pub async fn auth(ctx: &mut Context) -> HandlerResult {
+    metrics::AUTH_WEBAUTHN_REQUESTS.inc();
+    start_stage(ctx, WebauthnStage::Login, &[]).await
+}
+
+/// Offer to register a passkey, after the user confirmed their address using the email loop.
+///
+/// The session must be loaded. Passkeys already registered for the address are excluded, so the
+/// browser does not register the same authenticator twice.
+pub async fn offer_registration(ctx: &mut Context) -> HandlerResult {
+    let email_addr = ctx
+        .session_data
+        .as_ref()
+        .expect("offer_registration called without a session")
+        .email_addr
+        .clone();
+    let credentials = credentials(ctx, &email_addr).await?;
+    start_stage(ctx, WebauthnStage::Register, &credentials).await
+}
+
+/// Load a session in the given stage.
+async fn load_session(
+    ctx: &mut Context,
+    params: &mut HashMap<String, String>,
+    stage: WebauthnStage,
+) -> BrokerResult<WebauthnBridgeData> {
+    let session_id = try_get_provider_param!(params, "session");
+    match ctx.load_session(&session_id).await? {
+        BridgeData::Webauthn(bridge_data) if bridge_data.stage == stage => Ok(bridge_data),
+        _ => Err(BrokerError::ProviderInput("invalid session".to_owned())),
+    }
+}
+
+/// Decode a base64url request parameter.
+fn decode_param(params: &mut HashMap<String, String>, name: &'static str) -> BrokerResult<Vec<u8>> {
+    let value = params
+        .remove(name)
+        .ok_or_else(|| BrokerError::ProviderInput(format!("missing request parameter {name}")))?;
+    base64url::decode(&value)
+        .map_err(|_err| BrokerError::ProviderInput(format!("invalid request parameter {name}")))
+}
+
+/// Request handler for passkey authentication responses.
+///
+/// Verify the response against the credential registered for the email address in the session,
+/// then return an identity token to the relying party.
+pub async fn login(ctx: &mut Context) -> HandlerResult {
+    let mut params = ctx.form_params();
+    let bridge_data = load_session(ctx, &mut params, WebauthnStage::Login).await?;
+    let id = try_get_provider_param!(params, "id");
+    let client_data = decode_param(&mut params, "client_data")?;
+    let authenticator_data = decode_param(&mut params, "authenticator_data")?;
+    let signature = decode_param(&mut params, "signature")?;
+
+    let email_addr = ctx
+        .session_data
+        .as_ref()
+        .expect("session vanished")
+        .email_addr
+        .clone();
+    let mut credential = credentials(ctx, &email_addr)
+        .await?
+        .into_iter()
+        .find(|credential| credential.id == id)
+        .ok_or_else(|| BrokerError::ProviderInput("unknown passkey".to_owned()))?;
+
+    let (rp_id, origin) = (rp_id(ctx), origin(ctx));
+    let expected = Expected {
+        rp_id: &rp_id,
+        origin: &origin,
+        challenge: &bridge_data.challenge,
+    };
+    credential.sign_count = verify_assertion(
+        &credential,
+        &client_data,
+        &authenticator_data,
+        &signature,
+        &expected,
+    )
+    .map_err(|e| BrokerError::ProviderInput(format!("could not verify the passkey: {e}")))?;
+
+    ctx.app
+        .store
+        .send(SaveWebauthnCredential {
+            email_addr,
+            credential,
+        })
+        .await
+        .map_err(|e| BrokerError::Internal(format!("could not save a passkey: {e}")))?;
+
+    metrics::AUTH_WEBAUTHN_COMPLETED.inc();
+    complete_auth(ctx).await
+}
+
+/// Request handler for passkey registration responses.
+///
+/// The user already confirmed their address, so registration is optional. If the user skips it,
+/// or the response is invalid, the error is logged and login continues as usual.
+pub async fn register(ctx: &mut Context) -> HandlerResult {
+    let mut params = ctx.form_params();
+    let bridge_data = load_session(ctx, &mut params, WebauthnStage::Register).await?;
+
+    if !params.contains_key("skip") {
+        if let Err(e) = save_registration(ctx, &mut params, &bridge_data).await {
+            e.log(None).await;
+        }
+    }
+
+    complete_auth(ctx).await
+}
+
+/// Verify and save a passkey registration.
+async fn save_registration(
+    ctx: &Context,
+    params: &mut HashMap<String, String>,
+    bridge_data: &WebauthnBridgeData,
+) -> BrokerResult<()> {
+    let id = try_get_provider_param!(params, "id");
+    let client_data = decode_param(params, "client_data")?;
+    let authenticator_data = decode_param(params, "authenticator_data")?;
+    let public_key = decode_param(params, "public_key")?;
+    let alg = try_get_provider_param!(params, "alg")
+        .parse()
+        .map_err(|_err| BrokerError::ProviderInput("invalid request parameter alg".to_owned()))?;
+
+    let (rp_id, origin) = (rp_id(ctx), origin(ctx));
+    let expected = Expected {
+        rp_id: &rp_id,
+        origin: &origin,
+        challenge: &bridge_data.challenge,
+    };
+    let credential = verify_registration(
+        &id,
+        &client_data,
+        &authenticator_data,
+        &public_key,
+        alg,
+        &expected,
+    )
+    .map_err(|e| BrokerError::ProviderInput(format!("could not register the passkey: {e}")))?;
+
+    let email_addr = ctx
+        .session_data
+        .as_ref()
+        .expect("session vanished")
+        .email_addr
+        .clone();
+    ctx.app
+        .store
+        .send(SaveWebauthnCredential {
+            email_addr,
+            credential,
+        })
+        .await
+        .map_err(|e| BrokerError::Internal(format!("could not save a passkey: {e}")))?;
+
+    metrics::AUTH_WEBAUTHN_REGISTERED.inc();
+    Ok(())
+}
+
+/// Request handler to fall back to the email loop, if the passkey is not available.
+pub async fn email(ctx: &mut Context) -> HandlerResult {
+    let mut params = ctx.form_params();
+    load_session(ctx, &mut params, WebauthnStage::Login).await?;
+    let email_addr = ctx
+        .session_data
+        .as_ref()
+        .expect("session vanished")
+        .email_addr
+        .clone();
+    email::auth(ctx, email_addr).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_assertion, verify_registration, Expected, COSE_ES256};
+    use crate::agents::WebauthnCredential;
+    use crate::utils::base64url;
+    use ring::digest;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+    /// The DER prefix of a P-256 `SubjectPublicKeyInfo`, up to the public key point.
+    const P256_SPKI_PREFIX: [u8; 26] = [
+        0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08,
+        0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+    ];
+
+    const EXPECTED: Expected<'static> = Expected {
+        rp_id: "broker.example.com",
+        origin: "https://broker.example.com",
+        challenge: "Y2hhbGxlbmdl",
+    };
+
+    fn client_data(type_: &str, challenge: &str, origin: &str) -> Vec<u8> {
+        serde_json::json!({ "type": type_, "challenge": challenge, "origin": origin })
+            .to_string()
+            .into_bytes()
+    }
+
+    fn authenticator_data(rp_id: &str, flags: u8, sign_count: u32, attested: &[u8]) -> Vec<u8> {
+        let mut data = digest::digest(&digest::SHA256, rp_id.as_bytes())
+            .as_ref()
+            .to_vec();
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        data.extend_from_slice(attested);
+        data
+    }
+
+    fn key_pair() -> EcdsaKeyPair {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng).unwrap()
+    }
+
+    fn sign(key_pair: &EcdsaKeyPair, authenticator_data: &[u8], client_data: &[u8]) -> Vec<u8> {
+        let mut message = authenticator_data.to_vec();
+        message.extend_from_slice(digest::digest(&digest::SHA256, client_data).as_ref());
+        key_pair
+            .sign(&SystemRandom::new(), &message)
+            .unwrap()
+            .as_ref()
+            .to_vec()
+    }
+
+    fn credential(key_pair: &EcdsaKeyPair, sign_count: u32) -> WebauthnCredential {
+        WebauthnCredential {
+            id: "Y3JlZA".to_owned(),
+            alg: COSE_ES256,
+            public_key: base64url::encode(key_pair.public_key().as_ref()),
+            sign_count,
+            created: 0,
+        }
+    }
+
+    #[test]
+    fn test_verify_registration() {
+        let key_pair = key_pair();
+        let mut spki = P256_SPKI_PREFIX.to_vec();
+        spki.extend_from_slice(key_pair.public_key().as_ref());
+
+        // AAGUID, followed by the length of the credential ID and the ID itself.
+        let mut attested = vec![0; 16];
+        attested.extend_from_slice(&[0, 4]);
+        attested.extend_from_slice(b"cred");
+        let client_data = client_data("webauthn.create", EXPECTED.challenge, EXPECTED.origin);
+        let authenticator_data = authenticator_data(EXPECTED.rp_id, 0x45, 0, &attested);
+
+        let credential = verify_registration(
+            "Y3JlZA",
+            &client_data,
+            &authenticator_data,
+            &spki,
+            COSE_ES256,
+            &EXPECTED,
+        )
+        .unwrap();
+        assert_eq!(credential.id, "Y3JlZA");
+        assert_eq!(
+            credential.public_key,
+            base64url::encode(key_pair.public_key().as_ref())
+        );
+
+        let err = verify_registration(
+            "b3RoZXI",
+            &client_data,
+            &authenticator_data,
+            &spki,
+            COSE_ES256,
+            &EXPECTED,
+        )
+        .unwrap_err();
+        assert_eq!(err, "credential ID does not match");
+
+        let err = verify_registration(
+            "Y3JlZA",
+            &client_data,
+            &self::authenticator_data(EXPECTED.rp_id, 0x05, 0, &[]),
+            &spki,
+            COSE_ES256,
+            &EXPECTED,
+        )
+        .unwrap_err();
+        assert_eq!(err, "missing attested credential data");
+    }
+
+    #[test]
+    fn test_verify_assertion() {
+        let key_pair = key_pair();
+        let client_data = client_data("webauthn.get", EXPECTED.challenge, EXPECTED.origin);
+        let authenticator_data = authenticator_data(EXPECTED.rp_id, 0x05, 5, &[]);
+        let signature = sign(&key_pair, &authenticator_data, &client_data);
+
+        let sign_count = verify_assertion(
+            &credential(&key_pair, 4),
+            &client_data,
+            &authenticator_data,
+            &signature,
+            &EXPECTED,
+        )
+        .unwrap();
+        assert_eq!(sign_count, 5);
+
+        let err = verify_assertion(
+            &credential(&key_pair, 5),
+            &client_data,
+            &authenticator_data,
+            &signature,
+            &EXPECTED,
+        )
+        .unwrap_err();
+        assert_eq!(err, "signature counter did not increase");
+
+        let err = verify_assertion(
+            &credential(&self::key_pair(), 4),
+            &client_data,
+            &authenticator_data,
+            &signature,
+            &EXPECTED,
+        )
+        .unwrap_err();
+        assert_eq!(err, "invalid signature");
+
+        // Authenticators that don't count always report zero.
+        let authenticator_data = self::authenticator_data(EXPECTED.rp_id, 0x05, 0, &[]);
+        let signature = sign(&key_pair, &authenticator_data, &client_data);
+        verify_assertion(
+            &credential(&key_pair, 0),
+            &client_data,
+            &authenticator_data,
+            &signature,
+            &EXPECTED,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_verify_assertion_errors() {
+        let key_pair = key_pair();
+        let credential = credential(&key_pair, 0);
+        let check = |client_data: &[u8], authenticator_data: &[u8]| {
+            let signature = sign(&key_pair, authenticator_data, client_data);
+            verify_assertion(
+                &credential,
+                client_data,
+                authenticator_data,
+                &signature,
+                &EXPECTED,
+            )
+            .unwrap_err()
+        };
+        let good_authenticator_data = authenticator_data(EXPECTED.rp_id, 0x05, 0, &[]);
+
+        assert_eq!(
+            check(
+                &client_data("webauthn.create", EXPECTED.challenge, EXPECTED.origin),
+                &good_authenticator_data
+            ),
+            "invalid client data type"
+        );
+        assert_eq!(
+            check(
+                &client_data("webauthn.get", "b3RoZXI", EXPECTED.origin),
+                &good_authenticator_data
+            ),
+            "invalid challenge"
+        );
+        assert_eq!(
+            check(
+                &client_data("webauthn.get", EXPECTED.challenge, "https://evil.example"),
+                &good_authenticator_data
+            ),
+            "invalid origin"
+        );
+        let good_client_data = client_data("webauthn.get", EXPECTED.challenge, EXPECTED.origin);
+        assert_eq!(
+            check(
+                &good_client_data,
+                &authenticator_data("evil.example", 0x05, 0, &[])
+            ),
+            "invalid relying party ID"
+        );
+        assert_eq!(
+            check(
+                &good_client_data,
+                &authenticator_data(EXPECTED.rp_id, 0x00, 0, &[])
+            ),
+            "user was not present"
+        );
+        assert_eq!(
+            check(
+                &good_client_data,
+                &authenticator_data(EXPECTED.rp_id, 0x01, 0, &[])
+            ),
+            "user was not verified"
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use crate::agents::FetchUrlCached;
+use crate::bridges::oidc::{ProviderKey, ProviderKeys, LEEWAY};
+use crate::config::ClientConfig;
+use crate::crypto;
+use crate::error::{BrokerError, BrokerResult};
+use crate::metrics;
+use crate::utils::unix_timestamp;
+use crate::web::Context;
+use headers::{authorization::Basic, Authorization, HeaderMapExt};
+use percent_encoding::percent_decode_str;
+use ring::{constant_time, digest};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use url::Url;
+
+/// The `client_assertion_type` for JWT client assertions. (RFC 7523)
+pub const JWT_BEARER_ASSERTION_TYPE: &str =
+    "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+/// Client authentication methods we support, as listed in the discovery document.
+pub const CLIENT_AUTH_METHODS: &[&str] = &[
+    "client_secret_basic",
+    "client_secret_post",
+    "private_key_jwt",
+    "none",
+];
+
+/// Credentials presented by a client.
+pub enum ClientCredentials {
+    /// No credentials. The client may still identify itself using the `client_id` parameter.
+    None { client_id: Option<String> },
+    /// A client secret, sent using HTTP Basic or in the request body.
+    Secret { client_id: String, secret: String },
+    /// A signed JWT client assertion.
+    Assertion {
+        client_id: Option<String>,
+        assertion: String,
+    },
+}
+
+/// Decode a value from HTTP Basic credentials, which is form-encoded per the OAuth2 spec.
+fn decode_basic_value(input: &str) -> BrokerResult<String> {
+    percent_decode_str(&input.replace('+', " "))
+        .decode_utf8()
+        .map(Cow::into_owned)
+        .map_err(|_err| BrokerError::ClientAuth("invalid HTTP Basic credentials".to_owned()))
+}
+
+impl ClientCredentials {
+    /// Extract client credentials from the request.
+    ///
+    /// This removes the relevant parameters from `params`. Only one authentication method may be
+    /// used in a single request.
+    pub fn from_request(ctx: &Context, params: &mut HashMap<String, String>) -> BrokerResult<Self> {
+        let basic = ctx.headers.typed_get::<Authorization<Basic>>();
+        let client_id = params.remove("client_id");
+        let client_secret = params.remove("client_secret");
+        let assertion_type = params.remove("client_assertion_type");
+        let assertion = params.remove("client_assertion");
+
+        let num_methods = usize::from(basic.is_some())
+            + usize::from(client_secret.is_some())
+            + usize::from(assertion.is_some());
+        if num_methods > 1 {
+            return Err(BrokerError::Input(
+                "multiple client authentication methods used".to_owned(),
+            ));
+        }
+
+        if let Some(Authorization(basic)) = basic {
+            let basic_client_id = decode_basic_value(basic.username())?;
+            if client_id.as_ref().is_some_and(|id| *id != basic_client_id) {
+                return Err(BrokerError::ClientAuth(
+                    "client_id does not match the HTTP Basic credentials".to_owned(),
+                ));
+            }
+            Ok(ClientCredentials::Secret {
+                client_id: basic_client_id,
+                secret: decode_basic_value(basic.password())?,
+            })
+        } else if let Some(secret) = client_secret {
+            let client_id = client_id.ok_or_else(|| {
+                BrokerError::Input(
+                    "missing request parameter client_id, required with client_secret".to_owned(),
+                )
+            })?;
+            Ok(ClientCredentials::Secret { client_id, secret })
+        } else if let Some(assertion) = assertion {
+            if assertion_type.as_deref() != Some(JWT_BEARER_ASSERTION_TYPE) {
+                return Err(BrokerError::Input(format!(
+                    "unsupported client_assertion_type, must be {JWT_BEARER_ASSERTION_TYPE}"
+                )));
+            }
+            Ok(ClientCredentials::Assertion {
+                client_id,
+                assertion,
+            })
+        } else {
+            Ok(ClientCredentials::None { client_id })
+        }
+    }
+
+    /// The client ID the client identified itself with, if any.
+    ///
+    /// For assertions, this is only the `client_id` parameter, if present.
+    pub fn client_id(&self) -> Option<&str> {
+        match self {
+            ClientCredentials::None { client_id }
+            | ClientCredentials::Assertion { client_id, .. } => client_id.as_deref(),
+            ClientCredentials::Secret { client_id, .. } => Some(client_id),
+        }
+    }
+}
+
+/// Authenticate a client using the credentials it presented.
+///
+/// If no clients are registered, credentials are ignored and every client is treated as public,
+/// which matches the behavior for Portier clients that have no registration.
+pub async fn authenticate_client(
+    ctx: &Context,
+    client_id: &str,
+    credentials: &ClientCredentials,
+) -> BrokerResult<()> {
+    if ctx.app.clients.is_empty() {
+        return Ok(());
+    }
+
+    let client = ctx
+        .app
+        .clients
+        .get(client_id)
+        .ok_or_else(|| BrokerError::ClientAuth("the client is not registered".to_owned()))?;
+
+    if let Some(id) = credentials.client_id() {
+        if id != client_id {
+            return Err(BrokerError::ClientAuth(
+                "the client credentials are for a different client".to_owned(),
+            ));
+        }
+    }
+
+    match credentials {
+        ClientCredentials::None { .. } => {
+            if !client.public {
+                return Err(BrokerError::ClientAuth(
+                    "client authentication is required".to_owned(),
+                ));
+            }
+        }
+        ClientCredentials::Secret { secret, .. } => {
+            let expected = client.secret_hash.as_ref().ok_or_else(|| {
+                BrokerError::ClientAuth("the client has no secret configured".to_owned())
+            })?;
+            let actual = digest::digest(&digest::SHA256, secret.as_bytes());
+            if constant_time::verify_slices_are_equal(actual.as_ref(), expected).is_err() {
+                return Err(BrokerError::ClientAuth(
+                    "invalid client credentials".to_owned(),
+                ));
+            }
+        }
+        ClientCredentials::Assertion { assertion, .. } => {
+            verify_client_assertion(ctx, client, assertion).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fetch the keys of a registered client from its `jwks_uri`.
+pub async fn fetch_client_keys(
+    ctx: &Context,
+    client: &ClientConfig,
+    jwks_uri: &Url,
+) -> BrokerResult<Vec<ProviderKey>> {
+    let key_set = ctx
+        .app
+        .store
+        .send(FetchUrlCached {
+            url: jwks_uri.clone(),
+            metric: &metrics::CLIENT_FETCH_JWKS_DURATION,
+        })
+        .await
+        .map_err(|e| {
+            BrokerError::Internal(format!(
+                "could not fetch the keys of client {}: {e}",
+                client.client_id
+            ))
+        })?;
+    let key_set: ProviderKeys = serde_json::from_str(&key_set).map_err(|e| {
+        BrokerError::Internal(format!(
+            "could not parse the keys of client {}: {e}",
+            client.client_id
+        ))
+    })?;
+    Ok(key_set.keys)
+}
+
+/// Verify a `private_key_jwt` client assertion. (RFC 7523)
+///
+/// The assertion is verified against the keys found at the client `jwks_uri`. It must be issued
+/// by the client for itself, and the audience must be either our issuer identifier or the URL of
+/// the endpoint the request was made to.
+async fn verify_client_assertion(
+    ctx: &Context,
+    client: &ClientConfig,
+    assertion: &str,
+) -> BrokerResult<()> {
+    let jwks_uri = client.jwks_uri.as_ref().ok_or_else(|| {
+        BrokerError::ClientAuth("the client has no jwks_uri configured".to_owned())
+    })?;
+
+    let keys = fetch_client_keys(ctx, client, jwks_uri).await?;
+
+    let signing_alg = crypto::jws_signing_alg(assertion)
+        .map_err(|err| BrokerError::ClientAuth(format!("invalid client assertion: {err}")))?;
+    let payload = crypto::verify_jws(assertion, &keys, signing_alg)
+        .map_err(|err| BrokerError::ClientAuth(format!("invalid client assertion: {err}")))?;
+
+    let invalid_claim =
+        |claim: &str| BrokerError::ClientAuth(format!("client assertion has an invalid {claim}"));
+    let get_str = |claim: &str| payload.get(claim).and_then(Value::as_str);
+
+    if get_str("iss") != Some(&client.client_id) {
+        return Err(invalid_claim("iss"));
+    }
+    if get_str("sub") != Some(&client.client_id) {
+        return Err(invalid_claim("sub"));
+    }
+
+    let endpoint = format!("{}{}", ctx.app.public_url, ctx.uri.path());
+    let aud_matches = |aud: &str| aud == ctx.app.public_url || aud == endpoint;
+    let aud_ok = match payload.get("aud") {
+        Some(Value::String(aud)) => aud_matches(aud),
+        Some(Value::Array(list)) => list.iter().filter_map(Value::as_str).any(aud_matches),
+        _ => false,
+    };
+    if !aud_ok {
+        return Err(invalid_claim("aud"));
+    }
+
+    // NOTE: We don't track `jti` to prevent replay, but do require the assertion is short-lived.
+    let now = unix_timestamp();
+    let exp = payload
+        .get("exp")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| invalid_claim("exp"))?;
+    if now >= exp.saturating_add(LEEWAY) || exp > now + ctx.app.token_ttl.as_secs() + LEEWAY {
+        return Err(invalid_claim("exp"));
+    }
+    if let Some(nbf) = payload.get("nbf").and_then(Value::as_u64) {
+        if nbf.saturating_sub(LEEWAY) > now {
+            return Err(invalid_claim("nbf"));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,412 @@
+use crate::bridges::email::EmailMode;
+use crate::crypto::SigningAlgorithm;
+use crate::validation::{parse_redirect_uri, ValidationError};
+use crate::web::{ResponseMode, ResponseType};
+use serde::Deserialize;
+use std::{convert::TryFrom, str::FromStr};
+use thiserror::Error;
+use url::Url;
+
+#[derive(Debug, Error)]
+pub enum ClientConfigError {
+    #[error("client entry is missing a client_id")]
+    MissingClientId,
+    #[error("client {0} must have at least one redirect_uri")]
+    NoRedirectUris(String),
+    #[error("client {client_id} has an invalid redirect_uri: {reason}")]
+    InvalidRedirectUri {
+        client_id: String,
+        reason: ValidationError,
+    },
+    #[error("client {client_id} has a redirect_uri on a different origin: {redirect_uri}")]
+    RedirectUriOrigin {
+        client_id: String,
+        redirect_uri: Url,
+    },
+    #[error("client {client_id} has an invalid {key} value: {value}")]
+    InvalidValue {
+        client_id: String,
+        key: &'static str,
+        value: String,
+    },
+    #[error("client {0} has a secret_hash that is not a hex-encoded SHA-256 hash")]
+    InvalidSecretHash(String),
+    #[error("client {0} has an invalid jwks_uri")]
+    InvalidJwksUri(String),
+    #[error("client entry contains an item without a '=' separator: {0}")]
+    NoSeparator(String),
+    #[error("client entry contains an invalid keyword: {0}")]
+    InvalidKeyword(String),
+    #[error("client {0} is registered more than once")]
+    Duplicate(String),
+}
+
+/// How the `sub` claim of identity tokens is derived. (OpenID Connect Core, section 8)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubjectType {
+    /// The normalized email address.
+    Public,
+    /// A keyed hash of the normalized email address and the client ID.
+    Pairwise,
+}
+
+impl FromStr for SubjectType {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<SubjectType, &'static str> {
+        match s {
+            "public" => Ok(SubjectType::Public),
+            "pairwise" => Ok(SubjectType::Pairwise),
+            _ => Err("unsupported value"),
+        }
+    }
+}
+
+/// Configuration for a registered relying party.
+///
+/// The `client_id` is the origin of the relying party, just like for unregistered clients. The
+/// allowed response types, modes and signing algorithms may be empty, in which case all values
+/// supported by the broker are allowed.
+///
+/// At the token endpoint, the client must authenticate with either a secret or a JWT signed by
+/// one of the keys at the `jwks_uri`, unless it is marked as public.
+#[derive(Clone)]
+pub struct ClientConfig {
+    /// The client ID, which is the RP origin.
+    pub client_id: String,
+    /// Exact redirect URIs the client may use.
+    pub redirect_uris: Vec<Url>,
+    /// Response types the client may use.
+    pub response_types: Vec<ResponseType>,
+    /// Response modes the client may use.
+    pub response_modes: Vec<ResponseMode>,
+    /// Token signing algorithms the client may use.
+    pub signing_algs: Vec<SigningAlgorithm>,
+    /// SHA-256 hash of the client secret.
+    pub secret_hash: Option<Vec<u8>>,
+    /// URL of the JWKs used to verify client assertions.
+    pub jwks_uri: Option<Url>,
+    /// Whether the client may skip authentication.
+    pub public: bool,
+    /// How the `sub` claim is derived for this client.
+    pub subject_type: SubjectType,
+    /// Whether tokens omit the email address, and only contain the domain and a pairwise `sub`.
+    pub privacy: bool,
+    /// Which ways to confirm are offered in emails, overriding the global setting.
+    pub email_mode: Option<EmailMode>,
+}
+
+impl ClientConfig {
+    /// Whether the client may use the given response type.
+    pub fn allows_response_type(&self, value: ResponseType) -> bool {
+        self.response_types.is_empty() || self.response_types.contains(&value)
+    }
+
+    /// Whether the client may use the given response mode.
+    pub fn allows_response_mode(&self, value: ResponseMode) -> bool {
+        self.response_modes.is_empty() || self.response_modes.contains(&value)
+    }
+
+    /// How the `sub` claim is derived for this client, taking privacy mode into account.
+    pub fn effective_subject_type(&self) -> SubjectType {
+        if self.privacy {
+            SubjectType::Pairwise
+        } else {
+            self.subject_type
+        }
+    }
+
+    /// Whether the client may use the given signing algorithm.
+    pub fn allows_signing_alg(&self, value: SigningAlgorithm) -> bool {
+        self.signing_algs.is_empty() || self.signing_algs.contains(&value)
+    }
+}
+
+/// Client configuration before validation, as found in a TOML `[[clients]]` section.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RawClientConfig {
+    client_id: Option<String>,
+    #[serde(default)]
+    redirect_uris: Vec<String>,
+    #[serde(default)]
+    response_types: Vec<String>,
+    #[serde(default)]
+    response_modes: Vec<String>,
+    #[serde(default)]
+    signing_algs: Vec<String>,
+    secret_hash: Option<String>,
+    jwks_uri: Option<String>,
+    #[serde(default)]
+    public: bool,
+    subject_type: Option<String>,
+    #[serde(default)]
+    privacy: bool,
+    email_mode: Option<String>,
+}
+
+/// Decode a hex-encoded SHA-256 hash.
+fn parse_sha256_hex(input: &str) -> Option<Vec<u8>> {
+    if input.len() != 64 || !input.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..input.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&input[idx..idx + 2], 16).ok())
+        .collect()
+}
+
+/// Parse a list of values, reporting the first invalid value.
+fn parse_values<T: FromStr>(
+    client_id: &str,
+    key: &'static str,
+    values: Vec<String>,
+) -> Result<Vec<T>, ClientConfigError> {
+    values
+        .into_iter()
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_err| ClientConfigError::InvalidValue {
+                    client_id: client_id.to_owned(),
+                    key,
+                    value,
+                })
+        })
+        .collect()
+}
+
+/// Parse a boolean value from a client entry string.
+fn parse_flag(
+    raw: &RawClientConfig,
+    key: &'static str,
+    value: String,
+) -> Result<bool, ClientConfigError> {
+    value
+        .parse()
+        .map_err(|_err| ClientConfigError::InvalidValue {
+            client_id: raw.client_id.clone().unwrap_or_default(),
+            key,
+            value,
+        })
+}
+
+impl TryFrom<RawClientConfig> for ClientConfig {
+    type Error = ClientConfigError;
+
+    fn try_from(raw: RawClientConfig) -> Result<Self, Self::Error> {
+        let client_id = raw.client_id.ok_or(ClientConfigError::MissingClientId)?;
+        if raw.redirect_uris.is_empty() {
+            return Err(ClientConfigError::NoRedirectUris(client_id));
+        }
+
+        let mut redirect_uris = Vec::with_capacity(raw.redirect_uris.len());
+        for input in &raw.redirect_uris {
+            let redirect_uri = parse_redirect_uri(input, "redirect_uri").map_err(|reason| {
+                ClientConfigError::InvalidRedirectUri {
+                    client_id: client_id.clone(),
+                    reason,
+                }
+            })?;
+            if redirect_uri.origin().ascii_serialization() != client_id {
+                return Err(ClientConfigError::RedirectUriOrigin {
+                    client_id,
+                    redirect_uri,
+                });
+            }
+            redirect_uris.push(redirect_uri);
+        }
+
+        let secret_hash = match raw.secret_hash {
+            Some(ref input) => Some(
+                parse_sha256_hex(input)
+                    .ok_or_else(|| ClientConfigError::InvalidSecretHash(client_id.clone()))?,
+            ),
+            None => None,
+        };
+
+        let jwks_uri = match raw.jwks_uri {
+            Some(ref input) => Some(
+                Url::parse(input)
+                    .ok()
+                    .filter(|url| matches!(url.scheme(), "http" | "https"))
+                    .ok_or_else(|| ClientConfigError::InvalidJwksUri(client_id.clone()))?,
+            ),
+            None => None,
+        };
+
+        let subject_type = match raw.subject_type {
+            Some(value) => value
+                .parse()
+                .map_err(|_err| ClientConfigError::InvalidValue {
+                    client_id: client_id.clone(),
+                    key: "subject_type",
+                    value,
+                })?,
+            None => SubjectType::Public,
+        };
+
+        let email_mode = match raw.email_mode {
+            Some(value) => Some(
+                value
+                    .parse()
+                    .map_err(|_err| ClientConfigError::InvalidValue {
+                        client_id: client_id.clone(),
+                        key: "email_mode",
+                        value,
+                    })?,
+            ),
+            None => None,
+        };
+
+        Ok(ClientConfig {
+            secret_hash,
+            subject_type,
+            jwks_uri,
+            public: raw.public,
+            privacy: raw.privacy,
+            email_mode,
+            response_types: parse_values(&client_id, "response_type", raw.response_types)?,
+            response_modes: parse_values(&client_id, "response_mode", raw.response_modes)?,
+            signing_algs: parse_values(&client_id, "signing_alg", raw.signing_algs)?,
+            client_id,
+            redirect_uris,
+        })
+    }
+}
+
+/// Parse a client from a single line of whitespace separated `key=value` items.
+///
+/// This is the syntax used in environment variables and files. Keys are the singular form of the
+/// TOML keys, and list values are built by repeating a key. For example:
+///
+/// ```text
+/// client_id=https://example.com redirect_uri=https://example.com/callback response_type=code
+/// ```
+impl FromStr for ClientConfig {
+    type Err = ClientConfigError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut raw = RawClientConfig::default();
+        for item in value.split_whitespace() {
+            let (key, value) = item
+                .split_once('=')
+                .ok_or_else(|| ClientConfigError::NoSeparator(item.to_owned()))?;
+            let value = value.to_owned();
+            match key {
+                "client_id" => raw.client_id = Some(value),
+                "redirect_uri" => raw.redirect_uris.push(value),
+                "response_type" => raw.response_types.push(value),
+                "response_mode" => raw.response_modes.push(value),
+                "signing_alg" => raw.signing_algs.push(value),
+                "secret_hash" => raw.secret_hash = Some(value),
+                "jwks_uri" => raw.jwks_uri = Some(value),
+                "subject_type" => raw.subject_type = Some(value),
+                "public" => raw.public = parse_flag(&raw, "public", value)?,
+                "privacy" => raw.privacy = parse_flag(&raw, "privacy", value)?,
+                "email_mode" => raw.email_mode = Some(value),
+                _ => return Err(ClientConfigError::InvalidKeyword(key.to_owned())),
+            }
+        }
+        ClientConfig::try_from(raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientConfig, ClientConfigError, SubjectType};
+    use crate::bridges::email::EmailMode;
+    use crate::crypto::SigningAlgorithm;
+    use crate::web::{ResponseMode, ResponseType};
+
+    #[test]
+    fn test_parse() {
+        let client: ClientConfig = "client_id=https://example.com \
+             redirect_uri=https://example.com/a redirect_uri=https://example.com/b \
+             response_type=code response_mode=query signing_alg=EdDSA subject_type=pairwise \
+             email_mode=code"
+            .parse()
+            .unwrap();
+        assert_eq!(client.client_id, "https://example.com");
+        assert_eq!(client.redirect_uris.len(), 2);
+        assert_eq!(client.redirect_uris[1].as_str(), "https://example.com/b");
+        assert!(client.allows_response_type(ResponseType::Code));
+        assert!(!client.allows_response_type(ResponseType::IdToken));
+        assert!(client.allows_response_mode(ResponseMode::Query));
+        assert!(!client.allows_response_mode(ResponseMode::Fragment));
+        assert!(client.allows_signing_alg(SigningAlgorithm::EdDsa));
+        assert!(!client.allows_signing_alg(SigningAlgorithm::Rs256));
+        assert_eq!(client.subject_type, SubjectType::Pairwise);
+        assert_eq!(client.email_mode, Some(EmailMode::Code));
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let client: ClientConfig = "client_id=https://example.com redirect_uri=https://example.com"
+            .parse()
+            .unwrap();
+        assert!(client.allows_response_type(ResponseType::IdToken));
+        assert!(client.allows_response_mode(ResponseMode::FormPost));
+        assert!(client.allows_signing_alg(SigningAlgorithm::Rs256));
+        assert!(client.secret_hash.is_none());
+        assert!(client.jwks_uri.is_none());
+        assert!(!client.public);
+        assert_eq!(client.subject_type, SubjectType::Public);
+        assert!(client.email_mode.is_none());
+    }
+
+    #[test]
+    fn test_parse_credentials() {
+        let client: ClientConfig =
+            "client_id=https://example.com redirect_uri=https://example.com \
+             secret_hash=2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b \
+             jwks_uri=https://example.com/jwks.json public=true privacy=true"
+                .parse()
+                .unwrap();
+        assert_eq!(client.effective_subject_type(), SubjectType::Pairwise);
+        let secret_hash = client.secret_hash.unwrap();
+        assert_eq!(secret_hash.len(), 32);
+        assert_eq!(secret_hash[..2], [0x2b, 0xb8]);
+        assert_eq!(
+            client.jwks_uri.unwrap().as_str(),
+            "https://example.com/jwks.json"
+        );
+        assert!(client.public);
+        assert!(client.privacy);
+        assert!(matches!(
+            "client_id=https://example.com redirect_uri=https://example.com secret_hash=abc"
+                .parse::<ClientConfig>(),
+            Err(ClientConfigError::InvalidSecretHash(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            "redirect_uri=https://example.com".parse::<ClientConfig>(),
+            Err(ClientConfigError::MissingClientId)
+        ));
+        assert!(matches!(
+            "client_id=https://example.com".parse::<ClientConfig>(),
+            Err(ClientConfigError::NoRedirectUris(_))
+        ));
+        assert!(matches!(
+            "client_id=https://example.com redirect_uri=https://example.org/"
+                .parse::<ClientConfig>(),
+            Err(ClientConfigError::RedirectUriOrigin { .. })
+        ));
+        assert!(matches!(
+            "client_id=https://example.com redirect_uri=https://example.com response_type=token"
+                .parse::<ClientConfig>(),
+            Err(ClientConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            "client_id=https://example.com redirect_uri=https://example.com email_mode=none"
+                .parse::<ClientConfig>(),
+            Err(ClientConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            "client_id=https://example.com foo=bar".parse::<ClientConfig>(),
+            Err(ClientConfigError::InvalidKeyword(_))
+        ));
+    }
+}
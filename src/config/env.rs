@@ -1,4 +1,5 @@
 use super::{ConfigBuilder, LegacyLimitPerEmail, LimitConfig};
+use crate::bridges::email::{CodeAlphabet, ConfirmDevice, EmailMode};
 use crate::config::StringList;
 use crate::crypto::SigningAlgorithm;
 use ipnetwork::IpNetwork;
@@ -27,6 +28,16 @@ pub struct EnvConfig {
     verify_with_resolver: Option<String>,
     verify_public_ip: Option<bool>,
     allowed_domains_only: Option<bool>,
+    require_pkce: Option<bool>,
+    webauthn: Option<bool>,
+    max_code_attempts: Option<u32>,
+    resend_new_code: Option<bool>,
+    confirm_device: Option<ConfirmDevice>,
+    email_mode: Option<EmailMode>,
+    code_alphabet: Option<CodeAlphabet>,
+    code_length: Option<usize>,
+    code_group_size: Option<usize>,
+    clients: Option<StringList>,
 
     static_ttl: Option<u64>,
     discovery_ttl: Option<u64>,
@@ -34,7 +45,12 @@ pub struct EnvConfig {
     token_ttl: Option<u64>,
     session_ttl: Option<u64>,
     auth_code_ttl: Option<u64>,
+    pushed_request_ttl: Option<u64>,
+    device_code_ttl: Option<u64>,
+    login_cookie_ttl: Option<u64>,
     cache_ttl: Option<u64>,
+    webfinger_timeout: Option<u64>,
+    provider_timeout: Option<u64>,
 
     keyfiles: Option<Vec<PathBuf>>,
     keytext: Option<String>,
@@ -66,6 +82,7 @@ pub struct EnvConfig {
     limit_per_email: Option<LegacyLimitPerEmail>,
 
     google_client_id: Option<String>,
+    dns_discovery: Option<bool>,
 
     // Deprecated
     ip: Option<String>,
@@ -157,6 +174,45 @@ impl EnvConfig {
         if let Some(val) = parsed.allowed_domains_only {
             builder.domain_validator.allowed_domains_only = val;
         }
+        if let Some(val) = parsed.require_pkce {
+            builder.require_pkce = val;
+        }
+        if let Some(val) = parsed.webauthn {
+            builder.webauthn = val;
+        }
+        if let Some(val) = parsed.max_code_attempts {
+            builder.max_code_attempts = val;
+        }
+        if let Some(val) = parsed.resend_new_code {
+            builder.resend_new_code = val;
+        }
+        if let Some(val) = parsed.confirm_device {
+            builder.confirm_device = val;
+        }
+        if let Some(val) = parsed.email_mode {
+            builder.email_mode = val;
+        }
+        if let Some(val) = parsed.code_alphabet {
+            builder.code_alphabet = val;
+        }
+        if let Some(val) = parsed.code_length {
+            builder.code_length = val;
+        }
+        if let Some(val) = parsed.code_group_size {
+            builder.code_group_size = val;
+        }
+        if let Some(val) = parsed.clients {
+            for (source, res) in val.iter_values() {
+                let data = match res {
+                    Ok(data) => data,
+                    Err(err) => panic!("IO error in BROKER_CLIENTS entry {source}: {err}"),
+                };
+                match data.parse() {
+                    Ok(client) => builder.clients.push(client),
+                    Err(err) => panic!("Invalid BROKER_CLIENTS entry {source}: {err}"),
+                }
+            }
+        }
 
         if let Some(val) = parsed.static_ttl {
             builder.static_ttl = Duration::from_secs(val);
@@ -176,9 +232,24 @@ impl EnvConfig {
         if let Some(val) = parsed.auth_code_ttl {
             builder.auth_code_ttl = Duration::from_secs(val);
         }
+        if let Some(val) = parsed.pushed_request_ttl {
+            builder.pushed_request_ttl = Duration::from_secs(val);
+        }
+        if let Some(val) = parsed.device_code_ttl {
+            builder.device_code_ttl = Duration::from_secs(val);
+        }
+        if let Some(val) = parsed.login_cookie_ttl {
+            builder.login_cookie_ttl = Duration::from_secs(val);
+        }
         if let Some(val) = parsed.cache_ttl {
             builder.cache_ttl = Duration::from_secs(val);
         }
+        if let Some(val) = parsed.webfinger_timeout {
+            builder.webfinger_timeout = Duration::from_secs(val);
+        }
+        if let Some(val) = parsed.provider_timeout {
+            builder.provider_timeout = Duration::from_secs(val);
+        }
 
         if let Some(val) = parsed.keyfiles {
             builder.keyfiles = val;
@@ -255,5 +326,8 @@ impl EnvConfig {
         if let Some(val) = parsed.google_client_id {
             builder.google_client_id = Some(val);
         }
+        if let Some(val) = parsed.dns_discovery {
+            builder.dns_discovery = val;
+        }
     }
 }
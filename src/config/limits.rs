@@ -17,6 +17,18 @@ pub enum LimitConfigError {
     InvalidKeyword(String),
 }
 
+/// The type of request a rate limit applies to.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LimitKind {
+    /// Authentication requests, the default.
+    #[default]
+    Auth,
+    /// Requests to resend email, with the `resend` flag.
+    Resend,
+    /// Polls for the status of an email confirmation, with the `status` flag.
+    Status,
+}
+
 /// Configuration for a type of rate limiting.
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 #[allow(clippy::struct_excessive_bools)]
@@ -35,6 +47,8 @@ pub struct LimitConfig {
     pub extend_window: bool,
     /// Whether to decrement the limit for completed requests.
     pub decr_complete: bool,
+    /// The type of request the limit applies to.
+    pub kind: LimitKind,
     /// Maximum request count within the window before we refuse.
     pub max_count: usize,
     /// Timespan of the entire window, in seconds.
@@ -81,6 +95,7 @@ impl FromStr for LimitConfig {
             with_ip: false,
             extend_window: false,
             decr_complete: false,
+            kind: LimitKind::Auth,
             max_count,
             window: Duration::from_secs(window),
         };
@@ -93,6 +108,8 @@ impl FromStr for LimitConfig {
                 "origin" => config.with_origin = true,
                 "extend_window" => config.extend_window = true,
                 "decr_complete" => config.decr_complete = true,
+                "resend" => config.kind = LimitKind::Resend,
+                "status" => config.kind = LimitKind::Status,
                 _ => {
                     return Err(LimitConfigError::InvalidKeyword(keyword.to_owned()));
                 }
@@ -155,7 +172,7 @@ impl<'de> Deserialize<'de> for LegacyLimitPerEmail {
 
 #[cfg(test)]
 mod tests {
-    use super::LimitConfig;
+    use super::{LimitConfig, LimitKind};
     use std::time::Duration;
 
     #[test]
@@ -206,5 +223,16 @@ mod tests {
                 ..LimitConfig::default()
             })
         );
+        assert_eq!(
+            "ip:email:resend:3/15m".parse(),
+            Ok(LimitConfig {
+                with_ip: true,
+                with_email_addr: true,
+                kind: LimitKind::Resend,
+                max_count: 3,
+                window: Duration::from_secs(900),
+                ..LimitConfig::default()
+            })
+        );
     }
 }
@@ -1,11 +1,15 @@
+mod clients;
 mod env;
 mod i18n;
 mod limits;
+mod providers;
 mod string_list;
 mod templates;
 mod toml;
 
+pub use clients::*;
 pub use limits::*;
+pub use providers::*;
 pub use string_list::*;
 
 use self::env::EnvConfig;
@@ -13,9 +17,10 @@ use self::i18n::I18n;
 use self::templates::Templates;
 use self::toml::TomlConfig;
 use crate::agents::{
-    self, FetchAgent, KeyManagerSender, ManualKeys, ManualKeysError, RotatingKeys, SendMail,
-    StoreSender,
+    self, FetchAgent, GetPairwiseSecret, KeyManagerSender, ManualKeys, ManualKeysError,
+    RotatingKeys, SendMail, StoreSender,
 };
+use crate::bridges::email::{CodeAlphabet, ConfirmDevice, EmailMode, MAX_GUESS_PROBABILITY};
 use crate::bridges::oidc::GOOGLE_IDP_ORIGIN;
 use crate::crypto::SigningAlgorithm;
 use crate::email_address::EmailAddress;
@@ -49,6 +54,8 @@ pub enum ConfigError {
     ManualKeys(#[from] ManualKeysError),
     #[error("domain override configuration error: {0}")]
     DomainOverride(#[from] ParseLinkError),
+    #[error("client configuration error: {0}")]
+    Client(#[from] ClientConfigError),
 }
 
 impl From<&'static str> for ConfigError {
@@ -59,27 +66,53 @@ impl From<&'static str> for ConfigError {
 
 pub type ConfigRc = Arc<Config>;
 
+/// Limit for resending email, used when no configured limit has the `resend` flag.
+const DEFAULT_RESEND_LIMIT: &str = "ip:email:resend:3/15m";
+
+/// Limit for confirmation status polls, used when no configured limit has the `status` flag.
+const DEFAULT_STATUS_LIMIT: &str = "ip:email:status:60/m";
+
+#[allow(clippy::struct_excessive_bools)]
 pub struct Config {
     pub listen_ip: String,
     pub listen_port: u16,
     pub public_url: String,
     pub trusted_proxies: Vec<IpNetwork>,
     pub allowed_origins: Option<Vec<String>>,
+    pub require_pkce: bool,
+    pub webauthn: bool,
+    pub max_code_attempts: u32,
+    pub resend_new_code: bool,
+    pub confirm_device: ConfirmDevice,
+    pub email_mode: EmailMode,
+    pub code_alphabet: CodeAlphabet,
+    pub code_length: usize,
+    pub code_group_size: usize,
+    pub clients: HashMap<String, ClientConfig>,
     pub domain_validator: DomainValidator,
 
     pub static_ttl: Duration,
     pub discovery_ttl: Duration,
     pub keys_ttl: Duration,
     pub token_ttl: Duration,
+    pub pushed_request_ttl: Duration,
+    pub device_code_ttl: Duration,
+    pub login_cookie_ttl: Duration,
+    pub webfinger_timeout: Duration,
+    pub provider_timeout: Duration,
 
     pub key_manager: Box<dyn KeyManagerSender>,
     pub signing_algs: Vec<SigningAlgorithm>,
+    pub pairwise_secret: Vec<u8>,
 
     pub store: Arc<dyn StoreSender>,
     pub mailer: Box<dyn Sender<SendMail>>,
+    pub fetcher: Addr<FetchAgent>,
 
     pub google_client_id: Option<String>,
+    pub dns_discovery: bool,
     pub domain_overrides: HashMap<String, Vec<Link>>,
+    pub oidc_providers: HashMap<String, OidcProviderConfig>,
 
     pub res_dir: PathBuf,
     pub templates: Templates,
@@ -337,12 +370,23 @@ impl MailerConfig {
     }
 }
 
+#[allow(clippy::struct_excessive_bools)]
 pub struct ConfigBuilder {
     pub listen_ip: String,
     pub listen_port: u16,
     pub public_url: Option<String>,
     pub trusted_proxies: Vec<IpNetwork>,
     pub allowed_origins: Option<Vec<String>>,
+    pub require_pkce: bool,
+    pub webauthn: bool,
+    pub max_code_attempts: u32,
+    pub resend_new_code: bool,
+    pub confirm_device: ConfirmDevice,
+    pub email_mode: EmailMode,
+    pub code_alphabet: CodeAlphabet,
+    pub code_length: usize,
+    pub code_group_size: usize,
+    pub clients: Vec<ClientConfig>,
     pub domain_validator: DomainValidator,
     pub data_dir: String,
 
@@ -352,7 +396,12 @@ pub struct ConfigBuilder {
     pub token_ttl: Duration,
     pub session_ttl: Duration,
     pub auth_code_ttl: Duration,
+    pub pushed_request_ttl: Duration,
+    pub device_code_ttl: Duration,
+    pub login_cookie_ttl: Duration,
     pub cache_ttl: Duration,
+    pub webfinger_timeout: Duration,
+    pub provider_timeout: Duration,
 
     pub keyfiles: Vec<PathBuf>,
     pub keytext: Option<String>,
@@ -383,7 +432,9 @@ pub struct ConfigBuilder {
     pub limits: Vec<LimitConfig>,
 
     pub google_client_id: Option<String>,
+    pub dns_discovery: bool,
     pub domain_overrides: HashMap<String, Vec<Link>>,
+    pub oidc_providers: HashMap<String, OidcProviderConfig>,
 }
 
 impl ConfigBuilder {
@@ -397,6 +448,16 @@ impl ConfigBuilder {
                 .map(|v| v.parse().unwrap())
                 .collect(),
             allowed_origins: None,
+            require_pkce: false,
+            webauthn: false,
+            max_code_attempts: 5,
+            resend_new_code: false,
+            confirm_device: ConfirmDevice::Confirming,
+            email_mode: EmailMode::Both,
+            code_alphabet: CodeAlphabet::ZBase32,
+            code_length: 12,
+            code_group_size: 6,
+            clients: Vec::new(),
             domain_validator: DomainValidator::new(),
             data_dir: String::new(),
 
@@ -406,7 +467,12 @@ impl ConfigBuilder {
             token_ttl: Duration::from_secs(600),
             session_ttl: Duration::from_secs(900),
             auth_code_ttl: Duration::from_secs(600),
+            pushed_request_ttl: Duration::from_secs(60),
+            device_code_ttl: Duration::from_secs(600),
+            login_cookie_ttl: Duration::from_secs(0),
             cache_ttl: Duration::from_secs(3600),
+            webfinger_timeout: Duration::from_secs(5),
+            provider_timeout: Duration::from_secs(5),
 
             keyfiles: Vec::new(),
             keytext: None,
@@ -447,13 +513,17 @@ impl ConfigBuilder {
                 "ip:email:30/h",
                 "ip:email:decr_complete:5/15m",
                 "ip:email:origin:decr_complete:2/15m",
+                DEFAULT_RESEND_LIMIT,
+                DEFAULT_STATUS_LIMIT,
             ]
             .iter()
             .map(|value| value.parse().unwrap())
             .collect::<Vec<_>>(),
 
             google_client_id: None,
+            dns_discovery: false,
             domain_overrides: HashMap::new(),
+            oidc_providers: HashMap::new(),
         }
     }
 
@@ -521,11 +591,54 @@ impl ConfigBuilder {
             self.mailgun_domain,
         )?;
 
+        // Index registered clients by client ID.
+        let mut clients = HashMap::new();
+        for client in self.clients {
+            if let Some(client) = clients.insert(client.client_id.clone(), client) {
+                return Err(ClientConfigError::Duplicate(client.client_id).into());
+            }
+        }
+
+        // Codes must be long enough that they cannot be guessed within the allowed attempts.
+        if self.code_length > 64 {
+            return Err("code_length must be at most 64".into());
+        }
+        if self.max_code_attempts == 0 {
+            return Err("max_code_attempts must be at least 1".into());
+        }
+        if self
+            .code_alphabet
+            .guess_probability(self.code_length, self.max_code_attempts)
+            > MAX_GUESS_PROBABILITY
+        {
+            return Err("code_length is too short for code_alphabet and max_code_attempts".into());
+        }
+
+        if self.device_code_ttl.as_secs() == 0 {
+            return Err("device_code_ttl must be at least 1 second".into());
+        }
+
+        // Resending email and status polls are only limited by limits of their own kind, so
+        // there must be one of each.
+        for (kind, default) in [
+            (LimitKind::Resend, DEFAULT_RESEND_LIMIT),
+            (LimitKind::Status, DEFAULT_STATUS_LIMIT),
+        ] {
+            if !self.limits.iter().any(|limit| limit.kind == kind) {
+                self.limits
+                    .push(default.parse().expect("invalid default limit"));
+            }
+        }
+
         // Assign IDs to limit configs.
         for (idx, limit) in self.limits.iter_mut().enumerate() {
             limit.id = idx;
         }
 
+        if self.dns_discovery && !self.domain_validator.has_resolver() {
+            return Err("dns_discovery requires verify_with_resolver to be set".into());
+        }
+
         // Child structs
         let rng = SecureRandom::new().await;
         let fetcher = spawn_agent(FetchAgent::new()).await;
@@ -539,6 +652,12 @@ impl ConfigBuilder {
                 rng: rng.clone(),
             })
             .await;
+        let pairwise_secret = store
+            .send(GetPairwiseSecret {
+                candidate: rng.generate_async(32).await,
+            })
+            .await
+            .expect("Could not get the pairwise subject secret from the store");
         let key_manager: Box<dyn KeyManagerSender> = if is_keyed_manually {
             let key_manager = ManualKeys::new(
                 &self.keyfiles,
@@ -549,7 +668,10 @@ impl ConfigBuilder {
             Box::new(spawn_agent(key_manager).await)
         } else {
             if !cfg!(feature = "rsa")
-                && self.signing_algs.contains(&SigningAlgorithm::Rs256)
+                && self
+                    .signing_algs
+                    .iter()
+                    .any(|alg| alg.key_alg() == SigningAlgorithm::Rs256)
                 && self.generate_rsa_command.is_empty()
             {
                 return Err("generate_rsa_command is required for rotating RSA keys".into());
@@ -566,7 +688,7 @@ impl ConfigBuilder {
         };
         let mailer = mailer_config
             .spawn_mailer(MailerParams {
-                fetcher,
+                fetcher: fetcher.clone(),
                 from_address: self
                     .from_address
                     .expect("No mail 'From' address configured")
@@ -604,21 +726,40 @@ impl ConfigBuilder {
             public_url: self.public_url.expect("no public url configured"),
             trusted_proxies: self.trusted_proxies,
             allowed_origins: self.allowed_origins,
+            require_pkce: self.require_pkce,
+            webauthn: self.webauthn,
+            max_code_attempts: self.max_code_attempts,
+            resend_new_code: self.resend_new_code,
+            confirm_device: self.confirm_device,
+            email_mode: self.email_mode,
+            code_alphabet: self.code_alphabet,
+            code_length: self.code_length,
+            code_group_size: self.code_group_size,
+            clients,
             domain_validator: self.domain_validator,
 
             static_ttl: self.static_ttl,
             discovery_ttl: self.discovery_ttl,
             keys_ttl: self.keys_ttl,
             token_ttl: self.token_ttl,
+            pushed_request_ttl: self.pushed_request_ttl,
+            device_code_ttl: self.device_code_ttl,
+            login_cookie_ttl: self.login_cookie_ttl,
+            webfinger_timeout: self.webfinger_timeout,
+            provider_timeout: self.provider_timeout,
 
             key_manager,
             signing_algs: self.signing_algs,
+            pairwise_secret,
 
             store,
             mailer,
+            fetcher,
 
             google_client_id: self.google_client_id,
+            dns_discovery: self.dns_discovery,
             domain_overrides,
+            oidc_providers: self.oidc_providers,
 
             res_dir,
             templates,
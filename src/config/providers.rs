@@ -0,0 +1,139 @@
+use crate::webfinger::{Link, Relation};
+use serde::Deserialize;
+use thiserror::Error;
+use url::Url;
+
+#[derive(Debug, Error)]
+pub enum ProviderConfigError {
+    #[error("provider {0} is missing a client_id")]
+    MissingClientId(Url),
+    #[error("provider {0} has credentials, but is not a generic OpenID Connect provider")]
+    UnexpectedCredentials(Url),
+}
+
+/// Configuration for a generic upstream OpenID Connect provider.
+///
+/// Unlike Portier providers, these require a client registration with the provider. They can only
+/// be used through `domain_overrides`, never through webfinger.
+#[derive(Clone)]
+pub struct OidcProviderConfig {
+    /// The issuer identifier of the provider.
+    pub issuer: String,
+    /// The client ID registered with the provider.
+    pub client_id: String,
+    /// The client secret registered with the provider, used at its token endpoint.
+    pub client_secret: Option<String>,
+    /// Whether tokens must contain `email_verified` set to true.
+    pub require_email_verified: bool,
+}
+
+/// Get the issuer identifier for the `href` of a generic OpenID Connect provider link.
+///
+/// Issuers may contain a path, but the `url` crate adds a trailing slash to bare origins.
+pub fn issuer_from_href(href: &Url) -> String {
+    href.as_str().trim_end_matches('/').to_owned()
+}
+
+/// A link in `domain_overrides`, as found in TOML.
+///
+/// For generic OpenID Connect providers, the link also carries the client credentials, and the
+/// `href` is the issuer of the provider.
+#[derive(Deserialize)]
+pub struct RawDomainOverrideLink {
+    rel: Relation,
+    href: Url,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    require_email_verified: Option<bool>,
+}
+
+impl RawDomainOverrideLink {
+    /// Split into the link and, for generic OpenID Connect providers, the provider configuration.
+    pub fn into_parts(self) -> Result<(Link, Option<OidcProviderConfig>), ProviderConfigError> {
+        let provider = match (self.rel, self.client_id) {
+            (Relation::Oidc, Some(client_id)) => Some(OidcProviderConfig {
+                issuer: issuer_from_href(&self.href),
+                client_id,
+                client_secret: self.client_secret,
+                require_email_verified: self.require_email_verified.unwrap_or(true),
+            }),
+            (Relation::Oidc, None) => {
+                return Err(ProviderConfigError::MissingClientId(self.href));
+            }
+            (_, None) if self.client_secret.is_none() && self.require_email_verified.is_none() => {
+                None
+            }
+            (_, _) => return Err(ProviderConfigError::UnexpectedCredentials(self.href)),
+        };
+        let link = Link {
+            rel: self.rel,
+            href: self.href,
+        };
+        Ok((link, provider))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProviderConfigError, RawDomainOverrideLink};
+    use crate::webfinger::Relation;
+
+    fn parse(input: &str) -> RawDomainOverrideLink {
+        ::toml::from_str(input).unwrap()
+    }
+
+    #[test]
+    fn test_into_parts() {
+        let (link, provider) = parse(
+            r#"
+            rel = "https://portier.io/specs/auth/1.0/idp/oidc"
+            href = "https://sso.example.com/realms/staff"
+            client_id = "portier"
+            client_secret = "secret"
+            "#,
+        )
+        .into_parts()
+        .unwrap();
+        assert_eq!(link.rel, Relation::Oidc);
+        let provider = provider.unwrap();
+        assert_eq!(provider.issuer, "https://sso.example.com/realms/staff");
+        assert_eq!(provider.client_id, "portier");
+        assert!(provider.require_email_verified);
+
+        let (_, provider) = parse(
+            r#"
+            rel = "https://portier.io/specs/auth/1.0/idp/oidc"
+            href = "https://gitlab.com"
+            client_id = "portier"
+            "#,
+        )
+        .into_parts()
+        .unwrap();
+        assert_eq!(provider.unwrap().issuer, "https://gitlab.com");
+    }
+
+    #[test]
+    fn test_into_parts_errors() {
+        assert!(matches!(
+            parse(
+                r#"
+                rel = "https://portier.io/specs/auth/1.0/idp/oidc"
+                href = "https://sso.example.com"
+                "#
+            )
+            .into_parts(),
+            Err(ProviderConfigError::MissingClientId(_))
+        ));
+        assert!(matches!(
+            parse(
+                r#"
+                rel = "https://portier.io/specs/auth/1.0/idp"
+                href = "https://idp.example.com"
+                client_id = "portier"
+                "#
+            )
+            .into_parts(),
+            Err(ProviderConfigError::UnexpectedCredentials(_))
+        ));
+    }
+}
@@ -36,8 +36,14 @@ impl Template {
 
 // Contains all templates we use in compiled form.
 pub struct Templates {
+    /// Page where the user approves a login that the original tab completes.
+    pub approve: Template,
     /// Page displayed when the confirmation email was sent.
     pub confirm_email: Template,
+    /// Page displayed when the email was confirmed, but the original tab completes the login.
+    pub confirmed: Template,
+    /// Page where the user enters a device authorization code.
+    pub device: Template,
     /// Page displayed when the login_hint is missing.
     pub login_hint: Template,
     /// HTML formatted email containing the one-type pad.
@@ -50,18 +56,24 @@ pub struct Templates {
     pub forward: Template,
     /// A dummy form used to capture query and fragment parameters.
     pub rewrite_to_post: Template,
+    /// Page used to login with or register a passkey.
+    pub webauthn: Template,
 }
 
 impl Templates {
     pub fn new(data_dir: &str) -> Templates {
         Templates {
+            approve: Template::compile(data_dir, "approve"),
             confirm_email: Template::compile(data_dir, "confirm_email"),
+            confirmed: Template::compile(data_dir, "confirmed"),
             email_html: Template::compile(data_dir, "email_html"),
             email_text: Template::compile(data_dir, "email_text"),
+            device: Template::compile(data_dir, "device"),
             login_hint: Template::compile(data_dir, "login_hint"),
             error: Template::compile(data_dir, "error"),
             forward: Template::compile(data_dir, "forward"),
             rewrite_to_post: Template::compile(data_dir, "rewrite_to_post"),
+            webauthn: Template::compile(data_dir, "webauthn"),
         }
     }
 }
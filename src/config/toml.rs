@@ -1,10 +1,14 @@
-use super::{ConfigBuilder, LegacyLimitPerEmail, LimitConfig};
+use super::{
+    ClientConfig, ConfigBuilder, LegacyLimitPerEmail, LimitConfig, RawClientConfig,
+    RawDomainOverrideLink,
+};
+use crate::bridges::email::{CodeAlphabet, ConfirmDevice, EmailMode};
 use crate::config::StringList;
 use crate::crypto::SigningAlgorithm;
-use crate::webfinger::Link;
 use ipnetwork::IpNetwork;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::str::from_utf8;
@@ -27,6 +31,16 @@ pub struct TomlConfig {
     verify_with_resolver: Option<String>,
     verify_public_ip: Option<bool>,
     allowed_domains_only: Option<bool>,
+    require_pkce: Option<bool>,
+    webauthn: Option<bool>,
+    max_code_attempts: Option<u32>,
+    resend_new_code: Option<bool>,
+    confirm_device: Option<ConfirmDevice>,
+    email_mode: Option<EmailMode>,
+    code_alphabet: Option<CodeAlphabet>,
+    code_length: Option<usize>,
+    code_group_size: Option<usize>,
+    clients: Option<Vec<TomlClientEntry>>,
 
     static_ttl: Option<u64>,
     discovery_ttl: Option<u64>,
@@ -34,7 +48,12 @@ pub struct TomlConfig {
     token_ttl: Option<u64>,
     session_ttl: Option<u64>,
     auth_code_ttl: Option<u64>,
+    pushed_request_ttl: Option<u64>,
+    device_code_ttl: Option<u64>,
+    login_cookie_ttl: Option<u64>,
     cache_ttl: Option<u64>,
+    webfinger_timeout: Option<u64>,
+    provider_timeout: Option<u64>,
 
     keyfiles: Option<Vec<PathBuf>>,
     keytext: Option<String>,
@@ -65,7 +84,8 @@ pub struct TomlConfig {
     limit_per_email: Option<LegacyLimitPerEmail>,
 
     google_client_id: Option<String>,
-    domain_overrides: Option<HashMap<String, Vec<Link>>>,
+    dns_discovery: Option<bool>,
+    domain_overrides: Option<HashMap<String, Vec<RawDomainOverrideLink>>>,
 
     // Deprecated.
     server: Option<TomlServerTable>,
@@ -77,6 +97,16 @@ pub struct TomlConfig {
     google: Option<TomlGoogleTable>,
 }
 
+/// An entry in the `clients` list, which is either a table or a string.
+///
+/// Strings are parsed like in the environment, and may also be files prefixed with `@`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TomlClientEntry {
+    Table(RawClientConfig),
+    String(String),
+}
+
 #[derive(Deserialize)]
 struct TomlServerTable {
     listen_ip: Option<String>,
@@ -294,6 +324,55 @@ impl TomlConfig {
         if let Some(val) = parsed.allowed_domains_only {
             builder.domain_validator.allowed_domains_only = val;
         }
+        if let Some(val) = parsed.require_pkce {
+            builder.require_pkce = val;
+        }
+        if let Some(val) = parsed.webauthn {
+            builder.webauthn = val;
+        }
+        if let Some(val) = parsed.max_code_attempts {
+            builder.max_code_attempts = val;
+        }
+        if let Some(val) = parsed.resend_new_code {
+            builder.resend_new_code = val;
+        }
+        if let Some(val) = parsed.confirm_device {
+            builder.confirm_device = val;
+        }
+        if let Some(val) = parsed.email_mode {
+            builder.email_mode = val;
+        }
+        if let Some(val) = parsed.code_alphabet {
+            builder.code_alphabet = val;
+        }
+        if let Some(val) = parsed.code_length {
+            builder.code_length = val;
+        }
+        if let Some(val) = parsed.code_group_size {
+            builder.code_group_size = val;
+        }
+        if let Some(val) = parsed.clients {
+            let mut strings = vec![];
+            for (idx, entry) in val.into_iter().enumerate() {
+                match entry {
+                    TomlClientEntry::Table(raw) => match ClientConfig::try_from(raw) {
+                        Ok(client) => builder.clients.push(client),
+                        Err(err) => panic!("Invalid clients entry #{}: {err}", idx + 1),
+                    },
+                    TomlClientEntry::String(data) => strings.push(data),
+                }
+            }
+            for (source, res) in StringList::from(strings).iter_values() {
+                let data = match res {
+                    Ok(data) => data,
+                    Err(err) => panic!("IO error in clients entry {source}: {err}"),
+                };
+                match data.parse() {
+                    Ok(client) => builder.clients.push(client),
+                    Err(err) => panic!("Invalid clients entry {source}: {err}"),
+                }
+            }
+        }
 
         if let Some(val) = parsed.static_ttl {
             builder.static_ttl = Duration::from_secs(val);
@@ -313,9 +392,24 @@ impl TomlConfig {
         if let Some(val) = parsed.auth_code_ttl {
             builder.auth_code_ttl = Duration::from_secs(val);
         }
+        if let Some(val) = parsed.pushed_request_ttl {
+            builder.pushed_request_ttl = Duration::from_secs(val);
+        }
+        if let Some(val) = parsed.device_code_ttl {
+            builder.device_code_ttl = Duration::from_secs(val);
+        }
+        if let Some(val) = parsed.login_cookie_ttl {
+            builder.login_cookie_ttl = Duration::from_secs(val);
+        }
         if let Some(val) = parsed.cache_ttl {
             builder.cache_ttl = Duration::from_secs(val);
         }
+        if let Some(val) = parsed.webfinger_timeout {
+            builder.webfinger_timeout = Duration::from_secs(val);
+        }
+        if let Some(val) = parsed.provider_timeout {
+            builder.provider_timeout = Duration::from_secs(val);
+        }
 
         if let Some(mut val) = parsed.keyfiles {
             builder.keyfiles.append(&mut val);
@@ -389,8 +483,24 @@ impl TomlConfig {
         if let Some(val) = parsed.google_client_id {
             builder.google_client_id = Some(val);
         }
+        if let Some(val) = parsed.dns_discovery {
+            builder.dns_discovery = val;
+        }
         if let Some(val) = parsed.domain_overrides {
-            for (domain, links) in val {
+            for (domain, raw_links) in val {
+                let mut links = Vec::with_capacity(raw_links.len());
+                for raw in raw_links {
+                    let (link, provider) = match raw.into_parts() {
+                        Ok(parts) => parts,
+                        Err(err) => panic!("Invalid domain override for {domain}: {err}"),
+                    };
+                    if let Some(provider) = provider {
+                        builder
+                            .oidc_providers
+                            .insert(provider.issuer.clone(), provider);
+                    }
+                    links.push(link);
+                }
                 builder.domain_overrides.insert(domain, links);
             }
         }
@@ -1,35 +1,71 @@
 use crate::agents::SignJws;
 use crate::bridges::oidc::ProviderKey;
-use crate::config::Config;
+use crate::config::{ClientConfig, Config, SubjectType};
 use crate::email_address::EmailAddress;
 use crate::utils::{base64url, keys::SignError, unix_duration, SecureRandom};
 use ring::{
-    digest,
+    constant_time, digest,
     error::Unspecified,
+    hmac,
     signature::{self, UnparsedPublicKey},
 };
+use serde::{Deserialize, Serialize};
 use serde_json as json;
 use serde_json::{json, Error as JsonError, Value};
 use std::fmt;
 use std::iter::Iterator;
+use std::time::Duration;
 use thiserror::Error;
 
 type RsaPublicKey = signature::RsaPublicKeyComponents<Vec<u8>>;
+type RsaParameters = &'static signature::RsaParameters;
 
 /// Token signing algorithms we support.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum SigningAlgorithm {
     EdDsa,
+    Es256,
+    Es384,
     Rs256,
+    Ps256,
+    Ps384,
+    Ps512,
 }
 
 impl SigningAlgorithm {
+    /// All algorithms we support.
+    pub const ALL: &'static [SigningAlgorithm] = &[
+        SigningAlgorithm::EdDsa,
+        SigningAlgorithm::Es256,
+        SigningAlgorithm::Es384,
+        SigningAlgorithm::Rs256,
+        SigningAlgorithm::Ps256,
+        SigningAlgorithm::Ps384,
+        SigningAlgorithm::Ps512,
+    ];
+
     /// Get the JWA string representation.
     pub fn as_str(self) -> &'static str {
         use SigningAlgorithm::*;
         match self {
             EdDsa => "EdDSA",
+            Es256 => "ES256",
+            Es384 => "ES384",
             Rs256 => "RS256",
+            Ps256 => "PS256",
+            Ps384 => "PS384",
+            Ps512 => "PS512",
+        }
+    }
+
+    /// Get the algorithm that identifies the type of key used by this algorithm.
+    ///
+    /// Keys are managed per type, so all RSA algorithms share the RS256 keys.
+    pub fn key_alg(self) -> SigningAlgorithm {
+        use SigningAlgorithm::*;
+        match self {
+            Rs256 | Ps256 | Ps384 | Ps512 => Rs256,
+            other => other,
         }
     }
 
@@ -54,7 +90,12 @@ impl std::str::FromStr for SigningAlgorithm {
         use SigningAlgorithm::*;
         match s {
             "EdDSA" => Ok(EdDsa),
+            "ES256" => Ok(Es256),
+            "ES384" => Ok(Es384),
             "RS256" => Ok(Rs256),
+            "PS256" => Ok(Ps256),
+            "PS384" => Ok(Ps384),
+            "PS512" => Ok(Ps512),
             _ => Err("unsupported value"),
         }
     }
@@ -63,10 +104,88 @@ impl std::str::FromStr for SigningAlgorithm {
 serde_from_str!(SigningAlgorithm);
 serde_display!(SigningAlgorithm);
 
+/// PKCE code challenge methods we support. (RFC 7636)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CodeChallengeMethod {
+    Plain,
+    S256,
+}
+
+impl CodeChallengeMethod {
+    /// Get the string representation used in requests.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CodeChallengeMethod::Plain => "plain",
+            CodeChallengeMethod::S256 => "S256",
+        }
+    }
+}
+
+impl fmt::Display for CodeChallengeMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for CodeChallengeMethod {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<CodeChallengeMethod, &'static str> {
+        match s {
+            "plain" => Ok(CodeChallengeMethod::Plain),
+            "S256" => Ok(CodeChallengeMethod::S256),
+            _ => Err("unsupported value"),
+        }
+    }
+}
+
+serde_from_str!(CodeChallengeMethod);
+serde_display!(CodeChallengeMethod);
+
+/// A PKCE code challenge sent by the RP in the authorization request.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CodeChallenge {
+    pub method: CodeChallengeMethod,
+    pub challenge: String,
+}
+
+impl CodeChallenge {
+    /// Check that a string is a syntactically valid code verifier or challenge.
+    ///
+    /// Both are 43 to 128 characters from the unreserved URI character set.
+    pub fn is_valid_syntax(input: &str) -> bool {
+        (43..=128).contains(&input.len())
+            && input
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || b"-._~".contains(&c))
+    }
+
+    /// Create an S256 challenge for a code verifier.
+    pub fn s256(verifier: &str) -> Self {
+        CodeChallenge {
+            method: CodeChallengeMethod::S256,
+            challenge: base64url::encode(&digest::digest(&digest::SHA256, verifier.as_bytes())),
+        }
+    }
+
+    /// Verify a code verifier sent to the token endpoint against this challenge.
+    pub fn verify(&self, verifier: &str) -> bool {
+        if !Self::is_valid_syntax(verifier) {
+            return false;
+        }
+        let expected = match self.method {
+            CodeChallengeMethod::Plain => verifier.to_owned(),
+            CodeChallengeMethod::S256 => Self::s256(verifier).challenge,
+        };
+        constant_time::verify_slices_are_equal(expected.as_bytes(), self.challenge.as_bytes())
+            .is_ok()
+    }
+}
+
 /// The types of public keys we support.
 pub enum SupportedPublicKey {
     Ed25519(UnparsedPublicKey<Vec<u8>>),
-    Rsa(RsaPublicKey),
+    Ecdsa(UnparsedPublicKey<Vec<u8>>),
+    Rsa(RsaPublicKey, RsaParameters),
 }
 
 impl SupportedPublicKey {
@@ -74,10 +193,8 @@ impl SupportedPublicKey {
     pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), Unspecified> {
         use SupportedPublicKey::*;
         match self {
-            Ed25519(ref inner) => inner.verify(message, signature),
-            Rsa(ref inner) => {
-                inner.verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, signature)
-            }
+            Ed25519(ref inner) | Ecdsa(ref inner) => inner.verify(message, signature),
+            Rsa(ref inner, params) => inner.verify(params, message, signature),
         }
     }
 }
@@ -102,18 +219,52 @@ pub async fn nonce(rng: &SecureRandom) -> String {
     base64url::encode(&rand_bytes)
 }
 
+/// Helper function to create a PKCE code verifier. (RFC 7636)
+pub async fn code_verifier(rng: &SecureRandom) -> String {
+    let rand_bytes = rng.generate_async(32).await;
+    base64url::encode(&rand_bytes)
+}
+
+/// The z-base-32 character set.
+pub const ZBASE32_CHARSET: &[u8] = b"13456789abcdefghijkmnopqrstuwxyz";
+
 /// Helper function to create a random string consisting of
 /// characters from the z-base-32 set.
 pub async fn random_zbase32(len: usize, rng: &SecureRandom) -> String {
-    const CHARSET: &[u8] = b"13456789abcdefghijkmnopqrstuwxyz";
-    let rand_bytes = rng.generate_async(len).await;
+    random_code(ZBASE32_CHARSET, len, rng).await
+}
+
+/// Helper function to create a random string consisting of characters from the given set.
+///
+/// Random bytes that would bias the result towards some characters are discarded, so the
+/// character set may be of any size up to 256.
+pub async fn random_code(charset: &[u8], len: usize, rng: &SecureRandom) -> String {
+    let limit = 256 - 256 % charset.len();
+    let mut code = Vec::with_capacity(len);
+    while code.len() < len {
+        for v in rng.generate_async(len - code.len()).await {
+            if (v as usize) < limit {
+                code.push(charset[v as usize % charset.len()]);
+            }
+        }
+    }
+    String::from_utf8(code).expect("failed to build one-time pad")
+}
+
+/// Helper function to create a user code for device authorization.
+///
+/// The code is 8 uppercase consonants, which are easy to type on any keyboard, and unlikely to
+/// form words.
+pub async fn user_code(rng: &SecureRandom) -> String {
+    const CHARSET: &[u8] = b"BCDFGHJKLMNPRSTW";
+    let rand_bytes = rng.generate_async(8).await;
     String::from_utf8(
         rand_bytes
             .into_iter()
             .map(|v| CHARSET[v as usize % CHARSET.len()])
             .collect(),
     )
-    .expect("failed to build one-time pad")
+    .expect("failed to build user code")
 }
 
 #[derive(Debug, Error)]
@@ -127,6 +278,8 @@ pub enum VerifyError {
     },
     #[error("the token header contained invalid JSON: {0}")]
     InvalidHeaderJson(JsonError),
+    #[error("the token header does not specify a supported 'alg'")]
+    UnsupportedAlgorithm,
     #[error("did not find a string 'kid' property in the token header")]
     KidMissing,
     #[error("the token 'kid' could not be found in the JWKs document: {kid}")]
@@ -148,6 +301,40 @@ pub enum VerifyError {
     InvalidPayloadJson(JsonError),
 }
 
+/// Build an ECDSA public key from the coordinates in a JWK.
+fn ecdsa_public_key(
+    key: &ProviderKey,
+    alg: &'static signature::EcdsaVerificationAlgorithm,
+) -> Result<UnparsedPublicKey<Vec<u8>>, VerifyError> {
+    let x = base64url::decode(&key.x).map_err(|reason| VerifyError::InvalidJwkBase64 {
+        property: "x",
+        reason,
+    })?;
+    let y = base64url::decode(&key.y).map_err(|reason| VerifyError::InvalidJwkBase64 {
+        property: "y",
+        reason,
+    })?;
+    // Ring expects an uncompressed point: a 0x04 tag, followed by X and Y.
+    let mut point = Vec::with_capacity(1 + x.len() + y.len());
+    point.push(0x04);
+    point.extend_from_slice(&x);
+    point.extend_from_slice(&y);
+    Ok(UnparsedPublicKey::new(alg, point))
+}
+
+/// Read the signing algorithm from the header of a JWS, without verifying the signature.
+pub fn jws_signing_alg(jws: &str) -> Result<SigningAlgorithm, VerifyError> {
+    let header = jws.split('.').next().unwrap_or_default();
+    let header = base64url::decode(header)
+        .map_err(|reason| VerifyError::InvalidPartBase64 { index: 1, reason })?;
+    let header: json::Value = json::from_slice(&header).map_err(VerifyError::InvalidHeaderJson)?;
+    header
+        .get("alg")
+        .and_then(Value::as_str)
+        .and_then(|alg| alg.parse().ok())
+        .ok_or(VerifyError::UnsupportedAlgorithm)
+}
+
 /// Verify a JWS signature, returning the payload as a `Value` if successful.
 pub fn verify_jws(
     jws: &str,
@@ -202,7 +389,22 @@ pub fn verify_jws(
             let key = UnparsedPublicKey::new(&signature::ED25519, x);
             SupportedPublicKey::Ed25519(key)
         }
-        (SigningAlgorithm::Rs256, "RS256", _) => {
+        (SigningAlgorithm::Es256, "ES256", "P-256") => {
+            SupportedPublicKey::Ecdsa(ecdsa_public_key(key, &signature::ECDSA_P256_SHA256_FIXED)?)
+        }
+        (SigningAlgorithm::Es384, "ES384", "P-384") => {
+            SupportedPublicKey::Ecdsa(ecdsa_public_key(key, &signature::ECDSA_P384_SHA384_FIXED)?)
+        }
+        (SigningAlgorithm::Rs256, "RS256", _)
+        | (SigningAlgorithm::Ps256, "PS256", _)
+        | (SigningAlgorithm::Ps384, "PS384", _)
+        | (SigningAlgorithm::Ps512, "PS512", _) => {
+            let params: RsaParameters = match signing_alg {
+                SigningAlgorithm::Ps256 => &signature::RSA_PSS_2048_8192_SHA256,
+                SigningAlgorithm::Ps384 => &signature::RSA_PSS_2048_8192_SHA384,
+                SigningAlgorithm::Ps512 => &signature::RSA_PSS_2048_8192_SHA512,
+                _ => &signature::RSA_PKCS1_2048_8192_SHA256,
+            };
             let n = base64url::decode(&key.n).map_err(|reason| VerifyError::InvalidJwkBase64 {
                 property: "n",
                 reason,
@@ -212,7 +414,7 @@ pub fn verify_jws(
                 reason,
             })?;
             let key = RsaPublicKey { n, e };
-            SupportedPublicKey::Rsa(key)
+            SupportedPublicKey::Rsa(key, params)
         }
         _ => return Err(VerifyError::UnsupportedKeyType),
     };
@@ -227,8 +429,24 @@ pub fn verify_jws(
     json::from_slice(&decoded[1]).map_err(VerifyError::InvalidPayloadJson)
 }
 
+/// Derive a pairwise subject identifier for an email address. (OpenID Connect Core, section 8.1)
+///
+/// The sector identifier is the RP origin. The result is stable for the same email address and
+/// sector, but cannot be correlated across sectors without knowing the secret.
+pub fn pairwise_subject(secret: &[u8], sector: &str, email: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    let mut ctx = hmac::Context::with_key(&key);
+    ctx.update(sector.as_bytes());
+    ctx.update(b"\0");
+    ctx.update(email.as_bytes());
+    base64url::encode(&ctx.sign())
+}
+
 /// Helper method to create a JWT for a given email address and audience.
 ///
+/// The `auth_time` is when the user verified the email address, which may be earlier than now if
+/// the browser remembered a previous verification.
+///
 /// Builds the JSON payload, then signs it using the last key provided in the configuration object.
 ///
 /// Currently, the only possible failure here is that we accepted a signing algorithm from the RP
@@ -240,23 +458,266 @@ pub async fn create_jwt(
     aud: &str,
     nonce: &Option<String>,
     signing_alg: SigningAlgorithm,
+    auth_time: u64,
+) -> Result<String, SignError> {
+    let now = unix_duration();
+    let client = app.clients.get(aud);
+    let sub = match client.map(ClientConfig::effective_subject_type) {
+        Some(SubjectType::Pairwise) => {
+            pairwise_subject(&app.pairwise_secret, aud, email_addr.as_str())
+        }
+        Some(SubjectType::Public) | None => email_addr.as_str().to_owned(),
+    };
+    let mut payload = json!({
+        "aud": aud,
+        "email_verified": true,
+        "exp": (now + app.token_ttl).as_secs(),
+        "iat": now.as_secs(),
+        "iss": &app.public_url,
+        "sub": sub,
+        "nonce": nonce,
+        "auth_time": auth_time,
+    });
+    // In privacy mode, the client only learns the domain of the email address.
+    if client.is_some_and(|client| client.privacy) {
+        payload["email_domain"] = email_addr.domain().into();
+    } else {
+        payload["email"] = email_addr.as_str().into();
+        payload["email_original"] = email.into();
+    }
+    app.key_manager
+        .send(SignJws {
+            payload,
+            signing_alg,
+        })
+        .await
+}
+
+/// Lifetime of JWT secured authorization responses. (JARM)
+const JARM_TTL: Duration = Duration::from_secs(600);
+
+/// Select the algorithm to sign JWT secured authorization responses for a client with. (JARM)
+///
+/// This is the first enabled algorithm the client is allowed to use, if any.
+pub fn jarm_signing_alg(app: &Config, aud: &str) -> Option<SigningAlgorithm> {
+    let client = app.clients.get(aud);
+    app.signing_algs
+        .iter()
+        .copied()
+        .find(|&alg| client.map_or(true, |client| client.allows_signing_alg(alg)))
+}
+
+/// Helper method to wrap authorization response parameters in a JWT. (JARM)
+///
+/// The JWT is signed with the algorithm selected by `jarm_signing_alg`.
+pub async fn create_jarm_response(
+    app: &Config,
+    aud: &str,
+    params: &[(&str, &str)],
 ) -> Result<String, SignError> {
+    let signing_alg = jarm_signing_alg(app, aud).ok_or(SignError::NoAllowedAlgorithm)?;
     let now = unix_duration();
+    let mut payload = json!({
+        "iss": &app.public_url,
+        "aud": aud,
+        "exp": (now + JARM_TTL).as_secs(),
+    });
+    for &(name, value) in params {
+        payload[name] = value.into();
+    }
     app.key_manager
         .send(SignJws {
-            payload: json!({
-                "aud": aud,
-                "email": email_addr.as_str(),
-                "email_verified": true,
-                "email_original": email,
-                "exp": (now + app.token_ttl).as_secs(),
-                "iat": now.as_secs(),
-                "iss": &app.public_url,
-                "sub": email_addr.as_str(),
-                "nonce": nonce,
-                "auth_time": now.as_secs(),
-            }),
+            payload,
             signing_alg,
         })
         .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        create_jwt, pairwise_subject, verify_jws, CodeChallenge, CodeChallengeMethod,
+        SigningAlgorithm,
+    };
+    use crate::agents::GetPublicJwks;
+    use crate::bridges::oidc::ProviderKey;
+    use crate::email_address::EmailAddress;
+    #[cfg(feature = "rsa")]
+    use crate::utils::keys::GenerateRsaConfig;
+    use crate::utils::keys::{EcdsaP256KeyPair, EcdsaP384KeyPair, GeneratedKeyPair, NamedKeyPair};
+    use crate::utils::testing::test_builder;
+    use crate::utils::{pem, SecureRandom};
+    use ring::rand::SystemRandom;
+    #[cfg(feature = "rsa")]
+    use ring::signature::RsaKeyPair;
+    use serde_json::json;
+
+    fn sign_and_verify<T: GeneratedKeyPair>(config: T::Config, signing_algs: &[SigningAlgorithm]) {
+        let rng = SecureRandom {
+            generator: SystemRandom::new(),
+        };
+        let pem = T::generate(config);
+        let entry = pem::parse_key_pairs(pem.as_bytes())
+            .unwrap()
+            .pop()
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.key_pair.signing_alg(), signing_algs[0].key_alg());
+        let key_pair = NamedKeyPair::new(T::from_parsed(entry.key_pair).unwrap(), signing_algs);
+
+        let mut keys = vec![];
+        key_pair.append_public_jwks(&mut keys);
+        assert_eq!(keys.len(), signing_algs.len());
+        for (&signing_alg, key) in signing_algs.iter().zip(keys) {
+            let payload = json!({ "foo": "bar" });
+            let jws = key_pair.sign_jws(signing_alg, &payload, &rng).unwrap();
+            let key: ProviderKey = serde_json::from_value(key).unwrap();
+            assert_eq!(verify_jws(&jws, &[key], signing_alg).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn test_ecdsa_round_trip() {
+        let rng = SecureRandom {
+            generator: SystemRandom::new(),
+        };
+        sign_and_verify::<EcdsaP256KeyPair>(rng.clone(), &[SigningAlgorithm::Es256]);
+        sign_and_verify::<EcdsaP384KeyPair>(rng, &[SigningAlgorithm::Es384]);
+    }
+
+    #[cfg(feature = "rsa")]
+    #[test]
+    fn test_rsa_round_trip() {
+        let config = GenerateRsaConfig {
+            rng: SecureRandom {
+                generator: SystemRandom::new(),
+            },
+            modulus_bits: 2048,
+            command: vec![],
+        };
+        sign_and_verify::<RsaKeyPair>(
+            config,
+            &[
+                SigningAlgorithm::Rs256,
+                SigningAlgorithm::Ps256,
+                SigningAlgorithm::Ps384,
+                SigningAlgorithm::Ps512,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_code_challenge_s256() {
+        // Example from RFC 7636, Appendix B.
+        let challenge = CodeChallenge {
+            method: CodeChallengeMethod::S256,
+            challenge: "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM".to_owned(),
+        };
+        assert!(challenge.verify("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"));
+        assert!(!challenge.verify("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXl"));
+        assert!(!challenge.verify("E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"));
+    }
+
+    #[test]
+    fn test_code_challenge_plain() {
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = CodeChallenge {
+            method: CodeChallengeMethod::Plain,
+            challenge: verifier.to_owned(),
+        };
+        assert!(challenge.verify(verifier));
+        assert!(!challenge.verify("E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"));
+    }
+
+    #[test]
+    fn test_code_challenge_create() {
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = CodeChallenge::s256(verifier);
+        assert_eq!(
+            challenge.challenge,
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+        assert!(challenge.verify(verifier));
+    }
+
+    #[test]
+    fn test_code_verifier_syntax() {
+        assert!(!CodeChallenge::is_valid_syntax("tooshort"));
+        assert!(!CodeChallenge::is_valid_syntax(&"a".repeat(129)));
+        assert!(!CodeChallenge::is_valid_syntax(&"+".repeat(43)));
+        assert!(CodeChallenge::is_valid_syntax(&"a.b_c~d-".repeat(6)));
+    }
+
+    #[test]
+    fn test_pairwise_subject() {
+        let sub = pairwise_subject(b"secret", "https://a.example", "john@example.com");
+        assert_eq!(sub.len(), 43);
+        assert_eq!(
+            sub,
+            pairwise_subject(b"secret", "https://a.example", "john@example.com")
+        );
+        assert_ne!(
+            sub,
+            pairwise_subject(b"secret", "https://b.example", "john@example.com")
+        );
+        assert_ne!(
+            sub,
+            pairwise_subject(b"other", "https://a.example", "john@example.com")
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_privacy_claims() {
+        let mut builder = test_builder();
+        builder.clients = vec![
+            "client_id=https://private.example redirect_uri=https://private.example/callback \
+             privacy=true"
+                .parse()
+                .unwrap(),
+            "client_id=https://public.example redirect_uri=https://public.example/callback"
+                .parse()
+                .unwrap(),
+        ];
+        let app = builder.done().await.unwrap();
+        let keys: Vec<ProviderKey> = app
+            .key_manager
+            .send(GetPublicJwks)
+            .await
+            .jwks
+            .into_iter()
+            .map(|jwk| serde_json::from_value(jwk).unwrap())
+            .collect();
+
+        let email_addr: EmailAddress = "John.Doe@Example.com".parse().unwrap();
+        let claims = |aud: &'static str| {
+            let (app, keys, email_addr) = (&app, &keys, &email_addr);
+            async move {
+                let jwt = create_jwt(
+                    app,
+                    "John.Doe@Example.com",
+                    email_addr,
+                    aud,
+                    &Some("nonce".to_owned()),
+                    SigningAlgorithm::EdDsa,
+                    0,
+                )
+                .await
+                .unwrap();
+                verify_jws(&jwt, keys, SigningAlgorithm::EdDsa).unwrap()
+            }
+        };
+
+        let private = claims("https://private.example").await;
+        assert_eq!(private["email_domain"], "example.com");
+        assert!(private.get("email").is_none());
+        assert!(private.get("email_original").is_none());
+        assert_ne!(private["sub"], email_addr.as_str());
+        assert!(!private["sub"].as_str().unwrap().contains("example.com"));
+
+        let public = claims("https://public.example").await;
+        assert_eq!(public["email"], email_addr.as_str());
+        assert_eq!(public["email_original"], "John.Doe@Example.com");
+        assert_eq!(public["sub"], email_addr.as_str());
+        assert!(public.get("email_domain").is_none());
+    }
+}
@@ -15,6 +15,8 @@ pub enum BrokerError {
         error: String,
         error_description: String,
     },
+    /// Client authentication failure, which results in 401
+    ClientAuth(String),
     /// Identity provider error, which results in 503
     Provider(String),
     /// Identity provider request error, which results in 400
@@ -25,6 +27,8 @@ pub enum BrokerError {
     RateLimited,
     /// User session not found, results in 400
     SessionExpired,
+    /// User session invalidated after too many incorrect codes, results in 400
+    SessionLocked,
     /// Result status used by bridges to cancel a request
     ProviderCancelled,
 }
@@ -37,9 +41,11 @@ impl BrokerError {
             // User errors only at debug level.
             BrokerError::Input(_)
             | BrokerError::SpecificInput { .. }
+            | BrokerError::ClientAuth(_)
             | BrokerError::ProviderInput(_)
             | BrokerError::RateLimited
             | BrokerError::SessionExpired
+            | BrokerError::SessionLocked
             | BrokerError::ProviderCancelled => {
                 debug!("{}", self);
                 None
@@ -70,7 +76,9 @@ impl BrokerError {
             BrokerError::Input(_)
             | BrokerError::SpecificInput { .. }
             | BrokerError::SessionExpired
+            | BrokerError::SessionLocked
             | BrokerError::ProviderInput(_) => StatusCode::BAD_REQUEST,
+            BrokerError::ClientAuth(_) => StatusCode::UNAUTHORIZED,
             BrokerError::Provider(_) => StatusCode::SERVICE_UNAVAILABLE,
             BrokerError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
             BrokerError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
@@ -84,9 +92,10 @@ impl BrokerError {
         match *self {
             BrokerError::Input(_) | BrokerError::SessionExpired => "invalid_request",
             BrokerError::SpecificInput { ref error, .. } => error,
+            BrokerError::ClientAuth(_) => "invalid_client",
             BrokerError::Provider(_) | BrokerError::ProviderInput(_) => "temporarily_unavailable",
             BrokerError::Internal(_) => "server_error",
-            BrokerError::RateLimited => "access_denied",
+            BrokerError::RateLimited | BrokerError::SessionLocked => "access_denied",
             // Internal status that should never bubble this far
             BrokerError::ProviderCancelled => unreachable!(),
         }
@@ -99,6 +108,7 @@ impl fmt::Display for BrokerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str(match *self {
             BrokerError::Input(ref description)
+            | BrokerError::ClientAuth(ref description)
             | BrokerError::Provider(ref description)
             | BrokerError::ProviderInput(ref description)
             | BrokerError::Internal(ref description) => description,
@@ -108,6 +118,7 @@ impl fmt::Display for BrokerError {
             } => error_description,
             BrokerError::RateLimited => "too many requests",
             BrokerError::SessionExpired => "session has expired",
+            BrokerError::SessionLocked => "too many incorrect codes",
             BrokerError::ProviderCancelled => "bridge cancelled the request",
         })
     }
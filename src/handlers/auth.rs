@@ -1,22 +1,28 @@
 use crate::agents::{GetPublicJwks, IncrAndTestLimits};
-use crate::config::LimitInput;
-use crate::crypto::SigningAlgorithm;
+use crate::client_auth::CLIENT_AUTH_METHODS;
+use crate::config::{ClientConfig, Config, LimitInput, LimitKind};
+use crate::crypto::{self, CodeChallenge, CodeChallengeMethod, SigningAlgorithm};
 use crate::email_address::EmailAddress;
-use crate::error::BrokerError;
+use crate::error::{BrokerError, BrokerResult};
+use crate::handlers::device::DEVICE_CODE_GRANT_TYPE;
+use crate::login_cookie;
+use crate::request_object;
 use crate::utils::http::ResponseExt;
 use crate::utils::DomainValidationError;
 use crate::validation::parse_redirect_uri;
 use crate::web::{
-    html_response, json_response, Context, HandlerResult, ResponseMode, ResponseType, ReturnParams,
+    html_response, json_response, Context, HandlerResult, ResponseType, ReturnParams,
 };
 use crate::webfinger::{self, Relation};
 use crate::{bridges, metrics};
 use headers::{CacheControl, Expires};
 use http::Method;
-use log::info;
+use log::{error, info};
 use serde_json::json;
 use std::collections::HashSet;
+use std::future::Future;
 use std::time::Duration;
+use url::Url;
 
 /// Request handler to return the OpenID Discovery document.
 ///
@@ -27,14 +33,34 @@ pub async fn discovery(ctx: &mut Context) -> HandlerResult {
         "issuer": ctx.app.public_url,
         "authorization_endpoint": format!("{}/auth", ctx.app.public_url),
         "token_endpoint": format!("{}/token", ctx.app.public_url),
-        "token_endpoint_auth_methods_supported": ["client_secret_basic"],
+        "device_authorization_endpoint": format!("{}/device_authorization", ctx.app.public_url),
+        "pushed_authorization_request_endpoint": format!("{}/par", ctx.app.public_url),
+        "token_endpoint_auth_methods_supported": CLIENT_AUTH_METHODS,
+        "token_endpoint_auth_signing_alg_values_supported": SigningAlgorithm::ALL,
+        "introspection_endpoint": format!("{}/introspect", ctx.app.public_url),
+        "introspection_endpoint_auth_methods_supported":
+            vec!["client_secret_basic", "client_secret_post", "private_key_jwt"],
         "jwks_uri": format!("{}/keys.json", ctx.app.public_url),
         "scopes_supported": vec!["openid", "email"],
-        "claims_supported": vec!["iss", "aud", "exp", "iat", "email"],
+        "claims_supported": vec!["iss", "aud", "exp", "iat", "auth_time", "email", "email_domain"],
         "response_types_supported": vec!["id_token", "code"],
-        "response_modes_supported": vec!["form_post", "fragment", "query"],
-        "grant_types_supported": vec!["implicit", "authorization_code"],
-        "subject_types_supported": vec!["public"],
+        "response_modes_supported": vec![
+            "form_post",
+            "fragment",
+            "query",
+            "form_post.jwt",
+            "fragment.jwt",
+            "query.jwt",
+            "jwt",
+        ],
+        "authorization_signing_alg_values_supported": &ctx.app.signing_algs,
+        "grant_types_supported": vec!["implicit", "authorization_code", DEVICE_CODE_GRANT_TYPE],
+        "code_challenge_methods_supported": vec!["S256", "plain"],
+        "request_parameter_supported": true,
+        "request_uri_parameter_supported": true,
+        "require_request_uri_registration": false,
+        "request_object_signing_alg_values_supported": SigningAlgorithm::ALL,
+        "subject_types_supported": vec!["public", "pairwise"],
         "id_token_signing_alg_values_supported": &ctx.app.signing_algs,
         // NOTE: This field is non-standard.
         "accepts_id_token_signing_alg_query_param": true,
@@ -71,6 +97,65 @@ pub async fn key_set(ctx: &mut Context) -> HandlerResult {
     Ok(res)
 }
 
+/// Validate the `client_id` and `redirect_uri` of an authorization request.
+///
+/// If clients are registered, the `redirect_uri` must be an exact match. This must be checked
+/// before we can redirect to the RP.
+pub fn validate_client<'a>(
+    app: &'a Config,
+    client_id: &str,
+    redirect_uri: &str,
+) -> BrokerResult<(Url, Option<&'a ClientConfig>)> {
+    let redirect_uri = parse_redirect_uri(redirect_uri, "redirect_uri")
+        .map_err(|e| BrokerError::Input(format!("{e}")))?;
+
+    if client_id != redirect_uri.origin().ascii_serialization() {
+        return Err(BrokerError::Input(
+            "the client_id must be the origin of the redirect_uri".to_owned(),
+        ));
+    }
+
+    if app.clients.is_empty() {
+        return Ok((redirect_uri, None));
+    }
+    let client = app
+        .clients
+        .get(client_id)
+        .ok_or_else(|| BrokerError::Input("the client_id is not registered".to_owned()))?;
+    if !client.redirect_uris.contains(&redirect_uri) {
+        return Err(BrokerError::Input(
+            "the redirect_uri is not registered for this client".to_owned(),
+        ));
+    }
+    Ok((redirect_uri, Some(client)))
+}
+
+/// Parse the `id_token_signing_alg` parameter, and check it is enabled and allowed for the client.
+pub fn parse_signing_alg(
+    app: &Config,
+    client: Option<&ClientConfig>,
+    value: &str,
+) -> BrokerResult<SigningAlgorithm> {
+    let signing_alg = value
+        .parse()
+        .ok()
+        .filter(|alg| app.signing_algs.contains(alg))
+        .ok_or_else(|| {
+            BrokerError::Input(format!(
+                "unsupported id_token_signing_alg, must be one of: {}",
+                SigningAlgorithm::format_list(&app.signing_algs)
+            ))
+        })?;
+    if let Some(client) = client {
+        if !client.allows_signing_alg(signing_alg) {
+            return Err(BrokerError::Input(format!(
+                "the client is not allowed to use id_token_signing_alg={signing_alg}"
+            )));
+        }
+    }
+    Ok(signing_alg)
+}
+
 /// Request handler for authentication requests from the RP.
 ///
 /// Calls the `oidc::request()` function if the provided email address's
@@ -83,43 +168,46 @@ pub async fn auth(ctx: &mut Context) -> HandlerResult {
         _ => unreachable!(),
     };
 
+    // A pushed authorization request replaces all parameters.
+    request_object::resume_pushed_request(ctx, &mut params).await?;
+
     let original_params = params.clone();
 
+    // Claims from a request object replace query parameters. This happens before we validate
+    // redirect_uri, because it may be part of the request object.
+    request_object::merge_request_object(ctx, &mut params).await?;
+
     let redirect_uri = try_get_input_param!(params, "redirect_uri");
     let client_id = try_get_input_param!(params, "client_id");
     let response_errors = try_get_input_param!(params, "response_errors", "true".to_owned());
     let state = try_get_input_param!(params, "state", String::new());
     let prompt = try_get_input_param!(params, "prompt", String::new());
+    let max_age = try_get_input_param!(params, "max_age", String::new());
 
-    let response_type = match try_get_input_param!(params, "response_type").as_str() {
-        "id_token" => ResponseType::IdToken,
-        "code" => ResponseType::Code,
-        _ => {
-            return Err(BrokerError::Input(
-                "unsupported response_type, must be id_token or code".to_owned(),
-            ))
-        }
-    };
+    let response_type: ResponseType = try_get_input_param!(params, "response_type")
+        .parse()
+        .map_err(|err: &str| BrokerError::Input(err.to_owned()))?;
 
-    let response_mode = match try_get_input_param!(params, "response_mode", String::new()).as_str()
-    {
-        "" => response_type.default_response_mode(),
-        "fragment" => ResponseMode::Fragment,
-        "form_post" => ResponseMode::FormPost,
-        "query" => ResponseMode::Query,
-        _ => {
-            return Err(BrokerError::Input(
-                "unsupported response_mode, must be fragment, form_post or query".to_owned(),
-            ))
-        }
+    let response_mode = try_get_input_param!(params, "response_mode", String::new());
+    let response_mode = if response_mode.is_empty() {
+        response_type.default_response_mode()
+    } else if response_mode == "jwt" {
+        response_type.default_response_mode().jwt()
+    } else {
+        response_mode
+            .parse()
+            .map_err(|err: &str| BrokerError::Input(err.to_owned()))?
     };
 
-    let redirect_uri = parse_redirect_uri(&redirect_uri, "redirect_uri")
-        .map_err(|e| BrokerError::Input(format!("{e}")))?;
+    let (redirect_uri, client) = validate_client(&ctx.app, &client_id, &redirect_uri)?;
 
-    if client_id != redirect_uri.origin().ascii_serialization() {
+    // Check we can sign JARM responses before we make redirect_uri available, because errors are
+    // also returned in the signed response.
+    if response_mode.jwt_base().is_some()
+        && crypto::jarm_signing_alg(&ctx.app, &client_id).is_none()
+    {
         return Err(BrokerError::Input(
-            "the client_id must be the origin of the redirect_uri".to_owned(),
+            "the client is not allowed to use any of the enabled signing algorithms".to_owned(),
         ));
     }
 
@@ -138,17 +226,22 @@ pub async fn auth(ctx: &mut Context) -> HandlerResult {
         state,
     });
 
-    if params.contains_key("request") {
-        return Err(BrokerError::SpecificInput {
-            error: "request_not_supported".to_owned(),
-            error_description: "passing request parameters as JWTs is not supported".to_owned(),
-        });
-    }
-    if params.contains_key("request_uri") {
-        return Err(BrokerError::SpecificInput {
-            error: "request_uri_not_supported".to_owned(),
-            error_description: "passing request parameters as JWTs is not supported".to_owned(),
-        });
+    if let Some(client) = client {
+        if !client.allows_response_type(response_type) {
+            return Err(BrokerError::SpecificInput {
+                error: "unauthorized_client".to_owned(),
+                error_description: format!(
+                    "the client is not allowed to use response_type={}",
+                    response_type.as_str()
+                ),
+            });
+        }
+        if !client.allows_response_mode(response_mode) {
+            return Err(BrokerError::Input(format!(
+                "the client is not allowed to use response_mode={}",
+                response_mode.as_str()
+            )));
+        }
     }
 
     let nonce = try_get_input_param!(params, "nonce", String::new());
@@ -163,6 +256,46 @@ pub async fn auth(ctx: &mut Context) -> HandlerResult {
         Some(nonce)
     };
 
+    let code_challenge = try_get_input_param!(params, "code_challenge", String::new());
+    let code_challenge_method =
+        try_get_input_param!(params, "code_challenge_method", String::new());
+    let code_challenge = if code_challenge.is_empty() {
+        if !code_challenge_method.is_empty() {
+            return Err(BrokerError::Input(
+                "code_challenge_method specified without code_challenge".to_owned(),
+            ));
+        }
+        if response_type == ResponseType::Code && ctx.app.require_pkce {
+            return Err(BrokerError::Input(
+                "missing request parameter code_challenge, required with response_type=code"
+                    .to_owned(),
+            ));
+        }
+        None
+    } else {
+        if response_type != ResponseType::Code {
+            return Err(BrokerError::Input(
+                "code_challenge is only supported with response_type=code".to_owned(),
+            ));
+        }
+        let method = if code_challenge_method.is_empty() {
+            CodeChallengeMethod::Plain
+        } else {
+            code_challenge_method.parse().map_err(|_err| {
+                BrokerError::Input(
+                    "unsupported code_challenge_method, must be S256 or plain".to_owned(),
+                )
+            })?
+        };
+        if !CodeChallenge::is_valid_syntax(&code_challenge) {
+            return Err(BrokerError::Input("invalid code_challenge".to_owned()));
+        }
+        Some(CodeChallenge {
+            method,
+            challenge: code_challenge,
+        })
+    };
+
     if let Some(ref whitelist) = ctx.app.allowed_origins {
         if !whitelist.contains(&client_id) {
             return Err(BrokerError::Input(
@@ -181,16 +314,15 @@ pub async fn auth(ctx: &mut Context) -> HandlerResult {
 
     // NOTE: This query parameter is non-standard.
     let signing_alg = try_get_input_param!(params, "id_token_signing_alg", "RS256".to_owned());
-    let signing_alg = signing_alg
-        .parse()
-        .ok()
-        .filter(|alg| ctx.app.signing_algs.contains(alg))
-        .ok_or_else(|| {
-            BrokerError::Input(format!(
-                "unsupported id_token_signing_alg, must be one of: {}",
-                SigningAlgorithm::format_list(&ctx.app.signing_algs)
-            ))
-        })?;
+    let signing_alg = parse_signing_alg(&ctx.app, client, &signing_alg)?;
+
+    let max_age = if max_age.is_empty() {
+        None
+    } else {
+        Some(max_age.parse::<u64>().map_err(|_err| {
+            BrokerError::Input("max_age must be a non-negative integer".to_owned())
+        })?)
+    };
 
     let login_hint = try_get_input_param!(params, "login_hint", String::new());
     if login_hint.is_empty() && !ctx.want_json {
@@ -205,8 +337,16 @@ pub async fn auth(ctx: &mut Context) -> HandlerResult {
         let display_origin = redirect_uri_.origin().unicode_serialization();
 
         let catalog = ctx.catalog();
+        let privacy = if client.is_some_and(|client| client.privacy) {
+            catalog.gettext(
+                "This site will only receive the domain of your email address, not the address itself.",
+            )
+        } else {
+            ""
+        };
         let mut data = mustache::MapBuilder::new()
             .insert_str("display_origin", display_origin)
+            .insert_str("privacy", privacy)
             .insert_str("title", catalog.gettext("Finish logging in to"))
             .insert_str(
                 "explanation",
@@ -238,6 +378,55 @@ pub async fn auth(ctx: &mut Context) -> HandlerResult {
         ));
     }
 
+    start_auth(
+        ctx,
+        AuthRequest {
+            client_id,
+            login_hint,
+            response_type,
+            nonce,
+            signing_alg,
+            code_challenge,
+            prompt,
+            max_age,
+            device_code: None,
+        },
+    )
+    .await
+}
+
+/// A validated authentication request.
+pub struct AuthRequest {
+    pub client_id: String,
+    pub login_hint: String,
+    pub response_type: ResponseType,
+    pub nonce: Option<String>,
+    pub signing_alg: SigningAlgorithm,
+    pub code_challenge: Option<CodeChallenge>,
+    pub prompt: String,
+    /// Maximum time in seconds since the user last verified the email address.
+    pub max_age: Option<u64>,
+    /// Set if this authenticates a device authorization request.
+    pub device_code: Option<String>,
+}
+
+/// Start authentication of the email address in `login_hint`.
+///
+/// Return parameters must already be set on the context. This applies rate limits, creates the
+/// session, and starts the bridge for the email address.
+pub async fn start_auth(ctx: &mut Context, req: AuthRequest) -> HandlerResult {
+    let AuthRequest {
+        client_id,
+        login_hint,
+        response_type,
+        nonce,
+        signing_alg,
+        code_challenge,
+        prompt,
+        max_age,
+        device_code,
+    } = req;
+
     // Verify and normalize the email.
     let email_addr = login_hint.parse::<EmailAddress>().map_err(|err| {
         BrokerError::Input(format!("login_hint is not a valid email address: {err}"))
@@ -253,6 +442,7 @@ pub async fn auth(ctx: &mut Context) -> HandlerResult {
                 origin: client_id.clone(),
                 ip: ctx.ip,
             },
+            kind: LimitKind::Auth,
         })
         .await
     {
@@ -291,58 +481,95 @@ pub async fn auth(ctx: &mut Context) -> HandlerResult {
         response_type,
         nonce,
         signing_alg,
+        code_challenge,
         ctx.ip,
     )
     .await;
+    if let Some(data) = ctx.session_data.as_mut() {
+        data.device_code = device_code;
+    }
 
-    // Discover the authentication endpoints based on the email domain.
-    let discovery_future = async {
-        let links = webfinger::query(&ctx.app, &email_addr).await?;
-
-        // Try to authenticate with the first provider.
-        // TODO: Queue discovery of links and process in order, with individual timeouts.
-        let link = links.first().ok_or(BrokerError::ProviderCancelled)?;
-        match link.rel {
-            // Portier and Google providers share an implementation
-            Relation::Portier | Relation::Google => {
-                bridges::oidc::auth(ctx, &email_addr, link, &prompt).await
+    // Skip verification if the browser recently verified the email address, unless the relying
+    // party explicitly asks for it.
+    if prompt != "login" {
+        if let Some(auth_time) = login_cookie::recent_auth_time(ctx, &email_addr, max_age) {
+            metrics::AUTH_REMEMBERED.inc();
+            if let Some(data) = ctx.session_data.as_mut() {
+                data.auth_time = Some(auth_time);
             }
+            return bridges::complete_auth(ctx).await;
         }
-    };
+    }
 
-    // Apply a timeout to discovery.
-    match tokio::time::timeout(Duration::from_secs(5), discovery_future).await {
-        Err(_) => {
+    // Discover the authentication endpoints based on the email domain.
+    let links = match spawn_discovery(
+        ctx.app.webfinger_timeout,
+        webfinger::query(ctx.app.clone(), email_addr.clone()),
+    )
+    .await
+    {
+        None => {
             // Timeout causes fall back to email loop auth.
-            info!("discovery timed out for {}", email_addr);
-
-            // TODO: We used to (before async) continue discovery in the background, using shared
-            // access to Context through RefCell. We could bring that back by decoupling auth
-            // mechanisms from Context.
-            //
-            // (The original idea was also for auth mechanisms to have a 'commit' action, to
-            // indicate a side-effect is about the happen. From this side, it'd effectively abort
-            // the timeout and bubble all errors. An intermediate AuthContext could provide this.)
-            /*
-            tokio::spawn(async {
-                if let Err(e) = f.await {
-                    e.log();
-                }
-            });
-            */
-        }
-        Ok(Ok(v)) => {
-            // Discovery succeeded, simply return the response.
-            return Ok(v);
+            info!("webfinger timed out for {}", email_addr);
+            vec![]
         }
-        Ok(Err(e @ (BrokerError::Provider(_) | BrokerError::ProviderCancelled))) => {
+        Some(Ok(links)) => links,
+        Some(Err(e @ BrokerError::Provider(_))) => {
             // Provider errors cause fallback to email loop auth.
             e.log(None).await;
+            vec![]
         }
-        Ok(Err(e)) => {
+        Some(Err(e)) => {
             // Other errors during discovery are bubbled.
             return Err(e);
         }
+    };
+
+    // Try each provider in order, each with its own timeout.
+    for link in &links {
+        let result = match spawn_discovery(
+            ctx.app.provider_timeout,
+            bridges::prefetch(ctx.app.clone(), link.clone()),
+        )
+        .await
+        {
+            Some(Ok(())) => match link.rel {
+                // Portier, Google and generic providers share an implementation
+                Relation::Portier | Relation::Google | Relation::Oidc => {
+                    bridges::oidc::auth(ctx, &email_addr, link, &prompt).await
+                }
+                Relation::Saml => bridges::saml::auth(ctx, &email_addr, link, &prompt).await,
+            },
+            Some(Err(e)) => Err(e),
+            None => {
+                // Timeout causes us to move on to the next provider.
+                metrics::AUTH_DISCOVERY_LINK_TIMEOUT.inc();
+                info!("provider {} timed out for {}", link.href, email_addr);
+                continue;
+            }
+        };
+
+        match result {
+            Ok(v) => {
+                // Discovery succeeded, simply return the response.
+                metrics::AUTH_DISCOVERY_LINK_SUCCESS.inc();
+                return Ok(v);
+            }
+            Err(e @ BrokerError::ProviderCancelled) => {
+                // The provider is not usable, or the session was already claimed.
+                metrics::AUTH_DISCOVERY_LINK_CANCELLED.inc();
+                e.log(None).await;
+            }
+            Err(e @ BrokerError::Provider(_)) => {
+                // Provider errors cause us to move on to the next provider.
+                metrics::AUTH_DISCOVERY_LINK_ERROR.inc();
+                e.log(None).await;
+            }
+            Err(e) => {
+                // Other errors during discovery are bubbled.
+                return Err(e);
+            }
+        }
     }
 
     // Fall back to email loop auth.
@@ -352,5 +579,165 @@ pub async fn auth(ctx: &mut Context) -> HandlerResult {
             error_description: "prompt disabled, but email verification is required".to_owned(),
         });
     }
+
+    // Offer a passkey instead. This requires a browser. The offer is made regardless of whether
+    // the user registered one, so the response does not reveal that.
+    if ctx.app.webauthn && !ctx.want_json {
+        return bridges::webauthn::auth(ctx).await;
+    }
+
     bridges::email::auth(ctx, email_addr).await
 }
+
+/// Run a discovery step as a separate task, with a timeout.
+///
+/// Discovery steps only depend on the app configuration, not on the request. If the timeout
+/// expires, `None` is returned, but the task continues in the background, so that it can still
+/// warm the cache for the next attempt.
+async fn spawn_discovery<T: Send + 'static>(
+    timeout: Duration,
+    future: impl Future<Output = BrokerResult<T>> + Send + 'static,
+) -> Option<BrokerResult<T>> {
+    let mut task = tokio::spawn(future);
+    match tokio::time::timeout(timeout, &mut task).await {
+        Ok(Ok(result)) => Some(result),
+        Ok(Err(err)) => Some(Err(BrokerError::Internal(format!(
+            "discovery task failed: {err}"
+        )))),
+        Err(_) => {
+            tokio::spawn(async move {
+                match task.await {
+                    Ok(Ok(_)) => metrics::AUTH_DISCOVERY_LATE_SUCCESS.inc(),
+                    Ok(Err(err)) => {
+                        err.log(None).await;
+                    }
+                    Err(err) => error!("discovery task failed: {err}"),
+                }
+            });
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::spawn_discovery;
+    use crate::agents::SaveCacheEntry;
+    use crate::metrics;
+    use crate::utils::testing::{auth_params, TestBroker};
+    use crate::webfinger::{Link, Relation};
+    use http::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::Mutex;
+
+    /// Cache the configuration of a provider, so it can be used without network access.
+    async fn cache_provider(broker: &TestBroker, origin: &str) {
+        for (path, data) in [
+            (
+                "/.well-known/openid-configuration",
+                format!(
+                    r#"{{"authorization_endpoint":"{origin}/auth","jwks_uri":"{origin}/jwks","response_modes_supported":["form_post"]}}"#
+                ),
+            ),
+            ("/jwks", r#"{"keys":[]}"#.to_owned()),
+        ] {
+            broker
+                .app
+                .store
+                .send(SaveCacheEntry {
+                    key: format!("{origin}{path}").parse().unwrap(),
+                    data,
+                    ttl: Duration::from_secs(60),
+                })
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_provider_fallthrough() {
+        // A provider that accepts connections, but never responds.
+        let slow = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let slow_port = slow.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = slow.accept().await {
+                connections.push(stream);
+            }
+        });
+        // A provider that refuses connections.
+        let failing = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let failing_port = failing.local_addr().unwrap().port();
+        drop(failing);
+
+        let links = [
+            format!("https://127.0.0.1:{slow_port}"),
+            format!("https://127.0.0.1:{failing_port}"),
+            "https://idp.example.com".to_owned(),
+        ]
+        .iter()
+        .map(|href| Link {
+            rel: Relation::Portier,
+            href: href.parse().unwrap(),
+        })
+        .collect();
+        let broker = TestBroker::new(|builder| {
+            builder.provider_timeout = Duration::from_millis(500);
+            builder
+                .domain_overrides
+                .insert("example.com".to_owned(), links);
+        })
+        .await;
+        cache_provider(&broker, "https://idp.example.com").await;
+
+        let timeouts = metrics::AUTH_DISCOVERY_LINK_TIMEOUT.get();
+        let errors = metrics::AUTH_DISCOVERY_LINK_ERROR.get();
+        let res = broker
+            .post("/auth", &auth_params("id_token", "john.doe@example.com"))
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        let json = res.json();
+        assert_eq!(json["result"], "redirect_to_provider");
+        assert!(json["url"]
+            .as_str()
+            .unwrap()
+            .starts_with("https://idp.example.com/auth?"));
+        assert!(metrics::AUTH_DISCOVERY_LINK_TIMEOUT.get() > timeouts);
+        assert!(metrics::AUTH_DISCOVERY_LINK_ERROR.get() > errors);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_discovery_continues_after_timeout() {
+        let cache = Arc::new(Mutex::new(None));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        // Like cached fetches, a slow fetch fills the cache once it completes.
+        let discover = || {
+            let cache = Arc::clone(&cache);
+            let fetches = Arc::clone(&fetches);
+            async move {
+                let mut cache = cache.lock().await;
+                if let Some(ref data) = *cache {
+                    return Ok(String::clone(data));
+                }
+                fetches.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                *cache = Some("config".to_owned());
+                Ok("config".to_owned())
+            }
+        };
+
+        let late = metrics::AUTH_DISCOVERY_LATE_SUCCESS.get();
+        assert!(spawn_discovery(Duration::from_millis(50), discover())
+            .await
+            .is_none());
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(metrics::AUTH_DISCOVERY_LATE_SUCCESS.get() > late);
+
+        let result = spawn_discovery(Duration::from_millis(50), discover()).await;
+        assert_eq!(result.unwrap().unwrap(), "config");
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+}
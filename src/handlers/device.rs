@@ -0,0 +1,523 @@
+use crate::agents::{
+    CompleteDeviceAuthorization, ConsumeDeviceResult, DeviceAuthorization, DevicePoll,
+    FindDeviceCode, GetDeviceAuthorization, GetDevicePoll, IncrAndTestLimits,
+    SaveDeviceAuthorization, SaveDevicePoll,
+};
+use crate::client_auth::{authenticate_client, ClientCredentials};
+use crate::config::{LimitInput, LimitKind};
+use crate::crypto;
+use crate::email_address::EmailAddress;
+use crate::error::{BrokerError, BrokerResult};
+use crate::handlers::auth::{parse_signing_alg, start_auth, AuthRequest};
+use crate::handlers::token::id_token_response;
+use crate::metrics;
+use crate::utils::unix_timestamp;
+use crate::web::{
+    html_response, json_response, Context, HandlerResult, Response, ResponseMode, ResponseType,
+    ReturnParams, SessionData,
+};
+use http::Method;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use url::Url;
+
+/// The `grant_type` used to poll for a device authorization result. (RFC 8628)
+pub const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Minimum time in seconds between polls, and the increment on `slow_down`.
+const POLL_INTERVAL: u64 = 5;
+
+/// Number of user codes to try, when generated codes are already in use.
+const USER_CODE_ATTEMPTS: usize = 5;
+
+/// Format a user code for display, as two groups of 4 characters.
+fn format_user_code(code: &str) -> String {
+    format!("{}-{}", &code[..4], &code[4..])
+}
+
+/// Normalize a user code as entered by the user.
+fn normalize_user_code(input: &str) -> String {
+    input
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Time remaining until a device authorization request expires, or `None` if it has expired.
+fn remaining_ttl(data: &DeviceAuthorization) -> Option<Duration> {
+    match data.expires.saturating_sub(unix_timestamp()) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// Error for the client when the device code is unknown or has expired.
+fn expired_token() -> BrokerError {
+    BrokerError::SpecificInput {
+        error: "expired_token".to_owned(),
+        error_description: "the device_code is invalid or has expired".to_owned(),
+    }
+}
+
+/// Fetch a device authorization request.
+async fn get(ctx: &Context, device_code: &str) -> BrokerResult<Option<DeviceAuthorization>> {
+    ctx.app
+        .store
+        .send(GetDeviceAuthorization {
+            device_code: device_code.to_owned(),
+        })
+        .await
+        .map_err(|e| BrokerError::Internal(format!("could not lookup a device authorization: {e}")))
+}
+
+/// Render the verification page.
+fn render_page(ctx: &Context, user_code: &str, done: bool) -> Response {
+    let catalog = ctx.catalog();
+    let data = mustache::MapBuilder::new()
+        .insert_str("title", catalog.gettext("Connect a device"))
+        .insert_bool("done", done)
+        .insert_str(
+            "explanation",
+            if done {
+                catalog.gettext("Your device is now connected.")
+            } else {
+                catalog.gettext("Enter the code shown on your device.")
+            },
+        )
+        .insert_str(
+            "use",
+            catalog.gettext("Then specify the email you wish to use to login with."),
+        )
+        .insert_str(
+            "close",
+            catalog.gettext("You can close this window and return to your device."),
+        )
+        .insert_str("user_code", user_code)
+        .build();
+    html_response(ctx.app.templates.device.render_data(&data))
+}
+
+/// Request handler for device authorization requests from the client. (RFC 8628)
+///
+/// The client receives a device code to poll the token endpoint with, and a user code that the
+/// user enters on the verification page, possibly on another device.
+pub async fn device_authorization(ctx: &mut Context) -> HandlerResult {
+    // This is a server-to-server request that should always return a JSON response.
+    ctx.want_json = true;
+
+    let mut params = ctx.form_params();
+
+    let credentials = ClientCredentials::from_request(ctx, &mut params)?;
+    let client_id = credentials
+        .client_id()
+        .ok_or_else(|| BrokerError::Input("missing request parameter client_id".to_owned()))?
+        .to_owned();
+    authenticate_client(ctx, &client_id, &credentials).await?;
+
+    // The client has no redirect_uri, but the client_id must still be an origin.
+    if Url::parse(&client_id)
+        .ok()
+        .filter(|url| url.origin().ascii_serialization() == client_id)
+        .is_none()
+    {
+        return Err(BrokerError::Input(
+            "the client_id must be an origin".to_owned(),
+        ));
+    }
+
+    if let Some(ref whitelist) = ctx.app.allowed_origins {
+        if !whitelist.contains(&client_id) {
+            return Err(BrokerError::Input(
+                "the origin is not whitelisted".to_owned(),
+            ));
+        }
+    }
+
+    let scope = try_get_input_param!(params, "scope");
+    let mut scope_set: HashSet<&str> = scope.split(' ').collect();
+    if !scope_set.remove("openid") {
+        return Err(BrokerError::Input(
+            "unsupported scope, must contain 'openid'".to_owned(),
+        ));
+    }
+
+    let nonce = try_get_input_param!(params, "nonce", String::new());
+    let nonce = if nonce.is_empty() { None } else { Some(nonce) };
+
+    // NOTE: This parameter is non-standard.
+    let signing_alg = try_get_input_param!(params, "id_token_signing_alg", "RS256".to_owned());
+    let signing_alg = parse_signing_alg(&ctx.app, ctx.app.clients.get(&client_id), &signing_alg)?;
+
+    let device_code = crypto::nonce(&ctx.app.rng).await;
+    let expires_in = ctx.app.device_code_ttl.as_secs();
+    let mut data = DeviceAuthorization {
+        client_id,
+        user_code: String::new(),
+        nonce,
+        signing_alg,
+        expires: unix_timestamp() + expires_in,
+    };
+
+    // User codes are short, so may already be in use by another pending request.
+    let mut saved = false;
+    for _ in 0..USER_CODE_ATTEMPTS {
+        data.user_code = crypto::user_code(&ctx.app.rng).await;
+        saved = ctx
+            .app
+            .store
+            .send(SaveDeviceAuthorization {
+                device_code: device_code.clone(),
+                data: data.clone(),
+                ttl: ctx.app.device_code_ttl,
+            })
+            .await
+            .map_err(|e| {
+                BrokerError::Internal(format!("could not save a device authorization: {e}"))
+            })?;
+        if saved {
+            break;
+        }
+    }
+    if !saved {
+        return Err(BrokerError::Internal(
+            "could not generate an unused user code".to_owned(),
+        ));
+    }
+    let user_code = data.user_code;
+
+    let verification_uri = format!("{}/device", ctx.app.public_url);
+    Ok(json_response(&json!({
+        "device_code": device_code,
+        "user_code": format_user_code(&user_code),
+        "verification_uri": verification_uri,
+        "verification_uri_complete": format!("{verification_uri}?user_code={user_code}"),
+        "expires_in": expires_in,
+        "interval": POLL_INTERVAL,
+    })))
+}
+
+/// Request handler for the device verification page.
+///
+/// The user enters the user code and their email address, after which authentication continues
+/// like a regular authentication request. Instead of returning to the relying party, the result
+/// is stored for the client to pick up at the token endpoint.
+pub async fn verify(ctx: &mut Context) -> HandlerResult {
+    let mut params = match ctx.method {
+        Method::GET => ctx.query_params(),
+        Method::POST => ctx.form_params(),
+        _ => unreachable!(),
+    };
+
+    let user_code = normalize_user_code(&try_get_input_param!(params, "user_code", String::new()));
+    let login_hint = try_get_input_param!(params, "login_hint", String::new());
+    if ctx.method == Method::GET || user_code.is_empty() || login_hint.is_empty() {
+        return Ok(render_page(ctx, &user_code, false));
+    }
+
+    // Enforce rate limits before looking up the user code, so codes cannot be guessed. The client
+    // is not yet known, so the limits apply to the verification page as a whole.
+    let email_addr = login_hint.parse::<EmailAddress>().map_err(|err| {
+        BrokerError::Input(format!("login_hint is not a valid email address: {err}"))
+    })?;
+    match ctx
+        .app
+        .store
+        .send(IncrAndTestLimits {
+            input: LimitInput {
+                email_addr,
+                origin: ctx.app.public_url.clone(),
+                ip: ctx.ip,
+            },
+            kind: LimitKind::Auth,
+        })
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            metrics::AUTH_LIMITED.inc();
+            return Err(BrokerError::RateLimited);
+        }
+        Err(e) => {
+            return Err(BrokerError::Internal(format!(
+                "could not test rate limit: {e}"
+            )))
+        }
+    }
+
+    let invalid_code =
+        || BrokerError::Input("the code is invalid or has expired, please try again".to_owned());
+    let device_code = ctx
+        .app
+        .store
+        .send(FindDeviceCode { user_code })
+        .await
+        .map_err(|e| BrokerError::Internal(format!("could not lookup a user code: {e}")))?
+        .ok_or_else(invalid_code)?;
+    let data = get(ctx, &device_code).await?.ok_or_else(invalid_code)?;
+
+    // There is no relying party to redirect to, but the origin is used for display, and errors
+    // are always shown on our own error page.
+    let redirect_uri = Url::parse(&data.client_id)
+        .map_err(|_err| BrokerError::Internal("invalid client_id for device".to_owned()))?;
+    ctx.return_params = Some(ReturnParams {
+        redirect_uri,
+        response_mode: ResponseMode::FormPost,
+        response_errors: false,
+        state: String::new(),
+    });
+
+    start_auth(
+        ctx,
+        AuthRequest {
+            client_id: data.client_id,
+            login_hint,
+            response_type: ResponseType::IdToken,
+            nonce: data.nonce,
+            signing_alg: data.signing_alg,
+            code_challenge: None,
+            // The code may have been entered by someone other than the owner of this browser, at
+            // the request of whoever holds the device. Always verify the address again.
+            prompt: "login".to_owned(),
+            max_age: None,
+            device_code: Some(device_code),
+        },
+    )
+    .await
+}
+
+/// Complete a device authorization request, once the user has authenticated.
+pub async fn complete(ctx: &mut Context, device_code: &str, data: SessionData) -> HandlerResult {
+    let expired = || BrokerError::Input("the device authorization request has expired".to_owned());
+    let device = get(ctx, device_code).await?.ok_or_else(expired)?;
+    let ttl = remaining_ttl(&device).ok_or_else(expired)?;
+    let saved = ctx
+        .app
+        .store
+        .send(CompleteDeviceAuthorization {
+            device_code: device_code.to_owned(),
+            user_code: device.user_code,
+            data,
+            ttl,
+        })
+        .await
+        .map_err(|e| {
+            BrokerError::Internal(format!("could not complete a device authorization: {e}"))
+        })?;
+    if !saved {
+        return Err(BrokerError::Input("the code was already used".to_owned()));
+    }
+
+    if ctx.want_json {
+        Ok(json_response(&json!({ "result": "device_authorized" })))
+    } else {
+        Ok(render_page(ctx, "", true))
+    }
+}
+
+/// Token endpoint handler for `grant_type=urn:ietf:params:oauth:grant-type:device_code`.
+pub async fn token(ctx: &mut Context, mut params: HashMap<String, String>) -> HandlerResult {
+    let device_code = try_get_provider_param!(params, "device_code");
+    let credentials = ClientCredentials::from_request(ctx, &mut params)?;
+
+    let device = get(ctx, &device_code).await?.ok_or_else(expired_token)?;
+
+    authenticate_client(ctx, &device.client_id, &credentials).await?;
+    if credentials
+        .client_id()
+        .is_some_and(|client_id| client_id != device.client_id)
+    {
+        return Err(BrokerError::SpecificInput {
+            error: "invalid_grant".to_owned(),
+            error_description: "the device_code was issued to another client".to_owned(),
+        });
+    }
+
+    // Taking the result also removes the request, so only one poll can receive it.
+    let result = ctx
+        .app
+        .store
+        .send(ConsumeDeviceResult {
+            device_code: device_code.clone(),
+        })
+        .await
+        .map_err(|e| {
+            BrokerError::Internal(format!("could not lookup a device authorization: {e}"))
+        })?;
+    if let Some(data) = result {
+        return id_token_response(ctx, &data, &device.client_id).await;
+    }
+
+    let ttl = remaining_ttl(&device).ok_or_else(expired_token)?;
+    let mut poll = ctx
+        .app
+        .store
+        .send(GetDevicePoll {
+            device_code: device_code.clone(),
+        })
+        .await
+        .map_err(|e| BrokerError::Internal(format!("could not lookup a device poll: {e}")))?
+        .unwrap_or(DevicePoll {
+            interval: POLL_INTERVAL,
+            last_poll: 0,
+        });
+    let now = unix_timestamp();
+    let too_fast = now < poll.last_poll + poll.interval;
+    if too_fast {
+        poll.interval += POLL_INTERVAL;
+    }
+    poll.last_poll = now;
+    ctx.app
+        .store
+        .send(SaveDevicePoll {
+            device_code,
+            poll,
+            ttl,
+        })
+        .await
+        .map_err(|e| BrokerError::Internal(format!("could not save a device poll: {e}")))?;
+
+    Err(if too_fast {
+        BrokerError::SpecificInput {
+            error: "slow_down".to_owned(),
+            error_description: "the client is polling too quickly".to_owned(),
+        }
+    } else {
+        BrokerError::SpecificInput {
+            error: "authorization_pending".to_owned(),
+            error_description: "the user has not yet completed authentication".to_owned(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DEVICE_CODE_GRANT_TYPE;
+    use crate::config::LimitConfig;
+    use crate::utils::testing::{TestBroker, RP_ORIGIN};
+    use http::StatusCode;
+    use std::time::Duration;
+
+    /// Start a device authorization request, returning the device code and user code.
+    async fn start(broker: &TestBroker) -> (String, String) {
+        let res = broker
+            .post(
+                "/device_authorization",
+                &[
+                    ("client_id", RP_ORIGIN),
+                    ("scope", "openid email"),
+                    ("id_token_signing_alg", "EdDSA"),
+                ],
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        let json = res.json();
+        (
+            json["device_code"].as_str().unwrap().to_owned(),
+            json["user_code"].as_str().unwrap().to_owned(),
+        )
+    }
+
+    async fn poll(broker: &TestBroker, device_code: &str) -> Result<String, String> {
+        let res = broker
+            .post(
+                "/token",
+                &[
+                    ("grant_type", DEVICE_CODE_GRANT_TYPE),
+                    ("device_code", device_code),
+                    ("client_id", RP_ORIGIN),
+                ],
+            )
+            .await;
+        match res.json()["id_token"].as_str() {
+            Some(id_token) => Ok(id_token.to_owned()),
+            None => Err(res.error()),
+        }
+    }
+
+    async fn verify(broker: &TestBroker, user_code: &str) -> Result<String, String> {
+        let res = broker
+            .post(
+                "/device",
+                &[
+                    ("user_code", user_code),
+                    ("login_hint", "john.doe@example.com"),
+                ],
+            )
+            .await;
+        match res.json()["session"].as_str() {
+            Some(session) => Ok(session.to_owned()),
+            None => Err(res.error()),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_device_flow() {
+        let broker = TestBroker::new(|_| {}).await;
+        let (device_code, user_code) = start(&broker).await;
+
+        assert_eq!(
+            poll(&broker, &device_code).await.unwrap_err(),
+            "authorization_pending"
+        );
+        assert_eq!(poll(&broker, &device_code).await.unwrap_err(), "slow_down");
+
+        let session = verify(&broker, &user_code).await.unwrap();
+        let code = broker.mail_code().unwrap();
+        let res = broker
+            .post("/confirm", &[("session", &session), ("code", &code)])
+            .await;
+        assert_eq!(res.json()["result"], "device_authorized");
+
+        // The result can be picked up once, after which the request is gone.
+        assert!(poll(&broker, &device_code).await.is_ok());
+        assert_eq!(
+            poll(&broker, &device_code).await.unwrap_err(),
+            "expired_token"
+        );
+        assert_eq!(
+            verify(&broker, &user_code).await.unwrap_err(),
+            "invalid_request"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_device_expired() {
+        let broker = TestBroker::new(|builder| {
+            builder.device_code_ttl = Duration::from_secs(1);
+        })
+        .await;
+        let (device_code, user_code) = start(&broker).await;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(
+            poll(&broker, &device_code).await.unwrap_err(),
+            "expired_token"
+        );
+        assert_eq!(
+            verify(&broker, &user_code).await.unwrap_err(),
+            "invalid_request"
+        );
+        assert_eq!(poll(&broker, "unknown").await.unwrap_err(), "expired_token");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_device_verify_limited() {
+        let broker = TestBroker::new(|builder| {
+            builder.limits = vec!["ip:2/min".parse::<LimitConfig>().unwrap()];
+        })
+        .await;
+        assert_eq!(
+            verify(&broker, "AAAA-AAAA").await.unwrap_err(),
+            "invalid_request"
+        );
+        assert_eq!(
+            verify(&broker, "AAAA-AAAA").await.unwrap_err(),
+            "invalid_request"
+        );
+        assert_eq!(
+            verify(&broker, "AAAA-AAAA").await.unwrap_err(),
+            "access_denied"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::agents::GetPublicJwks;
+use crate::bridges::oidc::ProviderKey;
+use crate::client_auth::{authenticate_client, ClientCredentials};
+use crate::crypto;
+use crate::error::BrokerError;
+use crate::utils::{base64url, unix_timestamp};
+use crate::web::{json_response, Context, HandlerResult};
+use log::info;
+use serde_json::{json, Value};
+
+/// Request handler for token introspection. (RFC 7662)
+///
+/// This allows services that cannot verify JWTs themselves to check an identity token we issued.
+/// Only registered clients that authenticate can use this endpoint, and only for tokens issued to
+/// them, so it cannot be used by strangers to test tokens.
+pub async fn introspect(ctx: &mut Context) -> HandlerResult {
+    // This is a server-to-server request that should always return a JSON response.
+    ctx.want_json = true;
+
+    let mut params = ctx.form_params();
+    let token = try_get_input_param!(params, "token");
+
+    if ctx.app.clients.is_empty() {
+        return Err(BrokerError::ClientAuth(
+            "token introspection requires a registered client".to_owned(),
+        ));
+    }
+    let credentials = ClientCredentials::from_request(ctx, &mut params)?;
+    if matches!(credentials, ClientCredentials::None { .. }) {
+        return Err(BrokerError::ClientAuth(
+            "client authentication is required".to_owned(),
+        ));
+    }
+    // Assertions may omit the `client_id` parameter, so take it from the token in that case.
+    // Verification of the assertion still ensures it was issued by that client.
+    let client_id = match credentials.client_id() {
+        Some(client_id) => client_id.to_owned(),
+        None => unverified_aud(&token)
+            .ok_or_else(|| BrokerError::Input("missing request parameter client_id".to_owned()))?,
+    };
+    authenticate_client(ctx, &client_id, &credentials).await?;
+
+    let jwks = ctx.app.key_manager.send(GetPublicJwks).await.jwks;
+    let keys = jwks
+        .into_iter()
+        .map(serde_json::from_value)
+        .collect::<Result<Vec<ProviderKey>, _>>()
+        .map_err(|err| BrokerError::Internal(format!("could not parse our own keys: {err}")))?;
+
+    let claims = match verify_token(ctx, &keys, &token, &client_id) {
+        Ok(claims) => claims,
+        Err(reason) => {
+            info!("introspected token for {client_id} is inactive: {reason}");
+            return Ok(json_response(&json!({ "active": false })));
+        }
+    };
+
+    let mut res = claims;
+    res.insert("active".to_owned(), Value::Bool(true));
+    res.insert("client_id".to_owned(), Value::String(client_id));
+    res.insert("token_type".to_owned(), "id_token".into());
+    Ok(json_response(&Value::Object(res)))
+}
+
+/// Read the `aud` claim of a token without verifying it.
+fn unverified_aud(token: &str) -> Option<String> {
+    let payload = token.split('.').nth(1)?;
+    let payload = base64url::decode(payload).ok()?;
+    let payload: Value = serde_json::from_slice(&payload).ok()?;
+    payload.get("aud")?.as_str().map(ToOwned::to_owned)
+}
+
+/// Verify an identity token we issued to the given client, returning its claims.
+fn verify_token(
+    ctx: &Context,
+    keys: &[ProviderKey],
+    token: &str,
+    client_id: &str,
+) -> Result<serde_json::Map<String, Value>, String> {
+    let signing_alg = crypto::jws_signing_alg(token).map_err(|err| err.to_string())?;
+    if !ctx.app.signing_algs.contains(&signing_alg) {
+        return Err(format!("signing algorithm {signing_alg} is not enabled"));
+    }
+    let Value::Object(claims) =
+        crypto::verify_jws(token, keys, signing_alg).map_err(|err| err.to_string())?
+    else {
+        return Err("payload is not a JSON object".to_owned());
+    };
+
+    if claims.get("iss").and_then(Value::as_str) != Some(&ctx.app.public_url) {
+        return Err("invalid iss".to_owned());
+    }
+    if claims.get("aud").and_then(Value::as_str) != Some(client_id) {
+        return Err("invalid aud".to_owned());
+    }
+    let exp = claims
+        .get("exp")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| "invalid exp".to_owned())?;
+    if unix_timestamp() >= exp {
+        return Err("token has expired".to_owned());
+    }
+
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigBuilder;
+    use crate::utils::testing::{auth_params, TestBroker, RP_ORIGIN};
+    use http::StatusCode;
+    use std::time::Duration;
+
+    /// Register two confidential clients, both with the secret `secret`.
+    fn register_clients(builder: &mut ConfigBuilder) {
+        let secret_hash = "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b";
+        builder.clients = ["https://rp.example.com", "https://other.example.com"]
+            .iter()
+            .map(|origin| {
+                format!(
+                    "client_id={origin} redirect_uri={origin}/callback secret_hash={secret_hash}"
+                )
+                .parse()
+                .unwrap()
+            })
+            .collect();
+    }
+
+    async fn id_token(broker: &TestBroker) -> String {
+        let res = broker
+            .login(&auth_params("id_token", "john.doe@example.com"))
+            .await;
+        res["id_token"].as_str().unwrap().to_owned()
+    }
+
+    async fn introspect(broker: &TestBroker, client_id: &str, token: &str) -> bool {
+        let res = broker
+            .post(
+                "/introspect",
+                &[
+                    ("token", token),
+                    ("client_id", client_id),
+                    ("client_secret", "secret"),
+                ],
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        res.json()["active"].as_bool().unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_introspect() {
+        let broker = TestBroker::new(register_clients).await;
+        let token = id_token(&broker).await;
+        assert!(introspect(&broker, RP_ORIGIN, &token).await);
+
+        // Tokens issued to other clients, and tampered tokens, are inactive.
+        assert!(!introspect(&broker, "https://other.example.com", &token).await);
+        let (rest, signature) = token.rsplit_once('.').unwrap();
+        let tampered = format!("{rest}.{}", signature.replace(|c| c != 'A', "A"));
+        assert!(!introspect(&broker, RP_ORIGIN, &tampered).await);
+
+        let res = broker
+            .post(
+                "/introspect",
+                &[("token", &token), ("client_id", RP_ORIGIN)],
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(res.error(), "invalid_client");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_introspect_expired() {
+        let broker = TestBroker::new(|builder| {
+            register_clients(builder);
+            builder.token_ttl = Duration::from_secs(1);
+        })
+        .await;
+        let token = id_token(&broker).await;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(!introspect(&broker, RP_ORIGIN, &token).await);
+    }
+}
@@ -1,5 +1,8 @@
 pub mod auth;
+pub mod device;
+pub mod introspect;
 pub mod normalize;
 pub mod pages;
+pub mod par;
 pub mod rewrite_to_post;
 pub mod token;
@@ -0,0 +1,153 @@
+use crate::agents::SavePushedRequest;
+use crate::client_auth::{authenticate_client, ClientCredentials};
+use crate::crypto;
+use crate::error::BrokerError;
+use crate::handlers::auth::validate_client;
+use crate::request_object::{self, PUSHED_REQUEST_URI_PREFIX};
+use crate::web::{json_response, Context, HandlerResult};
+use http::StatusCode;
+use serde_json::json;
+
+/// Request handler for pushed authorization requests. (RFC 9126)
+///
+/// The RP sends the parameters of an authorization request directly to us, and receives a
+/// `request_uri` it can use in place of those parameters at the authorization endpoint.
+pub async fn par(ctx: &mut Context) -> HandlerResult {
+    // This is a server-to-server request that should always return a JSON response.
+    ctx.want_json = true;
+
+    let mut params = ctx.form_params();
+
+    let credentials = ClientCredentials::from_request(ctx, &mut params)?;
+    let client_id = credentials
+        .client_id()
+        .ok_or_else(|| BrokerError::Input("missing request parameter client_id".to_owned()))?
+        .to_owned();
+    authenticate_client(ctx, &client_id, &credentials).await?;
+    params.insert("client_id".to_owned(), client_id.clone());
+
+    if params.contains_key("request_uri") {
+        return Err(BrokerError::Input(
+            "request_uri cannot be used in a pushed authorization request".to_owned(),
+        ));
+    }
+
+    // Verify the request object and redirect_uri now, so the RP is notified of errors directly.
+    // The authorization endpoint verifies the full request again when it is used.
+    let mut resolved = params.clone();
+    request_object::merge_request_object(ctx, &mut resolved).await?;
+    let redirect_uri = resolved
+        .get("redirect_uri")
+        .ok_or_else(|| BrokerError::Input("missing request parameter redirect_uri".to_owned()))?;
+    validate_client(&ctx.app, &client_id, redirect_uri)?;
+
+    let request_uri = crypto::nonce(&ctx.app.rng).await;
+    ctx.app
+        .store
+        .send(SavePushedRequest {
+            request_uri: request_uri.clone(),
+            params,
+            ttl: ctx.app.pushed_request_ttl,
+        })
+        .await
+        .map_err(|e| BrokerError::Internal(format!("could not save the pushed request: {e}")))?;
+
+    let mut res = json_response(&json!({
+        "request_uri": format!("{PUSHED_REQUEST_URI_PREFIX}{request_uri}"),
+        "expires_in": ctx.app.pushed_request_ttl.as_secs(),
+    }));
+    *res.status_mut() = StatusCode::CREATED;
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::testing::{auth_params, TestBroker, RP_ORIGIN};
+    use http::StatusCode;
+    use std::time::Duration;
+
+    async fn push(broker: &TestBroker) -> String {
+        let res = broker
+            .post("/par", &auth_params("id_token", "john.doe@example.com"))
+            .await;
+        assert_eq!(res.status, StatusCode::CREATED, "{}", res.body);
+        res.json()["request_uri"].as_str().unwrap().to_owned()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pushed_request() {
+        let broker = TestBroker::new(|builder| {
+            builder.clients = vec![
+                "client_id=https://rp.example.com \
+                 redirect_uri=https://rp.example.com/callback public=true"
+                    .parse()
+                    .unwrap(),
+                "client_id=https://other.example.com \
+                 redirect_uri=https://other.example.com/callback public=true"
+                    .parse()
+                    .unwrap(),
+            ];
+            builder.pushed_request_ttl = Duration::from_secs(1);
+        })
+        .await;
+
+        let request_uri = push(&broker).await;
+        let params = [("client_id", RP_ORIGIN), ("request_uri", &request_uri)];
+        let res = broker.post("/auth", &params).await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        assert_eq!(res.json()["result"], "verification_code_sent");
+
+        // A pushed request can only be used once.
+        let res = broker.post("/auth", &params).await;
+        assert_eq!(res.error(), "invalid_request_uri");
+
+        let request_uri = push(&broker).await;
+        let res = broker
+            .post(
+                "/auth",
+                &[
+                    ("client_id", "https://other.example.com"),
+                    ("request_uri", &request_uri),
+                ],
+            )
+            .await;
+        assert_eq!(res.error(), "invalid_request");
+
+        let request_uri = push(&broker).await;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let res = broker
+            .post(
+                "/auth",
+                &[("client_id", RP_ORIGIN), ("request_uri", &request_uri)],
+            )
+            .await;
+        assert_eq!(res.error(), "invalid_request_uri");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pushed_request_errors() {
+        let broker = TestBroker::new(|builder| {
+            builder.clients = vec!["client_id=https://rp.example.com \
+                 redirect_uri=https://rp.example.com/callback \
+                 secret_hash=2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"
+                .parse()
+                .unwrap()];
+        })
+        .await;
+
+        let mut params = auth_params("id_token", "john.doe@example.com");
+        let res = broker.post("/par", &params).await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(res.error(), "invalid_client");
+
+        params.push(("client_secret", "secret"));
+        params.push(("request_uri", "https://rp.example.com/request.jwt"));
+        let res = broker.post("/par", &params).await;
+        assert_eq!(res.error(), "invalid_request");
+
+        params.pop();
+        params[1].1 = "https://rp.example.com/elsewhere";
+        let res = broker.post("/par", &params).await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    }
+}
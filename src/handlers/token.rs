@@ -2,9 +2,12 @@ use serde_json::json;
 
 use crate::{
     agents::ConsumeAuthCode,
+    client_auth::{authenticate_client, ClientCredentials},
     crypto::create_jwt,
     error::BrokerError,
-    web::{json_response, Context, HandlerResult},
+    handlers::device::{self, DEVICE_CODE_GRANT_TYPE},
+    utils::unix_timestamp,
+    web::{json_response, Context, HandlerResult, SessionData},
 };
 
 pub async fn token(ctx: &mut Context) -> HandlerResult {
@@ -14,14 +17,19 @@ pub async fn token(ctx: &mut Context) -> HandlerResult {
 
     let mut params = ctx.form_params();
 
-    if try_get_provider_param!(params, "grant_type") != "authorization_code" {
-        return Err(BrokerError::ProviderInput(
-            "invalid grant_type, must be authorization_code".to_owned(),
-        ));
+    match try_get_provider_param!(params, "grant_type").as_str() {
+        "authorization_code" => {}
+        DEVICE_CODE_GRANT_TYPE => return device::token(ctx, params).await,
+        _ => {
+            return Err(BrokerError::ProviderInput(format!(
+                "invalid grant_type, must be authorization_code or {DEVICE_CODE_GRANT_TYPE}"
+            )))
+        }
     }
 
     let code = try_get_provider_param!(params, "code");
     let redirect_uri = try_get_provider_param!(params, "redirect_uri");
+    let credentials = ClientCredentials::from_request(ctx, &mut params)?;
 
     let data = ctx
         .app
@@ -42,19 +50,55 @@ pub async fn token(ctx: &mut Context) -> HandlerResult {
         ));
     }
 
+    // Verify PKCE, if the RP sent a code challenge in the authorization request.
+    match (&data.code_challenge, params.remove("code_verifier")) {
+        (Some(challenge), Some(verifier)) => {
+            if !challenge.verify(&verifier) {
+                return Err(BrokerError::SpecificInput {
+                    error: "invalid_grant".to_owned(),
+                    error_description: "code_verifier does not match the code_challenge".to_owned(),
+                });
+            }
+        }
+        (Some(_), None) => {
+            return Err(BrokerError::SpecificInput {
+                error: "invalid_grant".to_owned(),
+                error_description: "missing request parameter code_verifier".to_owned(),
+            });
+        }
+        (None, Some(_)) => {
+            return Err(BrokerError::SpecificInput {
+                error: "invalid_grant".to_owned(),
+                error_description: "code_verifier specified, but the authorization request \
+                                    did not contain a code_challenge"
+                    .to_owned(),
+            });
+        }
+        (None, None) => {}
+    }
+
     let origin = data
         .return_params
         .redirect_uri
         .origin()
         .ascii_serialization();
 
+    // The code is consumed regardless, so a failed attempt also invalidates it.
+    authenticate_client(ctx, &origin, &credentials).await?;
+
+    id_token_response(ctx, &data, &origin).await
+}
+
+/// Create the token endpoint response containing an ID token for the session.
+pub async fn id_token_response(ctx: &Context, data: &SessionData, origin: &str) -> HandlerResult {
     let jwt = create_jwt(
         &ctx.app,
         &data.email,
         &data.email_addr,
-        &origin,
+        origin,
         &data.nonce,
         data.signing_alg,
+        data.auth_time.unwrap_or_else(unix_timestamp),
     )
     .await
     .map_err(|err| BrokerError::Internal(format!("Could not create a JWT: {err:?}")))?;
@@ -65,3 +109,87 @@ pub async fn token(ctx: &mut Context) -> HandlerResult {
         "id_token": &jwt,
     })))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::testing::{auth_params, TestBroker};
+    use http::StatusCode;
+
+    /// A confidential client, with the SHA-256 hash of the secret `secret`.
+    const CLIENT: &str = "client_id=https://rp.example.com \
+        redirect_uri=https://rp.example.com/callback \
+        secret_hash=2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b";
+
+    async fn login(broker: &TestBroker) -> String {
+        let res = broker
+            .login(&auth_params("code", "john.doe@example.com"))
+            .await;
+        res["code"].as_str().unwrap().to_owned()
+    }
+
+    fn token_params<'a>(code: &'a str, extra: &[(&'a str, &'a str)]) -> Vec<(&'a str, &'a str)> {
+        let mut params = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", "https://rp.example.com/callback"),
+        ];
+        params.extend_from_slice(extra);
+        params
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_client_auth() {
+        let broker = TestBroker::new(|builder| {
+            builder.clients = vec![CLIENT.parse().unwrap()];
+        })
+        .await;
+
+        let code = login(&broker).await;
+        let res = broker.post("/token", &token_params(&code, &[])).await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(res.error(), "invalid_client");
+
+        let code = login(&broker).await;
+        let credentials = [
+            ("client_id", "https://rp.example.com"),
+            ("client_secret", "wrong"),
+        ];
+        let res = broker
+            .post("/token", &token_params(&code, &credentials))
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(res.error(), "invalid_client");
+
+        // The client has no keys, so cannot use assertions.
+        let code = login(&broker).await;
+        let credentials = [
+            (
+                "client_assertion_type",
+                "urn:ietf:params:oauth:client-assertion-type:jwt-bearer",
+            ),
+            ("client_assertion", "e30.e30.c2ln"),
+        ];
+        let res = broker
+            .post("/token", &token_params(&code, &credentials))
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(res.error(), "invalid_client");
+
+        // A failed attempt also consumes the code.
+        let credentials = [
+            ("client_id", "https://rp.example.com"),
+            ("client_secret", "secret"),
+        ];
+        let res = broker
+            .post("/token", &token_params(&code, &credentials))
+            .await;
+        assert_eq!(res.error(), "invalid_grant");
+
+        let code = login(&broker).await;
+        let res = broker
+            .post("/token", &token_params(&code, &credentials))
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        assert!(res.json()["id_token"].is_string());
+    }
+}
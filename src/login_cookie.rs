@@ -0,0 +1,162 @@
+use crate::email_address::EmailAddress;
+use crate::utils::{base64url, http::ResponseExt, unix_timestamp};
+use crate::web::{Context, Response};
+use headers::{Cookie, HeaderMapExt};
+use ring::{constant_time, hkdf, hmac};
+use std::collections::HashMap;
+
+/// Name of the cookie that remembers verified email addresses.
+const COOKIE_NAME: &str = "portier_login";
+
+/// Maximum number of addresses remembered, to limit the size of the cookie.
+const MAX_ENTRIES: usize = 10;
+
+/// Email addresses the browser verified, mapped to the time of verification.
+type Entries = HashMap<String, u64>;
+
+/// Sign the cookie payload.
+///
+/// The key is derived from the pairwise secret, so it is never used directly for both cookies and
+/// pairwise subject identifiers.
+fn sign(secret: &[u8], payload: &str) -> hmac::Tag {
+    let key: hmac::Key = hkdf::Salt::new(hkdf::HKDF_SHA256, &[])
+        .extract(secret)
+        .expand(&[b"portier login cookie"], hmac::HMAC_SHA256)
+        .expect("could not derive the login cookie key")
+        .into();
+    hmac::sign(&key, payload.as_bytes())
+}
+
+/// Encode and sign entries as a cookie value.
+fn encode(secret: &[u8], entries: &Entries) -> String {
+    let payload = base64url::encode(&serde_json::to_vec(entries).expect("could not encode JSON"));
+    let tag = sign(secret, &payload);
+    format!("{payload}.{}", base64url::encode(&tag))
+}
+
+/// Verify and decode a cookie value, returning no entries if it is invalid.
+fn decode(secret: &[u8], value: &str) -> Entries {
+    let Some((payload, tag)) = value.split_once('.') else {
+        return Entries::new();
+    };
+    let Ok(tag) = base64url::decode(tag) else {
+        return Entries::new();
+    };
+    if constant_time::verify_slices_are_equal(sign(secret, payload).as_ref(), &tag).is_err() {
+        return Entries::new();
+    }
+    base64url::decode(payload)
+        .ok()
+        .and_then(|payload| serde_json::from_slice(&payload).ok())
+        .unwrap_or_default()
+}
+
+/// Remove entries verified `ttl` seconds or longer ago, and the oldest entries over the limit.
+fn prune(entries: &mut Entries, now: u64, ttl: u64) {
+    entries.retain(|_, &mut auth_time| auth_time <= now && now - auth_time < ttl);
+    while entries.len() > MAX_ENTRIES {
+        let oldest = entries
+            .iter()
+            .min_by_key(|&(_, &auth_time)| auth_time)
+            .map(|(email, _)| email.clone())
+            .expect("entries cannot be empty");
+        entries.remove(&oldest);
+    }
+}
+
+/// Read the unexpired entries in the cookie of the request.
+fn read(ctx: &Context) -> Entries {
+    let mut entries = ctx
+        .headers
+        .typed_get::<Cookie>()
+        .and_then(|cookie| cookie.get(COOKIE_NAME).map(ToOwned::to_owned))
+        .map(|value| decode(&ctx.app.pairwise_secret, &value))
+        .unwrap_or_default();
+    prune(
+        &mut entries,
+        unix_timestamp(),
+        ctx.app.login_cookie_ttl.as_secs(),
+    );
+    entries
+}
+
+/// Get the time the browser verified the email address, if it did so recently.
+///
+/// The relying party may further limit how recent verification must be using `max_age`.
+pub fn recent_auth_time(
+    ctx: &Context,
+    email_addr: &EmailAddress,
+    max_age: Option<u64>,
+) -> Option<u64> {
+    if ctx.app.login_cookie_ttl.is_zero() {
+        return None;
+    }
+    let auth_time = *read(ctx).get(email_addr.as_str())?;
+    match max_age {
+        Some(max_age) if unix_timestamp().saturating_sub(auth_time) > max_age => None,
+        _ => Some(auth_time),
+    }
+}
+
+/// Remember in the browser that it verified the email address at `auth_time`.
+pub fn remember(ctx: &Context, res: &mut Response, email_addr: &EmailAddress, auth_time: u64) {
+    let ttl = ctx.app.login_cookie_ttl.as_secs();
+    if ttl == 0 {
+        return;
+    }
+    let mut entries = read(ctx);
+    entries.insert(email_addr.as_str().to_owned(), auth_time);
+    prune(&mut entries, unix_timestamp(), ttl);
+
+    let secure = if ctx.app.public_url.starts_with("https://") {
+        "; Secure"
+    } else {
+        ""
+    };
+    res.header(
+        hyper::header::SET_COOKIE,
+        format!(
+            "{COOKIE_NAME}={}; Path=/; Max-Age={ttl}; HttpOnly; SameSite=Lax{secure}",
+            encode(&ctx.app.pairwise_secret, &entries)
+        ),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, prune, Entries, MAX_ENTRIES};
+
+    #[test]
+    fn test_encode_decode() {
+        let mut entries = Entries::new();
+        entries.insert("john.doe@example.com".to_owned(), 1_700_000_000);
+        let value = encode(b"secret", &entries);
+        assert_eq!(decode(b"secret", &value), entries);
+
+        assert!(decode(b"other secret", &value).is_empty());
+        let (payload, tag) = value.split_once('.').unwrap();
+        let mut other = Entries::new();
+        other.insert("jane.doe@example.com".to_owned(), 1_700_000_000);
+        let other_payload = encode(b"secret", &other);
+        let other_payload = other_payload.split_once('.').unwrap().0;
+        assert!(decode(b"secret", &format!("{other_payload}.{tag}")).is_empty());
+        assert!(decode(b"secret", payload).is_empty());
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut entries = Entries::new();
+        entries.insert("old@example.com".to_owned(), 1000);
+        entries.insert("recent@example.com".to_owned(), 1900);
+        entries.insert("future@example.com".to_owned(), 3000);
+        prune(&mut entries, 2000, 1000);
+        assert_eq!(entries.keys().collect::<Vec<_>>(), ["recent@example.com"]);
+
+        let mut entries = (0..=MAX_ENTRIES as u64)
+            .map(|idx| (format!("user{idx}@example.com"), 1000 + idx))
+            .collect::<Entries>();
+        prune(&mut entries, 2000, 3600);
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert!(!entries.contains_key("user0@example.com"));
+    }
+}
@@ -18,12 +18,15 @@ mod macros;
 
 mod agents;
 mod bridges;
+mod client_auth;
 mod config;
 mod crypto;
 mod email_address;
 mod error;
 mod handlers;
+mod login_cookie;
 mod metrics;
+mod request_object;
 mod router;
 mod utils;
 mod validation;
@@ -299,7 +302,11 @@ async fn import_keys(builder: ConfigBuilder, path: &Path, dry_run: bool) {
         };
 
         let alg = entry.key_pair.signing_alg();
-        if !builder.signing_algs.contains(&alg) {
+        if !builder
+            .signing_algs
+            .iter()
+            .any(|enabled| enabled.key_alg() == alg)
+        {
             eprintln!("#{idx}: ignored, disabled signing algorithm");
             continue;
         }
@@ -384,12 +391,17 @@ async fn export_keys(builder: ConfigBuilder, path: &Path) {
     };
 
     let mut num: usize = 0;
-    let signing_algs = builder.signing_algs.clone();
+    let mut key_algs = vec![];
+    for alg in &builder.signing_algs {
+        if !key_algs.contains(&alg.key_alg()) {
+            key_algs.push(alg.key_alg());
+        }
+    }
     let store = builder
         .into_store()
         .await
         .unwrap_or_else(|err| panic!("Failed to build configuration: {err}"));
-    for alg in signing_algs {
+    for alg in key_algs {
         let key_set = store.send(ExportKeySet(alg)).await;
         if let Some(key) = key_set.current {
             writer
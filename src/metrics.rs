@@ -39,12 +39,40 @@ lazy_static::lazy_static! {
         "portier_auth_requests",
         "Number of authentication requests"
     ).unwrap();
+    pub static ref AUTH_REMEMBERED: IntCounter = register_int_counter!(
+        "portier_auth_remembered",
+        "Number of authentication requests completed using a remembered verification"
+    ).unwrap();
 
     pub static ref AUTH_WEBFINGER_DURATION: Histogram = register_histogram!(
         "portier_auth_webfinger_duration",
         "Latency of outgoing Webfinger requests."
     ).unwrap();
 
+    pub static ref AUTH_DNS_DISCOVERY_DURATION: Histogram = register_histogram!(
+        "portier_auth_dns_discovery_duration",
+        "Latency of outgoing DNS queries for discovery."
+    ).unwrap();
+
+    pub static ref AUTH_DISCOVERY_LINKS: IntCounterVec = register_int_counter_vec!(
+        "portier_auth_discovery_links",
+        "Number of discovered provider links tried, by outcome",
+        &["outcome"]
+    ).unwrap();
+    pub static ref AUTH_DISCOVERY_LINK_SUCCESS: IntCounter =
+        AUTH_DISCOVERY_LINKS.with_label_values(&["success"]);
+    pub static ref AUTH_DISCOVERY_LINK_TIMEOUT: IntCounter =
+        AUTH_DISCOVERY_LINKS.with_label_values(&["timeout"]);
+    pub static ref AUTH_DISCOVERY_LINK_ERROR: IntCounter =
+        AUTH_DISCOVERY_LINKS.with_label_values(&["error"]);
+    pub static ref AUTH_DISCOVERY_LINK_CANCELLED: IntCounter =
+        AUTH_DISCOVERY_LINKS.with_label_values(&["cancelled"]);
+
+    pub static ref AUTH_DISCOVERY_LATE_SUCCESS: IntCounter = register_int_counter!(
+        "portier_auth_discovery_late_success",
+        "Number of discovery tasks that completed successfully in the background after a timeout"
+    ).unwrap();
+
     pub static ref AUTH_EMAIL_REQUESTS: IntCounter = register_int_counter!(
         "portier_auth_email_requests",
         "Number of authentication requests that used email"
@@ -64,6 +92,29 @@ lazy_static::lazy_static! {
         "portier_auth_email_code_incorrect",
         "Number of email confirmation attempts with an invalid code"
     ).unwrap();
+    pub static ref AUTH_EMAIL_RESENT: IntCounter = register_int_counter!(
+        "portier_auth_email_resent",
+        "Number of email confirmation emails sent again"
+    ).unwrap();
+    pub static ref AUTH_EMAIL_CODE_LOCKED: IntCounter = register_int_counter!(
+        "portier_auth_email_code_locked",
+        "Number of email confirmation sessions invalidated after too many invalid codes"
+    ).unwrap();
+
+    pub static ref AUTH_WEBAUTHN_REQUESTS: IntCounter = register_int_counter!(
+        "portier_auth_webauthn_requests",
+        "Number of authentication requests that offered a passkey"
+    ).unwrap();
+
+    pub static ref AUTH_WEBAUTHN_COMPLETED: IntCounter = register_int_counter!(
+        "portier_auth_webauthn_completed",
+        "Number of successful passkey authentications"
+    ).unwrap();
+
+    pub static ref AUTH_WEBAUTHN_REGISTERED: IntCounter = register_int_counter!(
+        "portier_auth_webauthn_registered",
+        "Number of passkeys registered after email confirmation"
+    ).unwrap();
 
     pub static ref AUTH_OIDC_REQUESTS: IntCounterVec = register_int_counter_vec!(
         "portier_auth_oidc_requests",
@@ -74,6 +125,8 @@ lazy_static::lazy_static! {
         AUTH_OIDC_REQUESTS.with_label_values(&["portier"]);
     pub static ref AUTH_OIDC_REQUESTS_GOOGLE: IntCounter =
         AUTH_OIDC_REQUESTS.with_label_values(&["google"]);
+    pub static ref AUTH_OIDC_REQUESTS_GENERIC: IntCounter =
+        AUTH_OIDC_REQUESTS.with_label_values(&["oidc"]);
 
     pub static ref AUTH_OIDC_FETCH_CONFIG_DURATION: Histogram = register_histogram!(
         "portier_auth_oidc_fetch_config_duration",
@@ -85,11 +138,41 @@ lazy_static::lazy_static! {
         "Latency of outgoing requests for OpenID Connect JWKs"
     ).unwrap();
 
+    pub static ref AUTH_OIDC_FETCH_TOKEN_DURATION: Histogram = register_histogram!(
+        "portier_auth_oidc_fetch_token_duration",
+        "Latency of outgoing OpenID Connect token requests"
+    ).unwrap();
+
     pub static ref AUTH_OIDC_COMPLETED: IntCounter = register_int_counter!(
         "portier_auth_oidc_completed",
         "Number of successful OpenID Connect authentications"
     ).unwrap();
 
+    pub static ref AUTH_SAML_REQUESTS: IntCounter = register_int_counter!(
+        "portier_auth_saml_requests",
+        "Number of authentication requests that used SAML"
+    ).unwrap();
+
+    pub static ref AUTH_SAML_FETCH_METADATA_DURATION: Histogram = register_histogram!(
+        "portier_auth_saml_fetch_metadata_duration",
+        "Latency of outgoing requests for SAML metadata documents"
+    ).unwrap();
+
+    pub static ref AUTH_SAML_COMPLETED: IntCounter = register_int_counter!(
+        "portier_auth_saml_completed",
+        "Number of successful SAML authentications"
+    ).unwrap();
+
+    pub static ref CLIENT_FETCH_JWKS_DURATION: Histogram = register_histogram!(
+        "portier_client_fetch_jwks_duration",
+        "Latency of outgoing requests for client JWKs"
+    ).unwrap();
+
+    pub static ref AUTH_FETCH_REQUEST_URI_DURATION: Histogram = register_histogram!(
+        "portier_auth_fetch_request_uri_duration",
+        "Latency of outgoing requests for request objects"
+    ).unwrap();
+
     pub static ref DOMAIN_VALIDATION_ERROR: IntCounterVec = register_int_counter_vec!(
         "portier_domain_validation_error",
         "Number of authentication requests for invalid domains",
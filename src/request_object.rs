@@ -0,0 +1,251 @@
+use crate::agents::{ConsumePushedRequest, FetchUrl};
+use crate::bridges::oidc::LEEWAY;
+use crate::client_auth::fetch_client_keys;
+use crate::crypto;
+use crate::error::{BrokerError, BrokerResult};
+use crate::metrics;
+use crate::utils::unix_timestamp;
+use crate::web::Context;
+use serde_json::Value;
+use std::collections::HashMap;
+use url::Url;
+
+/// Registered JWT claims that are not merged into the request parameters.
+const JWT_CLAIMS: &[&str] = &["iss", "aud", "exp", "iat", "nbf", "jti"];
+
+fn invalid_request_object(description: String) -> BrokerError {
+    BrokerError::SpecificInput {
+        error: "invalid_request_object".to_owned(),
+        error_description: description,
+    }
+}
+
+fn invalid_request_uri(description: String) -> BrokerError {
+    BrokerError::SpecificInput {
+        error: "invalid_request_uri".to_owned(),
+        error_description: description,
+    }
+}
+
+/// Prefix of `request_uri` values we issue for pushed authorization requests. (RFC 9126)
+pub const PUSHED_REQUEST_URI_PREFIX: &str = "urn:ietf:params:oauth:request_uri:";
+
+/// Resume a pushed authorization request. (RFC 9126)
+///
+/// If `request_uri` refers to a pushed authorization request, `params` is replaced with the
+/// parameters that were pushed. The pushed request can only be used once.
+pub async fn resume_pushed_request(
+    ctx: &Context,
+    params: &mut HashMap<String, String>,
+) -> BrokerResult<()> {
+    let Some(request_uri) = params
+        .get("request_uri")
+        .and_then(|value| value.strip_prefix(PUSHED_REQUEST_URI_PREFIX))
+    else {
+        return Ok(());
+    };
+
+    let pushed = ctx
+        .app
+        .store
+        .send(ConsumePushedRequest {
+            request_uri: request_uri.to_owned(),
+        })
+        .await
+        .map_err(|e| BrokerError::Internal(format!("could not lookup the pushed request: {e}")))?
+        .ok_or_else(|| {
+            invalid_request_uri("the request_uri is invalid or has expired".to_owned())
+        })?;
+
+    if params.get("client_id") != pushed.get("client_id") {
+        return Err(BrokerError::Input(
+            "the client_id does not match the pushed authorization request".to_owned(),
+        ));
+    }
+
+    *params = pushed;
+    Ok(())
+}
+
+/// Resolve a JWT-secured authorization request. (RFC 9101)
+///
+/// If the request contains a `request` or `request_uri` parameter, the request object is verified
+/// against the keys of the registered client, and its claims are merged into `params`, replacing
+/// query parameters with the same name. Requests without a request object are left untouched.
+///
+/// A `request_uri` must be on the origin of the client, so we never fetch arbitrary URLs.
+pub async fn merge_request_object(
+    ctx: &Context,
+    params: &mut HashMap<String, String>,
+) -> BrokerResult<()> {
+    let request = params.remove("request");
+    let request_uri = params.remove("request_uri");
+    if request.is_none() && request_uri.is_none() {
+        return Ok(());
+    }
+
+    let client_id = params.get("client_id").ok_or_else(|| {
+        BrokerError::Input(
+            "missing request parameter client_id, required with request objects".to_owned(),
+        )
+    })?;
+    let client = ctx.app.clients.get(client_id).ok_or_else(|| {
+        invalid_request_object(
+            "request objects are only supported for registered clients".to_owned(),
+        )
+    })?;
+    let jwks_uri = client.jwks_uri.as_ref().ok_or_else(|| {
+        invalid_request_object("the client has no jwks_uri configured".to_owned())
+    })?;
+
+    let jwt = match (request, request_uri) {
+        (Some(request), None) => request,
+        (None, Some(request_uri)) => {
+            let url = Url::parse(&request_uri)
+                .ok()
+                .filter(|url| url.origin().ascii_serialization() == *client_id)
+                .ok_or_else(|| {
+                    invalid_request_uri("request_uri must be a URL on the client origin".to_owned())
+                })?;
+            ctx.app
+                .fetcher
+                .send(FetchUrl::get(
+                    &url,
+                    &metrics::AUTH_FETCH_REQUEST_URI_DURATION,
+                ))
+                .await
+                .map_err(|e| invalid_request_uri(format!("could not fetch request_uri: {e}")))?
+                .data
+                .trim()
+                .to_owned()
+        }
+        _ => {
+            return Err(BrokerError::Input(
+                "request and request_uri cannot be used together".to_owned(),
+            ))
+        }
+    };
+
+    let keys = fetch_client_keys(ctx, client, jwks_uri).await?;
+    let signing_alg = crypto::jws_signing_alg(&jwt)
+        .map_err(|err| invalid_request_object(format!("invalid request object: {err}")))?;
+    let payload = crypto::verify_jws(&jwt, &keys, signing_alg)
+        .map_err(|err| invalid_request_object(format!("invalid request object: {err}")))?;
+    let Value::Object(claims) = payload else {
+        return Err(invalid_request_object(
+            "request object is not a JSON object".to_owned(),
+        ));
+    };
+
+    let invalid_claim =
+        |claim: &str| invalid_request_object(format!("request object has an invalid {claim}"));
+    match claims.get("iss") {
+        None => {}
+        Some(Value::String(iss)) if iss == client_id => {}
+        Some(_) => return Err(invalid_claim("iss")),
+    }
+    match claims.get("aud") {
+        None => {}
+        Some(Value::String(aud)) if *aud == ctx.app.public_url => {}
+        Some(Value::Array(list))
+            if list
+                .iter()
+                .any(|aud| aud.as_str() == Some(&ctx.app.public_url)) => {}
+        Some(_) => return Err(invalid_claim("aud")),
+    }
+    if let Some(exp) = claims.get("exp") {
+        let exp = exp.as_u64().ok_or_else(|| invalid_claim("exp"))?;
+        if unix_timestamp() >= exp.saturating_add(LEEWAY) {
+            return Err(invalid_claim("exp"));
+        }
+    }
+    if claims
+        .get("client_id")
+        .is_some_and(|value| value.as_str() != Some(client_id))
+    {
+        return Err(invalid_claim("client_id"));
+    }
+    if claims.contains_key("request") || claims.contains_key("request_uri") {
+        return Err(invalid_request_object(
+            "request objects must not contain request or request_uri".to_owned(),
+        ));
+    }
+
+    for (key, value) in claims {
+        if JWT_CLAIMS.contains(&key.as_str()) {
+            continue;
+        }
+        let value = match value {
+            Value::String(value) => value,
+            Value::Null => continue,
+            other => other.to_string(),
+        };
+        params.insert(key, value);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::testing::{auth_params, TestBroker};
+
+    async fn auth_error(broker: &TestBroker, extra: &[(&str, &str)]) -> String {
+        let mut params = auth_params("id_token", "john.doe@example.com");
+        params.extend_from_slice(extra);
+        broker.post("/auth", &params).await.error()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_request_object_errors() {
+        let broker = TestBroker::new(|builder| {
+            builder.clients = vec!["client_id=https://rp.example.com \
+                 redirect_uri=https://rp.example.com/callback public=true"
+                .parse()
+                .unwrap()];
+        })
+        .await;
+
+        // The registered client has no keys to verify request objects with.
+        assert_eq!(
+            auth_error(&broker, &[("request", "e30.e30.c2ln")]).await,
+            "invalid_request_object"
+        );
+
+        let broker = TestBroker::new(|builder| {
+            builder.clients = vec!["client_id=https://rp.example.com \
+                 redirect_uri=https://rp.example.com/callback \
+                 jwks_uri=https://rp.example.com/jwks.json public=true"
+                .parse()
+                .unwrap()];
+        })
+        .await;
+
+        // We only fetch request objects from the client origin.
+        assert_eq!(
+            auth_error(
+                &broker,
+                &[("request_uri", "https://evil.example.com/request.jwt")]
+            )
+            .await,
+            "invalid_request_uri"
+        );
+        assert_eq!(
+            auth_error(
+                &broker,
+                &[
+                    ("request", "e30.e30.c2ln"),
+                    ("request_uri", "https://rp.example.com/request.jwt")
+                ]
+            )
+            .await,
+            "invalid_request"
+        );
+
+        let broker = TestBroker::new(|_| {}).await;
+        assert_eq!(
+            auth_error(&broker, &[("request", "e30.e30.c2ln")]).await,
+            "invalid_request_object"
+        );
+    }
+}
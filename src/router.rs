@@ -9,8 +9,16 @@ pub async fn router(ctx: &mut Context) -> HandlerResult {
         (&Method::GET, "/.well-known/openid-configuration") => handlers::auth::discovery(ctx).await,
         (&Method::GET, "/keys.json") => handlers::auth::key_set(ctx).await,
         (&(Method::GET | Method::POST), "/auth") => handlers::auth::auth(ctx).await,
+        (&Method::POST, "/par") => handlers::par::par(ctx).await,
         (&Method::POST, "/normalize") => handlers::normalize::normalize(ctx).await,
         (&Method::POST, "/token") => handlers::token::token(ctx).await,
+        (&Method::POST, "/introspect") => handlers::introspect::introspect(ctx).await,
+        (&Method::POST, "/device_authorization") => {
+            handlers::device::device_authorization(ctx).await
+        }
+
+        // Device verification page
+        (&(Method::GET | Method::POST), "/device") => handlers::device::verify(ctx).await,
 
         // OpenID Connect endpoints
         // For providers that don't support `response_mode=form_post`, we capture the fragment
@@ -18,11 +26,22 @@ pub async fn router(ctx: &mut Context) -> HandlerResult {
         (&Method::GET, "/callback") => handlers::rewrite_to_post::rewrite_to_post(ctx).await,
         (&Method::POST, "/callback") => bridges::oidc::callback(ctx).await,
 
+        // SAML endpoints
+        (&Method::POST, "/saml/acs") => bridges::saml::callback(ctx).await,
+        (&Method::GET, "/saml/metadata") => bridges::saml::metadata(ctx).await,
+
+        // Passkey endpoints
+        (&Method::POST, "/webauthn/login") => bridges::webauthn::login(ctx).await,
+        (&Method::POST, "/webauthn/register") => bridges::webauthn::register(ctx).await,
+        (&Method::POST, "/webauthn/email") => bridges::webauthn::email(ctx).await,
+
         // Email loop endpoints
         // To thwart automated scanners that follow email links, we capture the query parameter in
         // javascripts and rewrite to a POST request.
         (&Method::GET, "/confirm") => handlers::rewrite_to_post::rewrite_to_post(ctx).await,
         (&Method::POST, "/confirm") => bridges::email::confirmation(ctx).await,
+        (&Method::POST, "/confirm/resend") => bridges::email::resend(ctx).await,
+        (&Method::POST, "/confirm/status") => bridges::email::status(ctx).await,
 
         // Misc endpoints
         (&Method::GET, "/") => handlers::pages::index(ctx).await,
@@ -0,0 +1,103 @@
+//! Minimal DER parsing, to extract public keys.
+
+const SEQUENCE: u8 = 0x30;
+const BIT_STRING: u8 = 0x03;
+const VERSION: u8 = 0xa0;
+
+/// Read a DER TLV, returning the tag, contents and remaining input.
+fn der_next(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&len, mut rest) = rest.split_first()?;
+    let len = if len < 0x80 {
+        usize::from(len)
+    } else {
+        let num_bytes = usize::from(len & 0x7f);
+        if num_bytes == 0 || num_bytes > 4 || rest.len() < num_bytes {
+            return None;
+        }
+        let (bytes, tail) = rest.split_at(num_bytes);
+        rest = tail;
+        bytes
+            .iter()
+            .fold(0, |acc, &byte| (acc << 8) | usize::from(byte))
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let (contents, rest) = rest.split_at(len);
+    Some((tag, contents, rest))
+}
+
+/// Extract the public key from a DER encoded X.509 certificate.
+///
+/// Returns the contents of the `subjectPublicKey` field, which is the format `ring` expects for
+/// both RSA and ECDSA keys. The certificate itself is not validated, because identity providers
+/// commonly use self-signed certificates, and the metadata they are found in is trusted.
+pub fn certificate_public_key(der: &[u8]) -> Option<Vec<u8>> {
+    let (SEQUENCE, certificate, _) = der_next(der)? else {
+        return None;
+    };
+    let (SEQUENCE, tbs_certificate, _) = der_next(certificate)? else {
+        return None;
+    };
+    let mut rest = tbs_certificate;
+    if let (VERSION, _, tail) = der_next(rest)? {
+        rest = tail;
+    }
+    // Skip the serial number, signature algorithm, issuer, validity and subject.
+    for _ in 0..5 {
+        (_, _, rest) = der_next(rest)?;
+    }
+    let (SEQUENCE, _, _) = der_next(rest)? else {
+        return None;
+    };
+    spki_public_key(rest)
+}
+
+/// Extract the public key from a DER encoded `SubjectPublicKeyInfo`.
+///
+/// Returns the contents of the `subjectPublicKey` field, like `certificate_public_key`. The
+/// algorithm identifier is not checked, because the caller determines the algorithm.
+pub fn spki_public_key(der: &[u8]) -> Option<Vec<u8>> {
+    let (SEQUENCE, spki, _) = der_next(der)? else {
+        return None;
+    };
+    let (SEQUENCE, _, rest) = der_next(spki)? else {
+        return None;
+    };
+    let (BIT_STRING, public_key, _) = der_next(rest)? else {
+        return None;
+    };
+    // The first byte is the number of unused bits, which must be zero for keys.
+    match public_key.split_first()? {
+        (0, public_key) => Some(public_key.to_vec()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::certificate_public_key;
+    use crate::utils::xmldsig::decode_base64_text;
+    use base64::prelude::*;
+
+    #[test]
+    fn test_certificate_public_key() {
+        // Self-signed P-256 certificate, generated using `openssl req -x509`.
+        let cert = decode_base64_text(
+            "MIIBjDCCATGgAwIBAgIUDVEmSUkTMhIEgeum7V0jJNoCSoAwCgYIKoZIzj0EAwIwGjEYMBYGA1UEAwwPaWRw
+            LmV4YW1wbGUuY29tMCAXDTI2MTAxNjIwNDgwM1oYDzIxMjYwOTIyMjA0ODAzWjAaMRgwFgYDVQQDDA9pZHAu
+            ZXhhbXBsZS5jb20wWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAASd3SQkWioKZ1NWLIb0ncwPIt5j4CqVCudr
+            uX4vV3QG0HcKf10DKs7Jd8ysF3nZHNntEQN7OE2Q+oeo+ykK9tf8o1MwUTAdBgNVHQ4EFgQU7CJgjdunw522
+            QmRIOsbr7uDW9bgwHwYDVR0jBBgwFoAU7CJgjdunw522QmRIOsbr7uDW9bgwDwYDVR0TAQH/BAUwAwEB/zAK
+            BggqhkjOPQQDAgNJADBGAiEAxHJAiQttuOPY/cfLSAiDPxKGM/eYAsySNLukMQhYoS4CIQCthBGgG4kVzuJE
+            Z30wpN6zs5r9GyZr5N86PA4mwUYdUQ==",
+        )
+        .unwrap();
+        assert_eq!(
+            BASE64_STANDARD.encode(certificate_public_key(&cert).unwrap()),
+            "BJ3dJCRaKgpnU1YshvSdzA8i3mPgKpUK52u5fi9XdAbQdwp/XQMqzsl3zKwXedkc2e0RA3s4TZD6h6j7KQr21/w="
+        );
+        assert!(certificate_public_key(&cert[..100]).is_none());
+    }
+}
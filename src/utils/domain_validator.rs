@@ -1,5 +1,6 @@
 use hickory_resolver::{
     config::{LookupIpStrategy, NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
     proto::{error::ProtoError, rr::rdata::MX},
     Name, TokioAsyncResolver,
 };
@@ -7,7 +8,7 @@ use std::{
     collections::HashSet,
     io,
     net::{IpAddr, ToSocketAddrs},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 
@@ -122,6 +123,44 @@ impl DomainValidator {
         Ok(())
     }
 
+    /// Whether a DNS resolver is configured.
+    pub fn has_resolver(&self) -> bool {
+        self.dns_resolver.is_some()
+    }
+
+    /// Look up TXT records using the configured resolver.
+    ///
+    /// Returns `None` if no resolver is configured. Otherwise, returns the records, with the
+    /// character strings of each record concatenated, and the time the answer may be cached for.
+    /// A name without TXT records results in an empty list.
+    pub async fn lookup_txt(
+        &self,
+        name: &str,
+    ) -> Option<Result<(Vec<String>, Duration), ResolveError>> {
+        let resolver = self.dns_resolver.as_ref()?;
+        let mut name = match Name::from_utf8(name) {
+            Ok(name) => name,
+            Err(err) => return Some(Err(err.into())),
+        };
+        name.set_fqdn(true);
+        Some(match resolver.txt_lookup(name).await {
+            Ok(lookup) => {
+                let ttl = lookup
+                    .valid_until()
+                    .saturating_duration_since(Instant::now());
+                let records = lookup.iter().map(ToString::to_string).collect();
+                Ok((records, ttl))
+            }
+            Err(err) => match err.kind() {
+                ResolveErrorKind::NoRecordsFound { negative_ttl, .. } => Ok((
+                    vec![],
+                    Duration::from_secs(negative_ttl.unwrap_or(0).into()),
+                )),
+                _ => Err(err),
+            },
+        })
+    }
+
     /// Validate a domain.
     pub async fn validate(&self, domain: &str) -> Result<(), DomainValidationError> {
         // Use trust-dns to do domain name validation. This does the punycode transform for us, as
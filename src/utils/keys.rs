@@ -7,7 +7,7 @@ use crate::utils::{
 use ring::{
     digest,
     rsa::PublicKeyComponents,
-    signature::{self, Ed25519KeyPair, KeyPair, RsaKeyPair},
+    signature::{self, EcdsaKeyPair, EcdsaSigningAlgorithm, Ed25519KeyPair, KeyPair, RsaKeyPair},
 };
 use serde_json::{json, Value as JsonValue};
 use std::ffi::OsString;
@@ -21,6 +21,8 @@ use rsa::pkcs8::EncodePrivateKey;
 pub enum SignError {
     #[error("unsupported signing algorithm {0}")]
     UnsupportedAlgorithm(SigningAlgorithm),
+    #[error("none of the enabled signing algorithms are allowed for the client")]
+    NoAllowedAlgorithm,
     #[error("unspecified signing error")]
     Unspecified,
 }
@@ -32,27 +34,56 @@ impl From<ring::error::Unspecified> for SignError {
 }
 
 /// A named key pair, for use in JWS signing.
+///
+/// The key pair has a key ID for each signing algorithm it is used with, because a JWK describes a
+/// single algorithm, and key IDs must be unique within a key set.
 pub struct NamedKeyPair<T: KeyPairExt> {
-    pub kid: String,
     pub key_pair: T,
+    kids: Vec<(SigningAlgorithm, String)>,
 }
 
 impl<T: KeyPairExt> NamedKeyPair<T> {
-    /// Create a JSON Web Signature (JWS) for the given JSON structure.
-    pub fn sign_jws(&self, payload: &JsonValue, rng: &SecureRandom) -> Result<String, SignError> {
-        self.key_pair.sign_jws(&self.kid, payload, rng)
+    /// Name a key pair for use with the given signing algorithms.
+    ///
+    /// Algorithms that don't use this type of key are ignored. The key ID for the primary algorithm
+    /// of the key pair is a hash of the public key, and other algorithms append their name to it.
+    pub fn new(key_pair: T, signing_algs: &[SigningAlgorithm]) -> Self {
+        let primary = key_pair.signing_alg();
+        let kid = key_pair.generate_kid();
+        let kids = signing_algs
+            .iter()
+            .filter(|alg| alg.key_alg() == primary)
+            .map(|&alg| {
+                if alg == primary {
+                    (alg, kid.clone())
+                } else {
+                    (alg, format!("{kid}.{alg}"))
+                }
+            })
+            .collect();
+        Self { key_pair, kids }
     }
 
-    /// Return JSON represenation of the public key for use in JWK key sets.
-    pub fn public_jwk(&self) -> JsonValue {
-        self.key_pair.public_jwk(&self.kid)
+    /// Create a JSON Web Signature (JWS) for the given JSON structure.
+    pub fn sign_jws(
+        &self,
+        signing_alg: SigningAlgorithm,
+        payload: &JsonValue,
+        rng: &SecureRandom,
+    ) -> Result<String, SignError> {
+        let (_, kid) = self
+            .kids
+            .iter()
+            .find(|(alg, _)| *alg == signing_alg)
+            .ok_or(SignError::UnsupportedAlgorithm(signing_alg))?;
+        self.key_pair.sign_jws(kid, signing_alg, payload, rng)
     }
-}
 
-impl<T: KeyPairExt> From<T> for NamedKeyPair<T> {
-    fn from(key_pair: T) -> Self {
-        let kid = key_pair.generate_kid();
-        Self { kid, key_pair }
+    /// Append JSON represenations of the public key for use in JWK key sets.
+    pub fn append_public_jwks(&self, vec: &mut Vec<JsonValue>) {
+        for (signing_alg, kid) in &self.kids {
+            vec.push(self.key_pair.public_jwk(kid, *signing_alg));
+        }
     }
 }
 
@@ -64,19 +95,24 @@ pub trait KeyPairExt {
     /// a simple identifier in JWKs.
     fn generate_kid(&self) -> String;
 
-    /// Get the signing algorithm for this key type.
+    /// Get the primary signing algorithm for this key type.
+    ///
+    /// This is also the `SigningAlgorithm::key_alg` of all algorithms this key type supports.
     fn signing_alg(&self) -> SigningAlgorithm;
 
     /// Create a JSON Web Signature (JWS) for the given JSON structure.
+    ///
+    /// The caller must ensure the key type supports the signing algorithm.
     fn sign_jws(
         &self,
         kid: &str,
+        signing_alg: SigningAlgorithm,
         payload: &JsonValue,
         rng: &SecureRandom,
     ) -> Result<String, SignError>;
 
     /// Return JSON represenation of the public key for use in JWK key sets.
-    fn public_jwk(&self, kid: &str) -> JsonValue;
+    fn public_jwk(&self, kid: &str, signing_alg: SigningAlgorithm) -> JsonValue;
 }
 
 impl KeyPairExt for Ed25519KeyPair {
@@ -94,6 +130,7 @@ impl KeyPairExt for Ed25519KeyPair {
     fn sign_jws(
         &self,
         kid: &str,
+        _signing_alg: SigningAlgorithm,
         payload: &JsonValue,
         _rng: &SecureRandom,
     ) -> Result<String, SignError> {
@@ -108,7 +145,7 @@ impl KeyPairExt for Ed25519KeyPair {
         Ok(data)
     }
 
-    fn public_jwk(&self, kid: &str) -> JsonValue {
+    fn public_jwk(&self, kid: &str, _signing_alg: SigningAlgorithm) -> JsonValue {
         let public = self.public_key();
         json!({
             "kty": "OKP",
@@ -138,31 +175,34 @@ impl KeyPairExt for RsaKeyPair {
     fn sign_jws(
         &self,
         kid: &str,
+        signing_alg: SigningAlgorithm,
         payload: &JsonValue,
         rng: &SecureRandom,
     ) -> Result<String, SignError> {
-        let header = json!({ "kid": kid, "alg": "RS256" }).to_string();
+        let padding_alg: &dyn signature::RsaEncoding = match signing_alg {
+            SigningAlgorithm::Rs256 => &signature::RSA_PKCS1_SHA256,
+            SigningAlgorithm::Ps256 => &signature::RSA_PSS_SHA256,
+            SigningAlgorithm::Ps384 => &signature::RSA_PSS_SHA384,
+            SigningAlgorithm::Ps512 => &signature::RSA_PSS_SHA512,
+            _ => return Err(SignError::UnsupportedAlgorithm(signing_alg)),
+        };
+        let header = json!({ "kid": kid, "alg": signing_alg.as_str() }).to_string();
         let mut data = String::new();
         data.push_str(&base64url::encode(&header));
         data.push('.');
         data.push_str(&base64url::encode(&payload.to_string()));
         let mut sig = vec![0; self.public().modulus_len()];
-        self.sign(
-            &signature::RSA_PKCS1_SHA256,
-            &rng.generator,
-            data.as_bytes(),
-            &mut sig,
-        )?;
+        self.sign(padding_alg, &rng.generator, data.as_bytes(), &mut sig)?;
         data.push('.');
         data.push_str(&base64url::encode(&sig));
         Ok(data)
     }
 
-    fn public_jwk(&self, kid: &str) -> JsonValue {
+    fn public_jwk(&self, kid: &str, signing_alg: SigningAlgorithm) -> JsonValue {
         let public: PublicKeyComponents<Vec<u8>> = self.public_key().into();
         json!({
             "kty": "RSA",
-            "alg": "RS256",
+            "alg": signing_alg.as_str(),
             "use": "sig",
             "kid": &kid,
             "n": base64url::encode(&public.n),
@@ -171,6 +211,106 @@ impl KeyPairExt for RsaKeyPair {
     }
 }
 
+/// Implements `KeyPairExt` and `GeneratedKeyPair` for a wrapper around an `EcdsaKeyPair`.
+///
+/// Ring does not expose the curve of an `EcdsaKeyPair`, so we use a wrapper type per curve.
+macro_rules! ecdsa_key_pair {
+    ( $(#[$attr:meta])* $name:ident, $variant:ident, $alg:ident, $crv:expr, $ring_alg:expr ) => {
+        $(#[$attr])*
+        pub struct $name(pub EcdsaKeyPair);
+
+        impl $name {
+            /// The ring signing algorithm for this curve.
+            pub fn ring_alg() -> &'static EcdsaSigningAlgorithm {
+                $ring_alg
+            }
+        }
+
+        impl KeyPairExt for $name {
+            fn generate_kid(&self) -> String {
+                let mut ctx = digest::Context::new(&digest::SHA256);
+                ctx.update(concat!($crv, ".").as_bytes());
+                ctx.update(self.0.public_key().as_ref());
+                base64url::encode(&ctx.finish())
+            }
+
+            fn signing_alg(&self) -> SigningAlgorithm {
+                SigningAlgorithm::$alg
+            }
+
+            fn sign_jws(
+                &self,
+                kid: &str,
+                _signing_alg: SigningAlgorithm,
+                payload: &JsonValue,
+                rng: &SecureRandom,
+            ) -> Result<String, SignError> {
+                let alg = SigningAlgorithm::$alg.as_str();
+                let header = json!({ "kid": kid, "alg": alg }).to_string();
+                let mut data = String::new();
+                data.push_str(&base64url::encode(&header));
+                data.push('.');
+                data.push_str(&base64url::encode(&payload.to_string()));
+                let sig = self.0.sign(&rng.generator, data.as_bytes())?;
+                data.push('.');
+                data.push_str(&base64url::encode(&sig));
+                Ok(data)
+            }
+
+            fn public_jwk(&self, kid: &str, _signing_alg: SigningAlgorithm) -> JsonValue {
+                // The public key is an uncompressed point: a 0x04 tag, followed by X and Y.
+                let public = self.0.public_key().as_ref();
+                let (x, y) = public[1..].split_at((public.len() - 1) / 2);
+                json!({
+                    "kty": "EC",
+                    "alg": SigningAlgorithm::$alg.as_str(),
+                    "crv": $crv,
+                    "use": "sig",
+                    "kid": &kid,
+                    "x": base64url::encode(x),
+                    "y": base64url::encode(y),
+                })
+            }
+        }
+
+        impl GeneratedKeyPair for $name {
+            type Config = SecureRandom;
+
+            fn generate(config: Self::Config) -> String {
+                let doc = EcdsaKeyPair::generate_pkcs8($ring_alg, &config.generator)
+                    .expect(concat!("could not generate ", $crv, " key pair"));
+                pem::encode(doc.as_ref(), pem::PKCS8)
+            }
+
+            fn from_parsed(parsed: ParsedKeyPair) -> Option<Self> {
+                #[allow(clippy::match_wildcard_for_single_variants)]
+                match parsed {
+                    ParsedKeyPair::$variant(inner) => Some(inner),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+ecdsa_key_pair!(
+    /// An ECDSA key pair using the P-256 curve.
+    EcdsaP256KeyPair,
+    EcdsaP256,
+    Es256,
+    "P-256",
+    &signature::ECDSA_P256_SHA256_FIXED_SIGNING
+);
+
+ecdsa_key_pair!(
+    /// An ECDSA key pair using the P-384 curve.
+    EcdsaP384KeyPair,
+    EcdsaP384,
+    Es384,
+    "P-384",
+    &signature::ECDSA_P384_SHA384_FIXED_SIGNING
+);
+
 /// Trait for key pair types we can generate.
 pub trait GeneratedKeyPair: KeyPairExt + Sized {
     /// Configuration required for generating a key pair.
@@ -1,6 +1,7 @@
 pub mod agent;
 pub mod base64url;
 mod delay_queue_task;
+pub mod der;
 mod domain_validator;
 pub mod http;
 pub mod keys;
@@ -10,7 +11,10 @@ mod real_ip;
 #[cfg(feature = "redis")]
 pub mod redis;
 mod rng;
+#[cfg(test)]
+pub mod testing;
 mod time;
+pub mod xmldsig;
 
 use std::{error::Error, future::Future, pin::Pin};
 
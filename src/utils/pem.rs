@@ -5,11 +5,15 @@ use std::io::{BufRead, Error as IoError, Read};
 use base64::prelude::*;
 use ring::{
     digest::{digest, SHA256},
-    signature::{Ed25519KeyPair, RsaKeyPair},
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, Ed25519KeyPair, RsaKeyPair},
 };
 use thiserror::Error;
 
-use crate::{crypto::SigningAlgorithm, utils::keys::KeyPairExt};
+use crate::{
+    crypto::SigningAlgorithm,
+    utils::keys::{EcdsaP256KeyPair, EcdsaP384KeyPair, KeyPairExt},
+};
 
 const ARMOR_BEGIN: &str = "-----BEGIN ";
 const ARMOR_END: &str = "-----END ";
@@ -55,6 +59,8 @@ impl RawPem {
 
 pub enum ParsedKeyPair {
     Ed25519(Ed25519KeyPair),
+    EcdsaP256(EcdsaP256KeyPair),
+    EcdsaP384(EcdsaP384KeyPair),
     Rsa(RsaKeyPair),
 }
 
@@ -64,6 +70,8 @@ impl ParsedKeyPair {
         use ParsedKeyPair::*;
         match self {
             Ed25519(ref inner) => inner.signing_alg(),
+            EcdsaP256(ref inner) => inner.signing_alg(),
+            EcdsaP384(ref inner) => inner.signing_alg(),
             Rsa(ref inner) => inner.signing_alg(),
         }
     }
@@ -117,8 +125,18 @@ pub fn parse_key_pairs(
             },
             State::InPkcs8 if get_section(&line, ARMOR_END).as_deref() == Some(PKCS8) => {
                 entries.push(PemEntry::new(&b64buf, PKCS8, |data| {
+                    // Ring only uses the RNG for ECDSA nonce hedging, so a fresh one is fine here.
+                    let rng = SystemRandom::new();
                     Ed25519KeyPair::from_pkcs8(data)
                         .map(ParsedKeyPair::Ed25519)
+                        .or_else(|_| {
+                            EcdsaKeyPair::from_pkcs8(EcdsaP256KeyPair::ring_alg(), data, &rng)
+                                .map(|inner| ParsedKeyPair::EcdsaP256(EcdsaP256KeyPair(inner)))
+                        })
+                        .or_else(|_| {
+                            EcdsaKeyPair::from_pkcs8(EcdsaP384KeyPair::ring_alg(), data, &rng)
+                                .map(|inner| ParsedKeyPair::EcdsaP384(EcdsaP384KeyPair(inner)))
+                        })
                         .or_else(|_| RsaKeyPair::from_pkcs8(data).map(ParsedKeyPair::Rsa))
                         .map_err(ParseError::KeyRejected)
                 }));
@@ -0,0 +1,199 @@
+//! Helpers to test request handlers against a broker with in-memory storage.
+
+use crate::agents::mailer::SendMail;
+use crate::config::{ConfigBuilder, ConfigRc};
+use crate::crypto::SigningAlgorithm;
+use crate::utils::agent::{spawn_agent, Agent, Context, Handler};
+use crate::web::Service;
+use http::{HeaderMap, Request, StatusCode};
+use hyper::service::Service as _;
+use hyper::Body;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+/// The public URL of test brokers.
+pub const PUBLIC_URL: &str = "https://broker.example.com";
+
+/// The origin of the relying party used in tests.
+pub const RP_ORIGIN: &str = "https://rp.example.com";
+
+/// Mailer agent that keeps sent mail in memory.
+struct TestMailer {
+    sent: Arc<Mutex<Vec<SendMail>>>,
+}
+
+impl Agent for TestMailer {}
+
+impl Handler<SendMail> for TestMailer {
+    fn handle(&mut self, message: SendMail, cx: Context<Self, SendMail>) {
+        self.sent.lock().unwrap().push(message);
+        cx.reply(true);
+    }
+}
+
+/// A response from a test broker.
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl TestResponse {
+    /// Parse the body as JSON.
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body).expect("response is not JSON")
+    }
+
+    /// The OAuth2 error code in a JSON response.
+    pub fn error(&self) -> String {
+        self.json()["error"]
+            .as_str()
+            .expect("response is not an error")
+            .to_owned()
+    }
+}
+
+/// A broker using in-memory storage, that keeps mail instead of sending it.
+///
+/// Discovery for `example.com` is overridden, so addresses there use the email loop without
+/// network access.
+pub struct TestBroker {
+    pub app: ConfigRc,
+    sent: Arc<Mutex<Vec<SendMail>>>,
+}
+
+/// Create a configuration builder with the settings test brokers need.
+pub fn test_builder() -> ConfigBuilder {
+    let mut builder = ConfigBuilder::new();
+    builder.public_url = Some(PUBLIC_URL.to_owned());
+    builder.memory_storage = true;
+    builder.from_address = Some("portier@broker.example.com".to_owned());
+    builder.smtp_server = Some("localhost:25".to_owned());
+    // Generating RSA keys is slow without optimizations.
+    builder.signing_algs = vec![SigningAlgorithm::EdDsa];
+    builder
+        .domain_overrides
+        .insert("example.com".to_owned(), vec![]);
+    builder
+}
+
+impl TestBroker {
+    /// Create a broker, after applying test-specific configuration.
+    pub async fn new(configure: impl FnOnce(&mut ConfigBuilder)) -> TestBroker {
+        let mut builder = test_builder();
+        configure(&mut builder);
+
+        let mut app = builder.done().await.expect("invalid test configuration");
+        let sent = Arc::default();
+        app.mailer = Box::new(
+            spawn_agent(TestMailer {
+                sent: Arc::clone(&sent),
+            })
+            .await,
+        );
+        TestBroker {
+            app: Arc::new(app),
+            sent,
+        }
+    }
+
+    /// Send a request to the broker.
+    pub async fn send(&self, req: Request<Body>) -> TestResponse {
+        let remote_addr = "192.0.2.1:12345".parse().unwrap();
+        let res = Service::new(&self.app, Some(remote_addr))
+            .call(req)
+            .await
+            .expect("request failed");
+        let (parts, body) = res.into_parts();
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        TestResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body: String::from_utf8(body.to_vec()).expect("response is not UTF-8"),
+        }
+    }
+
+    /// Send a form-encoded POST request, asking for a JSON response.
+    pub async fn post(&self, path: &str, form: &[(&str, &str)]) -> TestResponse {
+        self.send(form_request(path, form, Some("application/json")))
+            .await
+    }
+
+    /// The text body of the last mail sent.
+    pub fn last_mail(&self) -> String {
+        self.sent
+            .lock()
+            .unwrap()
+            .last()
+            .expect("no mail was sent")
+            .text_body
+            .clone()
+    }
+
+    /// The number of mails sent.
+    pub fn mail_count(&self) -> usize {
+        self.sent.lock().unwrap().len()
+    }
+
+    /// The code in the last mail sent, if any.
+    pub fn mail_code(&self) -> Option<String> {
+        let mail = self.last_mail();
+        let (_, code) = mail.split_once("code on the login page:")?;
+        Some(code.replace(char::is_whitespace, ""))
+    }
+
+    /// The token in the link of the last mail sent, if any.
+    pub fn mail_token(&self) -> Option<String> {
+        let mail = self.last_mail();
+        let link = mail
+            .split_whitespace()
+            .find(|word| word.starts_with(PUBLIC_URL))?;
+        let link = url::Url::parse(link).expect("invalid link in mail");
+        link.query_pairs()
+            .find(|(key, _)| key == "token")
+            .map(|(_, value)| value.into_owned())
+    }
+
+    /// Start a login for `params` using the email loop, and confirm it using the code.
+    ///
+    /// Returns the JSON response, which contains either an `id_token` or a `code`.
+    pub async fn login(&self, params: &[(&str, &str)]) -> Value {
+        let res = self.post("/auth", params).await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        let session = res.json()["session"].as_str().unwrap().to_owned();
+        let code = self.mail_code().expect("mail has no code");
+        let res = self
+            .post("/confirm", &[("session", &session), ("code", &code)])
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        res.json()
+    }
+}
+
+/// Build a form-encoded POST request, optionally with an `Accept` header.
+pub fn form_request(path: &str, form: &[(&str, &str)], accept: Option<&str>) -> Request<Body> {
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(form)
+        .finish();
+    let mut req = Request::post(path).header(
+        hyper::header::CONTENT_TYPE,
+        "application/x-www-form-urlencoded",
+    );
+    if let Some(accept) = accept {
+        req = req.header(hyper::header::ACCEPT, accept);
+    }
+    req.body(Body::from(body)).unwrap()
+}
+
+/// Parameters of an authentication request from the test relying party.
+pub fn auth_params<'a>(response_type: &'a str, login_hint: &'a str) -> Vec<(&'a str, &'a str)> {
+    vec![
+        ("client_id", RP_ORIGIN),
+        ("redirect_uri", "https://rp.example.com/callback"),
+        ("response_type", response_type),
+        ("scope", "openid email"),
+        ("nonce", "test-nonce"),
+        ("login_hint", login_hint),
+        ("id_token_signing_alg", "EdDSA"),
+    ]
+}
@@ -11,3 +11,94 @@ pub fn unix_duration() -> Duration {
 pub fn unix_timestamp() -> u64 {
     unix_duration().as_secs()
 }
+
+/// Format a Unix timestamp as an `xs:dateTime` in UTC, as used in XML documents.
+pub fn format_xml_datetime(timestamp: u64) -> String {
+    let days = timestamp / 86_400;
+    let secs = timestamp % 86_400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Parse an `xs:dateTime` in UTC to a Unix timestamp, truncating fractional seconds.
+///
+/// Only the `Z` and `+00:00` time zone designators are accepted.
+pub fn parse_xml_datetime(input: &str) -> Option<u64> {
+    let input = input
+        .strip_suffix('Z')
+        .or_else(|| input.strip_suffix("+00:00"))?;
+    let (date, time) = input.split_once('T')?;
+    let time = time.split_once('.').map_or(time, |(time, _)| time);
+
+    let mut date = date.splitn(3, '-').map(str::parse::<u64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.splitn(3, ':').map(str::parse::<u64>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+// Conversions between days since the Unix epoch and dates in the proleptic Gregorian calendar.
+// Based on the algorithms by Howard Hinnant: https://howardhinnant.github.io/date_algorithms.html
+
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = (mp + 2) % 12 + 1;
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_xml_datetime, parse_xml_datetime};
+
+    #[test]
+    fn test_xml_datetime() {
+        assert_eq!(format_xml_datetime(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_xml_datetime(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_xml_datetime(1_700_000_000), "2023-11-14T22:13:20Z");
+
+        assert_eq!(
+            parse_xml_datetime("2023-11-14T22:13:20Z"),
+            Some(1_700_000_000)
+        );
+        assert_eq!(
+            parse_xml_datetime("2023-11-14T22:13:20.123Z"),
+            Some(1_700_000_000)
+        );
+        assert_eq!(
+            parse_xml_datetime("2000-02-29T00:00:00+00:00"),
+            Some(951_782_400)
+        );
+        assert_eq!(parse_xml_datetime("2023-11-14T22:13:20"), None);
+        assert_eq!(parse_xml_datetime("2023-11-14T22:13:20+01:00"), None);
+        assert_eq!(parse_xml_datetime("2023-13-14T22:13:20Z"), None);
+    }
+}
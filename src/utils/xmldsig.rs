@@ -0,0 +1,474 @@
+use base64::prelude::*;
+use ring::{digest, signature};
+use roxmltree::{Node, NodeType};
+use thiserror::Error;
+
+/// XML Signature namespace.
+pub const NS_DSIG: &str = "http://www.w3.org/2000/09/xmldsig#";
+/// Exclusive XML Canonicalization, without comments.
+const ALG_EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+/// Enveloped signature transform.
+const ALG_ENVELOPED: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+
+const ALG_SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+const ALG_SHA512: &str = "http://www.w3.org/2001/04/xmlenc#sha512";
+const ALG_RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const ALG_RSA_SHA512: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha512";
+const ALG_ECDSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha256";
+const ALG_ECDSA_SHA384: &str = "http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha384";
+
+#[derive(Debug, Error)]
+pub enum XmlDsigError {
+    #[error("the element is not signed")]
+    NotSigned,
+    #[error("the signature is missing {0}")]
+    Missing(&'static str),
+    #[error("the signature must contain exactly one reference")]
+    ReferenceCount,
+    #[error("the signature does not reference the signed element")]
+    ReferenceMismatch,
+    #[error("unsupported algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("invalid base64 in {0}")]
+    InvalidBase64(&'static str),
+    #[error("the digest does not match")]
+    DigestMismatch,
+    #[error("the signature does not match any of the keys")]
+    BadSignature,
+}
+
+/// Whether a node is an element with the given namespace and local name.
+pub fn is_element(node: Node, namespace: &str, name: &str) -> bool {
+    node.is_element()
+        && node.tag_name().namespace() == Some(namespace)
+        && node.tag_name().name() == name
+}
+
+/// Find a child element by namespace and local name.
+pub fn find_child<'a, 'input>(
+    node: Node<'a, 'input>,
+    namespace: &str,
+    name: &str,
+) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|&child| is_element(child, namespace, name))
+}
+
+/// Iterate child elements with the given namespace and local name.
+pub fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    namespace: &'a str,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |&child| is_element(child, namespace, name))
+}
+
+/// Read the text content of an element that must contain nothing but text.
+///
+/// Returns `None` if the element contains comments or other nodes. Canonicalization drops
+/// comments, so reading only the first text node would let a signed `a<!---->b` be read as `a`.
+pub fn element_text<'a>(node: Node<'a, '_>) -> Option<&'a str> {
+    let mut children = node.children();
+    match (children.next(), children.next()) {
+        (Some(child), None) if child.is_text() => child.text(),
+        _ => None,
+    }
+}
+
+/// Find a child element of a signature, and read its `Algorithm` attribute.
+fn algorithm<'a, 'input>(
+    parent: Node<'a, 'input>,
+    name: &'static str,
+) -> Result<(Node<'a, 'input>, &'a str), XmlDsigError> {
+    find_child(parent, NS_DSIG, name)
+        .and_then(|node| node.attribute("Algorithm").map(|alg| (node, alg)))
+        .ok_or(XmlDsigError::Missing(name))
+}
+
+/// Decode base64 text content, which may contain whitespace.
+pub fn decode_base64_text(text: &str) -> Option<Vec<u8>> {
+    let text: String = text.split_ascii_whitespace().collect();
+    BASE64_STANDARD.decode(text).ok()
+}
+
+/// Verify the enveloped signature of an element. (XML-DSig)
+///
+/// Only a narrow profile is supported, which covers what SAML identity providers use in practice:
+/// a single reference to the signed element by its `ID` attribute, the enveloped signature
+/// transform followed by exclusive canonicalization, SHA-256 or SHA-512 digests, and RSA or ECDSA
+/// signatures. Any key info in the signature is ignored; `keys` are the trusted public keys, in
+/// the format of the `subjectPublicKey` field of a certificate.
+pub fn verify_enveloped(element: Node, keys: &[Vec<u8>]) -> Result<(), XmlDsigError> {
+    let signature = find_child(element, NS_DSIG, "Signature").ok_or(XmlDsigError::NotSigned)?;
+    let signed_info =
+        find_child(signature, NS_DSIG, "SignedInfo").ok_or(XmlDsigError::Missing("SignedInfo"))?;
+    let (c14n_method, c14n_alg) = algorithm(signed_info, "CanonicalizationMethod")?;
+    if c14n_alg != ALG_EXC_C14N {
+        return Err(XmlDsigError::UnsupportedAlgorithm(c14n_alg.to_owned()));
+    }
+    let (_, signature_alg) = algorithm(signed_info, "SignatureMethod")?;
+    let verification_alg: &dyn signature::VerificationAlgorithm = match signature_alg {
+        ALG_RSA_SHA256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+        ALG_RSA_SHA512 => &signature::RSA_PKCS1_2048_8192_SHA512,
+        ALG_ECDSA_SHA256 => &signature::ECDSA_P256_SHA256_FIXED,
+        ALG_ECDSA_SHA384 => &signature::ECDSA_P384_SHA384_FIXED,
+        other => return Err(XmlDsigError::UnsupportedAlgorithm(other.to_owned())),
+    };
+
+    // Check the reference is to the element itself. Because we never look up elements by ID, this
+    // prevents signature wrapping attacks.
+    let mut references = children(signed_info, NS_DSIG, "Reference");
+    let (Some(reference), None) = (references.next(), references.next()) else {
+        return Err(XmlDsigError::ReferenceCount);
+    };
+    let id = element
+        .attribute("ID")
+        .ok_or(XmlDsigError::ReferenceMismatch)?;
+    if reference.attribute("URI") != Some(&format!("#{id}")) {
+        return Err(XmlDsigError::ReferenceMismatch);
+    }
+
+    let transforms =
+        find_child(reference, NS_DSIG, "Transforms").ok_or(XmlDsigError::Missing("Transforms"))?;
+    let mut enveloped = false;
+    let mut c14n_prefixes = None;
+    for transform in children(transforms, NS_DSIG, "Transform") {
+        match transform.attribute("Algorithm").unwrap_or_default() {
+            ALG_ENVELOPED => enveloped = true,
+            ALG_EXC_C14N => c14n_prefixes = Some(inclusive_prefixes(transform)),
+            other => return Err(XmlDsigError::UnsupportedAlgorithm(other.to_owned())),
+        }
+    }
+    let (true, Some(c14n_prefixes)) = (enveloped, c14n_prefixes) else {
+        return Err(XmlDsigError::Missing(
+            "enveloped and exclusive canonicalization transforms",
+        ));
+    };
+
+    let (_, digest_alg) = algorithm(reference, "DigestMethod")?;
+    let digest_alg = match digest_alg {
+        ALG_SHA256 => &digest::SHA256,
+        ALG_SHA512 => &digest::SHA512,
+        other => return Err(XmlDsigError::UnsupportedAlgorithm(other.to_owned())),
+    };
+    let expected_digest = find_child(reference, NS_DSIG, "DigestValue")
+        .and_then(|node| node.text())
+        .ok_or(XmlDsigError::Missing("DigestValue"))?;
+    let expected_digest =
+        decode_base64_text(expected_digest).ok_or(XmlDsigError::InvalidBase64("DigestValue"))?;
+    let actual_digest = digest::digest(
+        digest_alg,
+        exc_c14n(element, Some(signature), &c14n_prefixes).as_bytes(),
+    );
+    if actual_digest.as_ref() != expected_digest {
+        return Err(XmlDsigError::DigestMismatch);
+    }
+
+    let signature_value = find_child(signature, NS_DSIG, "SignatureValue")
+        .and_then(|node| node.text())
+        .ok_or(XmlDsigError::Missing("SignatureValue"))?;
+    let signature_value =
+        decode_base64_text(signature_value).ok_or(XmlDsigError::InvalidBase64("SignatureValue"))?;
+    let message = exc_c14n(signed_info, None, &inclusive_prefixes(c14n_method));
+    if keys.iter().any(|key| {
+        signature::UnparsedPublicKey::new(verification_alg, key)
+            .verify(message.as_bytes(), &signature_value)
+            .is_ok()
+    }) {
+        Ok(())
+    } else {
+        Err(XmlDsigError::BadSignature)
+    }
+}
+
+/// Read the `InclusiveNamespaces` prefix list of a canonicalization method or transform.
+fn inclusive_prefixes<'a>(node: Node<'a, '_>) -> Vec<&'a str> {
+    find_child(node, ALG_EXC_C14N, "InclusiveNamespaces")
+        .and_then(|child| child.attribute("PrefixList"))
+        .map(|list| list.split_ascii_whitespace().collect())
+        .unwrap_or_default()
+}
+
+/// Canonicalize an element using Exclusive XML Canonicalization, without comments.
+///
+/// The `exclude` element and its descendants are omitted, which implements the enveloped
+/// signature transform. Prefixes in `inclusive_prefixes` are treated as in inclusive
+/// canonicalization, with `#default` for the default namespace.
+pub fn exc_c14n<'a>(
+    element: Node<'a, '_>,
+    exclude: Option<Node>,
+    inclusive_prefixes: &[&'a str],
+) -> String {
+    let mut out = String::new();
+    write_element(
+        &mut out,
+        element,
+        exclude,
+        inclusive_prefixes,
+        &mut Vec::new(),
+    );
+    out
+}
+
+/// The qualified name of an element, as written in the source.
+fn element_qname<'input>(node: Node<'_, 'input>) -> &'input str {
+    let source = &node.document().input_text()[node.range().start + 1..];
+    let end = source
+        .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+        .unwrap_or(source.len());
+    &source[..end]
+}
+
+/// The prefix of a qualified name, or the empty string if there is none.
+fn qname_prefix(qname: &str) -> &str {
+    qname.split_once(':').map_or("", |(prefix, _)| prefix)
+}
+
+/// Write an element. `rendered` holds the namespace declarations of output ancestors.
+fn write_element<'a, 'input: 'a>(
+    out: &mut String,
+    node: Node<'a, 'input>,
+    exclude: Option<Node>,
+    inclusive_prefixes: &[&'a str],
+    rendered: &mut Vec<(&'a str, &'a str)>,
+) {
+    let input = node.document().input_text();
+    let qname = element_qname(node);
+
+    let mut attributes: Vec<_> = node
+        .attributes()
+        .map(|attr| {
+            (
+                attr.namespace().unwrap_or_default(),
+                attr.name(),
+                &input[attr.range_qname()],
+                attr.value(),
+            )
+        })
+        .collect();
+    attributes.sort_unstable_by_key(|&(namespace, name, _, _)| (namespace, name));
+
+    // Namespaces that are visibly utilized, or listed as inclusive.
+    let mut prefixes = vec![qname_prefix(qname)];
+    prefixes.extend(
+        attributes
+            .iter()
+            .map(|&(_, _, qname, _)| qname_prefix(qname))
+            .filter(|prefix| !prefix.is_empty()),
+    );
+    prefixes.extend(inclusive_prefixes.iter().map(|&prefix| match prefix {
+        "#default" => "",
+        prefix => prefix,
+    }));
+    prefixes.sort_unstable();
+    prefixes.dedup();
+
+    let scope_len = rendered.len();
+    out.push('<');
+    out.push_str(qname);
+    for prefix in prefixes {
+        if prefix == "xml" {
+            continue;
+        }
+        let in_scope = node
+            .namespaces()
+            .find(|ns| ns.name().unwrap_or_default() == prefix)
+            .map(roxmltree::Namespace::uri);
+        let uri = match (prefix, in_scope) {
+            (_, Some(uri)) => uri,
+            ("", None) => "",
+            (_, None) => continue,
+        };
+        // Skip if already rendered by an output ancestor. The empty default namespace is
+        // implicitly rendered, and only needs a declaration to undo a non-empty one.
+        let current = rendered
+            .iter()
+            .rev()
+            .find(|&&(rendered_prefix, _)| rendered_prefix == prefix)
+            .map(|&(_, uri)| uri);
+        let is_rendered = if prefix.is_empty() {
+            current.unwrap_or_default() == uri
+        } else {
+            current == Some(uri)
+        };
+        if is_rendered {
+            continue;
+        }
+        rendered.push((prefix, uri));
+        if prefix.is_empty() {
+            out.push_str(" xmlns=\"");
+        } else {
+            out.push_str(" xmlns:");
+            out.push_str(prefix);
+            out.push_str("=\"");
+        }
+        escape_attribute(out, uri);
+        out.push('"');
+    }
+    for (_, _, qname, value) in attributes {
+        out.push(' ');
+        out.push_str(qname);
+        out.push_str("=\"");
+        escape_attribute(out, value);
+        out.push('"');
+    }
+    out.push('>');
+
+    for child in node.children() {
+        match child.node_type() {
+            NodeType::Element if Some(child) != exclude => {
+                write_element(out, child, exclude, inclusive_prefixes, rendered);
+            }
+            NodeType::Text => escape_text(out, child.text().unwrap_or_default()),
+            NodeType::PI => {
+                if let Some(pi) = child.pi() {
+                    out.push_str("<?");
+                    out.push_str(pi.target);
+                    if let Some(value) = pi.value {
+                        out.push(' ');
+                        out.push_str(value);
+                    }
+                    out.push_str("?>");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    out.push_str("</");
+    out.push_str(qname);
+    out.push('>');
+    rendered.truncate(scope_len);
+}
+
+fn escape_text(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Escape an attribute value, as canonical XML does. Also useful to build documents.
+pub fn escape_attribute(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#x9;"),
+            '\n' => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exc_c14n, find_child, verify_enveloped, XmlDsigError, NS_DSIG};
+    use base64::prelude::*;
+    use ring::digest;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use roxmltree::Document;
+
+    const SIGNED_TEMPLATE: &str = r##"<samlp:Response xmlns:samlp="urn:p" xmlns:saml="urn:a" xmlns:xs="http://www.w3.org/2001/XMLSchema" ID="r1"><saml:Assertion ID="a1"><saml:Issuer>idp</saml:Issuer><ds:Signature xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><ds:SignedInfo><ds:CanonicalizationMethod Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/><ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha256"/><ds:Reference URI="#a1"><ds:Transforms><ds:Transform Algorithm="http://www.w3.org/2000/09/xmldsig#enveloped-signature"/><ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"><ec:InclusiveNamespaces xmlns:ec="http://www.w3.org/2001/10/xml-exc-c14n#" PrefixList="xs"/></ds:Transform></ds:Transforms><ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/><ds:DigestValue>DIGEST</ds:DigestValue></ds:Reference></ds:SignedInfo><ds:SignatureValue>SIGNATURE</ds:SignatureValue></ds:Signature><saml:Subject>user@example.com</saml:Subject></saml:Assertion></samlp:Response>"##;
+
+    fn c14n_document(input: &str) -> String {
+        let doc = Document::parse(input).unwrap();
+        exc_c14n(doc.root_element(), None, &[])
+    }
+
+    /// Sign the template with the given key, like an identity provider would.
+    fn sign(key_pair: &EcdsaKeyPair) -> String {
+        let doc = Document::parse(SIGNED_TEMPLATE).unwrap();
+        let assertion = doc.root_element().first_child().unwrap();
+        let signature = find_child(assertion, NS_DSIG, "Signature").unwrap();
+        let digest = digest::digest(
+            &digest::SHA256,
+            exc_c14n(assertion, Some(signature), &["xs"]).as_bytes(),
+        );
+        let input = SIGNED_TEMPLATE.replace("DIGEST", &BASE64_STANDARD.encode(digest));
+
+        let doc = Document::parse(&input).unwrap();
+        let signed_info = doc
+            .descendants()
+            .find(|node| node.has_tag_name((NS_DSIG, "SignedInfo")))
+            .unwrap();
+        let message = exc_c14n(signed_info, None, &[]);
+        let signature = key_pair
+            .sign(&SystemRandom::new(), message.as_bytes())
+            .unwrap();
+        input.replace("SIGNATURE", &BASE64_STANDARD.encode(signature))
+    }
+
+    fn generate_key_pair() -> EcdsaKeyPair {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap()
+    }
+
+    fn verify(input: &str, key: &[u8]) -> Result<(), XmlDsigError> {
+        let doc = Document::parse(input).unwrap();
+        let assertion = doc.root_element().first_child().unwrap();
+        verify_enveloped(assertion, &[key.to_vec()])
+    }
+
+    #[test]
+    fn test_exc_c14n() {
+        // Expected output generated using `xmllint --exc-c14n`.
+        assert_eq!(
+            c14n_document(
+                r#"<root xmlns="http://a" xmlns:b="http://b" xmlns:unused="http://u" z="1" b:y="2" a="3&#9;&#10;&lt;&quot;>"><b:child   b:x='1'>t&amp;&gt;&#13;<![CDATA[<x>]]></b:child><child xmlns=""><?pi data?></child><e/><b:f xmlns:b="http://b2"/></root>"#
+            ),
+            r#"<root xmlns="http://a" xmlns:b="http://b" a="3&#x9;&#xA;&lt;&quot;>" z="1" b:y="2"><b:child b:x="1">t&amp;&gt;&#xD;&lt;x&gt;</b:child><child xmlns=""><?pi data?></child><e></e><b:f xmlns:b="http://b2"></b:f></root>"#
+        );
+        assert_eq!(
+            c14n_document(
+                r#"<samlp:Response xmlns:samlp="urn:p" xmlns:saml="urn:a" xmlns:xs="http://www.w3.org/2001/XMLSchema" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" ID="r1"><saml:Assertion ID="a1"><saml:AttributeValue xsi:type="xs:string">v</saml:AttributeValue></saml:Assertion></samlp:Response>"#
+            ),
+            r#"<samlp:Response xmlns:samlp="urn:p" ID="r1"><saml:Assertion xmlns:saml="urn:a" ID="a1"><saml:AttributeValue xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:type="xs:string">v</saml:AttributeValue></saml:Assertion></samlp:Response>"#
+        );
+    }
+
+    #[test]
+    fn test_exc_c14n_subtree() {
+        let doc = Document::parse(SIGNED_TEMPLATE).unwrap();
+        let assertion = doc.root_element().first_child().unwrap();
+        let signature = find_child(assertion, NS_DSIG, "Signature").unwrap();
+        assert_eq!(
+            exc_c14n(assertion, Some(signature), &["xs"]),
+            r#"<saml:Assertion xmlns:saml="urn:a" xmlns:xs="http://www.w3.org/2001/XMLSchema" ID="a1"><saml:Issuer>idp</saml:Issuer><saml:Subject>user@example.com</saml:Subject></saml:Assertion>"#
+        );
+    }
+
+    #[test]
+    fn test_verify_enveloped() {
+        let key_pair = generate_key_pair();
+        let key = key_pair.public_key().as_ref();
+        let signed = sign(&key_pair);
+        verify(&signed, key).unwrap();
+
+        let tampered = signed.replace("user@example.com", "evil@example.com");
+        assert!(matches!(
+            verify(&tampered, key),
+            Err(XmlDsigError::DigestMismatch)
+        ));
+        let other_key_pair = generate_key_pair();
+        assert!(matches!(
+            verify(&signed, other_key_pair.public_key().as_ref()),
+            Err(XmlDsigError::BadSignature)
+        ));
+        let wrapped = signed.replace(r##"URI="#a1""##, r##"URI="#r1""##);
+        assert!(matches!(
+            verify(&wrapped, key),
+            Err(XmlDsigError::ReferenceMismatch)
+        ));
+    }
+}
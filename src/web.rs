@@ -1,7 +1,7 @@
 use crate::agents::{GetSession, SaveSession};
 use crate::bridges::BridgeData;
 use crate::config::ConfigRc;
-use crate::crypto::{self, SigningAlgorithm};
+use crate::crypto::{self, CodeChallenge, SigningAlgorithm};
 use crate::email_address::EmailAddress;
 use crate::error::{BrokerError, BrokerResult};
 use crate::metrics;
@@ -11,7 +11,7 @@ use bytes::{Bytes, BytesMut};
 use futures_util::stream::StreamExt;
 use gettext::Catalog;
 use headers::{CacheControl, ContentType, Header, StrictTransportSecurity};
-use http::{HeaderMap, Method, StatusCode, Uri};
+use http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
 use hyper::service::Service as HyperService;
 use hyper::Body;
 use log::info;
@@ -49,6 +49,14 @@ pub enum ResponseType {
 }
 
 impl ResponseType {
+    /// Get the string representation used in requests.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResponseType::IdToken => "id_token",
+            ResponseType::Code => "code",
+        }
+    }
+
     /// Get the default response mode for this response type.
     pub fn default_response_mode(self) -> ResponseMode {
         match self {
@@ -58,14 +66,81 @@ impl ResponseType {
     }
 }
 
+impl std::str::FromStr for ResponseType {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<ResponseType, &'static str> {
+        match s {
+            "id_token" => Ok(ResponseType::IdToken),
+            "code" => Ok(ResponseType::Code),
+            _ => Err("unsupported response_type, must be id_token or code"),
+        }
+    }
+}
+
 /// Response modes we support.
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ResponseMode {
     Fragment,
     FormPost,
     // NOTE: This mode is outside the Portier spec, but we support it in this implementation for
     // compatibility with other OpenID Connect clients.
     Query,
+    // JWT Secured Authorization Response Modes (JARM), which wrap the response in a signed JWT.
+    // These are also outside the Portier spec.
+    FragmentJwt,
+    FormPostJwt,
+    QueryJwt,
+}
+
+impl ResponseMode {
+    /// Get the string representation used in requests.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResponseMode::Fragment => "fragment",
+            ResponseMode::FormPost => "form_post",
+            ResponseMode::Query => "query",
+            ResponseMode::FragmentJwt => "fragment.jwt",
+            ResponseMode::FormPostJwt => "form_post.jwt",
+            ResponseMode::QueryJwt => "query.jwt",
+        }
+    }
+
+    /// Get the JARM variant of this response mode.
+    pub fn jwt(self) -> ResponseMode {
+        match self {
+            ResponseMode::Fragment | ResponseMode::FragmentJwt => ResponseMode::FragmentJwt,
+            ResponseMode::FormPost | ResponseMode::FormPostJwt => ResponseMode::FormPostJwt,
+            ResponseMode::Query | ResponseMode::QueryJwt => ResponseMode::QueryJwt,
+        }
+    }
+
+    /// For JARM response modes, get the underlying response mode used to deliver the JWT.
+    pub fn jwt_base(self) -> Option<ResponseMode> {
+        match self {
+            ResponseMode::FragmentJwt => Some(ResponseMode::Fragment),
+            ResponseMode::FormPostJwt => Some(ResponseMode::FormPost),
+            ResponseMode::QueryJwt => Some(ResponseMode::Query),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for ResponseMode {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<ResponseMode, &'static str> {
+        match s {
+            "fragment" => Ok(ResponseMode::Fragment),
+            "form_post" => Ok(ResponseMode::FormPost),
+            "query" => Ok(ResponseMode::Query),
+            "fragment.jwt" => Ok(ResponseMode::FragmentJwt),
+            "form_post.jwt" => Ok(ResponseMode::FormPostJwt),
+            "query.jwt" => Ok(ResponseMode::QueryJwt),
+            _ => Err(
+                "unsupported response_mode, must be fragment, form_post, query, \
+                      fragment.jwt, form_post.jwt, query.jwt or jwt",
+            ),
+        }
+    }
 }
 
 /// Parameters used to return to the relying party
@@ -87,6 +162,14 @@ pub struct SessionData {
     pub response_type: ResponseType,
     pub nonce: Option<String>,
     pub signing_alg: SigningAlgorithm,
+    #[serde(default)]
+    pub code_challenge: Option<CodeChallenge>,
+    /// Set if the session authenticates a device authorization request.
+    #[serde(default)]
+    pub device_code: Option<String>,
+    /// UNIX timestamp when the user verified the email address, once verified.
+    #[serde(default)]
+    pub auth_time: Option<u64>,
 }
 
 /// Context for a request
@@ -142,6 +225,7 @@ impl Context {
         response_type: ResponseType,
         nonce: Option<String>,
         signing_alg: SigningAlgorithm,
+        code_challenge: Option<CodeChallenge>,
         ip: IpAddr,
     ) {
         assert!(self.session_id.is_empty());
@@ -159,6 +243,9 @@ impl Context {
             response_type,
             nonce,
             signing_alg,
+            code_challenge,
+            device_code: None,
+            auth_time: None,
         });
     }
 
@@ -349,6 +436,9 @@ async fn handle_error(ctx: &Context, err: BrokerError) -> Response {
             "reference": reference,
         }));
         *res.status_mut() = err.http_status_code();
+        if res.status() == StatusCode::UNAUTHORIZED {
+            res.header(hyper::header::WWW_AUTHENTICATE, "Basic");
+        }
         return res;
     }
 
@@ -371,15 +461,28 @@ async fn handle_error(ctx: &Context, err: BrokerError) -> Response {
             | BrokerError::Provider(_)
             | BrokerError::ProviderInput(_)),
             true,
-        ) => return_to_relier(
-            ctx,
-            &[
-                ("error", err.oauth_error_code()),
-                ("error_description", &format!("{err}")),
-            ],
-        ),
+        ) => {
+            let res = return_to_relier(
+                ctx,
+                &[
+                    ("error", err.oauth_error_code()),
+                    ("error_description", &format!("{err}")),
+                ],
+            )
+            .await;
+            match res {
+                Ok(res) => res,
+                // Internal errors are never redirected, so this recurses only once.
+                Err(err) => Box::pin(handle_error(ctx, err)).await,
+            }
+        }
         // Friendly error pages for what we can't redirect.
-        (err @ (BrokerError::Input(_) | BrokerError::SpecificInput { .. }), false) => {
+        (
+            err @ (BrokerError::Input(_)
+            | BrokerError::SpecificInput { .. }
+            | BrokerError::ClientAuth(_)),
+            _,
+        ) => {
             let mut res = html_response(ctx.app.templates.error.render(&[
                 ("error", &format!("{err}")),
                 ("intro", catalog.gettext("The request is invalid, and could not be completed.")),
@@ -433,27 +536,52 @@ async fn handle_error(ctx: &Context, err: BrokerError) -> Response {
             *res.status_mut() = err.http_status_code();
             res
         }
+        (err @ BrokerError::SessionLocked, _) => {
+            let mut res = html_response(ctx.app.templates.error.render(&[
+                ("intro", catalog.gettext("Too many incorrect codes.")),
+                ("explanation", catalog.gettext("The code from the email was entered incorrectly too many times, so this login attempt was stopped. Please try again.")),
+            ]));
+            *res.status_mut() = err.http_status_code();
+            res
+        }
         // Internal status that should never bubble this far
         (BrokerError::ProviderCancelled, _) => unreachable!(),
     }
 }
 
+/// Specify a tight content security policy. We need to be able to POST redirect anywhere, and run
+/// our own scripts.
+const CSP: &str = concat!(
+    "sandbox allow-scripts allow-forms",
+    "; default-src 'none'",
+    "; script-src 'self'",
+    "; style-src 'self'",
+    "; form-action *",
+);
+
+/// Like `CSP`, but the page keeps our origin and may make requests to it. Passkeys require our
+/// origin, and the email confirmation page polls for the session status.
+pub const CSP_SAME_ORIGIN: &str = concat!(
+    "sandbox allow-scripts allow-forms allow-same-origin",
+    "; default-src 'none'",
+    "; script-src 'self'",
+    "; style-src 'self'",
+    "; connect-src 'self'",
+    "; form-action *",
+);
+
 /// Mutate a response to set common headers.
 fn set_headers<B>(res: &mut hyper::Response<B>) {
-    // Specify a tight content security policy. We need to be able to POST
-    // redirect anywhere, and run our own scripts.
-    let csp = concat!(
-        "sandbox allow-scripts allow-forms",
-        "; default-src 'none'",
-        "; script-src 'self'",
-        "; style-src 'self'",
-        "; form-action *",
-    );
-
     res.typed_header(StrictTransportSecurity::excluding_subdomains(
         Duration::from_secs(31_536_000_u64),
     ));
-    res.header(hyper::header::CONTENT_SECURITY_POLICY, csp);
+    // Default to our tight content security policy, unless the handler chose another.
+    let csp = res
+        .headers()
+        .get(hyper::header::CONTENT_SECURITY_POLICY)
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::from_static(CSP));
+    res.header(hyper::header::CONTENT_SECURITY_POLICY, csp.clone());
     res.header("x-content-security-policy", csp);
     res.header(hyper::header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_owned());
     res.header(hyper::header::X_XSS_PROTECTION, "1; mode=block".to_owned());
@@ -492,7 +620,10 @@ pub async fn read_body(body: &mut Body) -> Result<Bytes, BoxError> {
 /// Takes an array of `(name, value)` parameter pairs and returns a response
 /// that sends them to the RP's `redirect_uri`. The method used to return to
 /// the RP depends on the `response_mode`.
-pub fn return_to_relier(ctx: &Context, params: &[(&str, &str)]) -> Response {
+///
+/// For JARM response modes, the parameters are wrapped in a signed JWT, which
+/// is sent as the single `response` parameter.
+pub async fn return_to_relier(ctx: &Context, params: &[(&str, &str)]) -> BrokerResult<Response> {
     let &ReturnParams {
         ref redirect_uri,
         response_mode,
@@ -503,13 +634,31 @@ pub fn return_to_relier(ctx: &Context, params: &[(&str, &str)]) -> Response {
         .as_ref()
         .expect("return_to_relier called without return parameters");
 
-    match response_mode {
+    let mut params: Vec<(&str, &str)> = [("state", state.as_str())]
+        .iter()
+        .chain(params)
+        .copied()
+        .collect();
+    let jwt;
+    let response_mode = if let Some(base) = response_mode.jwt_base() {
+        let origin = redirect_uri.origin().ascii_serialization();
+        jwt = crypto::create_jarm_response(&ctx.app, &origin, &params)
+            .await
+            .map_err(|err| {
+                BrokerError::Internal(format!("Could not create a JARM response: {err:?}"))
+            })?;
+        params = vec![("response", &jwt)];
+        base
+    } else {
+        response_mode
+    };
+
+    Ok(match response_mode {
         // Add params as fragment parameters and redirect.
         ResponseMode::Fragment => {
             let mut redirect_uri = redirect_uri.clone();
             let fragment = redirect_uri.fragment().unwrap_or("").to_owned();
             let fragment = form_urlencoded::Serializer::for_suffix(fragment, 0)
-                .append_pair("state", state)
                 .extend_pairs(params)
                 .finish();
             redirect_uri.set_fragment(Some(&fragment));
@@ -519,41 +668,37 @@ pub fn return_to_relier(ctx: &Context, params: &[(&str, &str)]) -> Response {
             res
         }
         // Render a form that submits a POST request.
-        ResponseMode::FormPost => {
-            let data = mustache::MapBuilder::new()
-                .insert_str("redirect_uri", redirect_uri.to_string())
-                .insert_vec("params", |mut builder| {
-                    builder = builder.push_map(|builder| {
-                        builder
-                            .insert_str("name", "state")
-                            .insert_str("value", state)
-                    });
-                    for &param in params {
-                        builder = builder.push_map(|builder| {
-                            let (name, value) = param;
-                            builder.insert_str("name", name).insert_str("value", value)
-                        });
-                    }
-                    builder
-                })
-                .build();
-
-            html_response(ctx.app.templates.forward.render_data(&data))
-        }
+        ResponseMode::FormPost => form_post_response(ctx, redirect_uri.as_str(), &params),
         // Add params as query parameters and redirect.
         ResponseMode::Query => {
             let mut redirect_uri = redirect_uri.clone();
-            redirect_uri
-                .query_pairs_mut()
-                .append_pair("state", state)
-                .extend_pairs(params)
-                .finish();
+            redirect_uri.query_pairs_mut().extend_pairs(params).finish();
 
             let mut res = empty_response(StatusCode::SEE_OTHER);
             res.header(hyper::header::LOCATION, String::from(redirect_uri));
             res
         }
-    }
+        ResponseMode::FragmentJwt | ResponseMode::FormPostJwt | ResponseMode::QueryJwt => {
+            unreachable!()
+        }
+    })
+}
+
+/// Render a form that submits a POST request with the given parameters.
+pub fn form_post_response(ctx: &Context, url: &str, params: &[(&str, &str)]) -> Response {
+    let data = mustache::MapBuilder::new()
+        .insert_str("redirect_uri", url)
+        .insert_vec("params", |mut builder| {
+            for &(name, value) in params {
+                builder = builder.push_map(|builder| {
+                    builder.insert_str("name", name).insert_str("value", value)
+                });
+            }
+            builder
+        })
+        .build();
+
+    html_response(ctx.app.templates.forward.render_data(&data))
 }
 
 /// Helper function for returning a response with JSON data.
@@ -583,7 +728,7 @@ pub fn empty_response(status: StatusCode) -> Response {
 
 #[cfg(test)]
 mod tests {
-    use super::set_headers;
+    use super::{set_headers, ResponseMode};
     use http::Response;
 
     #[test]
@@ -600,4 +745,14 @@ mod tests {
         assert!(headers.contains_key("X-Frame-Options"));
         assert!(headers.contains_key("Cache-Control"));
     }
+
+    #[test]
+    fn parses_jarm_response_modes() {
+        let mode: ResponseMode = "form_post.jwt".parse().unwrap();
+        assert!(mode == ResponseMode::FormPostJwt);
+        assert!(mode.jwt_base() == Some(ResponseMode::FormPost));
+        assert!(ResponseMode::Query.jwt() == ResponseMode::QueryJwt);
+        assert!(ResponseMode::Query.jwt_base().is_none());
+        assert!("jwt".parse::<ResponseMode>().is_err());
+    }
 }
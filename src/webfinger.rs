@@ -1,8 +1,9 @@
-use crate::agents::FetchUrlCached;
-use crate::config::ConfigRc;
+use crate::agents::{FetchUrlCached, GetCacheEntry, SaveCacheEntry};
+use crate::config::{Config, ConfigRc};
 use crate::email_address::EmailAddress;
 use crate::error::BrokerError;
 use crate::metrics;
+use futures_util::future;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Error as FmtError, Formatter};
 use std::str::FromStr;
@@ -13,6 +14,15 @@ use url::Url;
 pub const WEBFINGER_PORTIER_REL: &str = "https://portier.io/specs/auth/1.0/idp";
 /// Portier + Google webfinger relation
 pub const WEBFINGER_GOOGLE_REL: &str = "https://portier.io/specs/auth/1.0/idp/google";
+/// Generic OpenID Connect relation, only valid in `domain_overrides`
+pub const WEBFINGER_OIDC_REL: &str = "https://portier.io/specs/auth/1.0/idp/oidc";
+/// SAML 2.0 identity provider relation, only valid in `domain_overrides`
+pub const WEBFINGER_SAML_REL: &str = "https://portier.io/specs/auth/1.0/idp/saml";
+
+/// Label prepended to the email domain to find DNS TXT records for discovery
+pub const DNS_DISCOVERY_LABEL: &str = "_portier";
+/// Version tag that DNS TXT records for discovery must start with
+pub const DNS_DISCOVERY_VERSION: &str = "portier1";
 
 /// Deserialization types
 #[derive(Deserialize)]
@@ -40,6 +50,8 @@ pub enum ParseRelationError {
 pub enum Relation {
     Portier,
     Google,
+    Oidc,
+    Saml,
 }
 
 impl Display for Relation {
@@ -47,6 +59,8 @@ impl Display for Relation {
         match self {
             Relation::Portier => Display::fmt(WEBFINGER_PORTIER_REL, f),
             Relation::Google => Display::fmt(WEBFINGER_GOOGLE_REL, f),
+            Relation::Oidc => Display::fmt(WEBFINGER_OIDC_REL, f),
+            Relation::Saml => Display::fmt(WEBFINGER_SAML_REL, f),
         }
     }
 }
@@ -58,6 +72,8 @@ impl FromStr for Relation {
         match s {
             WEBFINGER_PORTIER_REL => Ok(Relation::Portier),
             WEBFINGER_GOOGLE_REL => Ok(Relation::Google),
+            WEBFINGER_OIDC_REL => Ok(Relation::Oidc),
+            WEBFINGER_SAML_REL => Ok(Relation::Saml),
             value => Err(ParseRelationError::InvalidValue(value.to_owned())),
         }
     }
@@ -72,6 +88,8 @@ pub enum ParseLinkError {
     InvalidRelation(#[from] ParseRelationError),
     #[error("invalid href: {0}")]
     InvalidHref(#[from] url::ParseError),
+    #[error("missing {0}")]
+    Missing(&'static str),
 }
 
 /// Parsed and validated webfinger link
@@ -88,19 +106,98 @@ impl Link {
         let href = link.href.parse()?;
         Ok(Link { rel, href })
     }
+
+    /// Parse and validate a DNS TXT record for discovery
+    ///
+    /// Records have the form `v=portier1; rel=<relation>; href=<url>`. Returns `None` for records
+    /// that don't start with the version tag, so other TXT records can exist at the same name.
+    pub fn from_dns_record(record: &str) -> Option<Result<Link, ParseLinkError>> {
+        let mut fields = record
+            .split(';')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(|field| field.split_once('=').unwrap_or((field, "")));
+        if fields.next() != Some(("v", DNS_DISCOVERY_VERSION)) {
+            return None;
+        }
+        let mut link = LinkDef {
+            rel: String::new(),
+            href: String::new(),
+        };
+        for (key, value) in fields {
+            match key {
+                "rel" => value.clone_into(&mut link.rel),
+                "href" => value.clone_into(&mut link.href),
+                // Ignore unknown fields, for forwards compatibility.
+                _ => {}
+            }
+        }
+        if link.rel.is_empty() {
+            return Some(Err(ParseLinkError::Missing("rel")));
+        }
+        if link.href.is_empty() {
+            return Some(Err(ParseLinkError::Missing("href")));
+        }
+        Some(Link::from_de_link(&link))
+    }
 }
 
-/// Query webfinger for the given email address
+/// Discover identity providers for the given email address
 ///
-/// This queries the webfinger endpoint of the domain for the given email
-/// address. The resource queried is the email address itself, as an `acct` URL.
-/// Request failures of any kind simply result in an empty list.
-pub async fn query(app: &ConfigRc, email_addr: &EmailAddress) -> Result<Vec<Link>, BrokerError> {
+/// Configuration overrides take precedence. Otherwise, webfinger is queried, and if enabled, DNS
+/// TXT records are also queried. Links from webfinger come first, followed by any links from DNS
+/// that webfinger did not already return. If one of the two fails, the results of the other are
+/// still used.
+///
+/// Arguments are owned, so the query can run as a separate task.
+pub async fn query(app: ConfigRc, email_addr: EmailAddress) -> Result<Vec<Link>, BrokerError> {
     // Look for a configuration override.
     if let Some(mapped) = app.domain_overrides.get(email_addr.domain()) {
         return Ok(mapped.clone());
     }
 
+    if !app.dns_discovery {
+        return query_webfinger(&app, &email_addr).await;
+    }
+
+    match future::join(
+        query_webfinger(&app, &email_addr),
+        query_dns(&app, email_addr.domain()),
+    )
+    .await
+    {
+        (Ok(mut links), Ok(dns_links)) => {
+            for link in dns_links {
+                if !links.contains(&link) {
+                    links.push(link);
+                }
+            }
+            Ok(links)
+        }
+        (Ok(links), Err(err)) | (Err(err), Ok(links)) if !links.is_empty() => {
+            err.log(None).await;
+            Ok(links)
+        }
+        (Err(err), _) | (_, Err(err)) => Err(err),
+    }
+}
+
+/// Whether a discovered link is usable.
+fn is_usable_link(app: &Config, link: &Link) -> bool {
+    // Generic and SAML providers require registration, so are only configured locally.
+    !matches!(link.rel, Relation::Oidc | Relation::Saml)
+        // Sanity check: skip results that refer to ourselves.
+        && link.href.as_str() != app.public_url
+}
+
+/// Query webfinger for the given email address
+///
+/// This queries the webfinger endpoint of the domain for the given email
+/// address. The resource queried is the email address itself, as an `acct` URL.
+async fn query_webfinger(
+    app: &Config,
+    email_addr: &EmailAddress,
+) -> Result<Vec<Link>, BrokerError> {
     // Build the webfinger query URL. We can safely do string concatenation here, because the
     // domain has already been validated using the `url` crate.
     #[cfg(feature = "insecure")]
@@ -135,9 +232,81 @@ pub async fn query(app: &ConfigRc, email_addr: &EmailAddress) -> Result<Vec<Link
         .links
         .iter()
         .filter_map(|link| Link::from_de_link(link).ok())
-        // Sanity check: skip results that refer to ourselves.
-        .filter(|link| link.href.as_str() != app.public_url)
+        .filter(|link| is_usable_link(app, link))
         .collect();
 
     Ok(links)
 }
+
+/// Query DNS TXT records for the given email domain
+///
+/// Records are looked up at `_portier.<domain>` using the resolver configured for domain
+/// validation, and the answer is cached in the store.
+async fn query_dns(app: &Config, domain: &str) -> Result<Vec<Link>, BrokerError> {
+    let name = format!("{DNS_DISCOVERY_LABEL}.{domain}");
+    let key: Url = format!("dns:{name}?type=TXT")
+        .parse()
+        .map_err(|e| BrokerError::Internal(format!("could not build dns cache key: {e}")))?;
+
+    let cached = app
+        .store
+        .send(GetCacheEntry { key: key.clone() })
+        .await
+        .map_err(|e| BrokerError::Internal(format!("could not read dns cache: {e}")))?;
+    let records: Vec<String> = if let Some(data) = cached {
+        serde_json::from_str(&data)
+            .map_err(|e| BrokerError::Internal(format!("invalid dns cache entry: {e}")))?
+    } else {
+        let timer = metrics::AUTH_DNS_DISCOVERY_DURATION.start_timer();
+        let result = app.domain_validator.lookup_txt(&name).await;
+        timer.observe_duration();
+        let (records, ttl) = match result {
+            Some(Ok(answer)) => answer,
+            Some(Err(e)) => {
+                return Err(BrokerError::Provider(format!("dns discovery failed: {e}")));
+            }
+            // Configuration ensures a resolver is set when DNS discovery is enabled.
+            None => return Ok(vec![]),
+        };
+        let data = serde_json::to_string(&records).expect("could not serialize dns records");
+        app.store
+            .send(SaveCacheEntry { key, data, ttl })
+            .await
+            .map_err(|e| BrokerError::Internal(format!("could not write dns cache: {e}")))?;
+        records
+    };
+
+    let links = records
+        .iter()
+        .filter_map(|record| Link::from_dns_record(record)?.ok())
+        .filter(|link| is_usable_link(app, link))
+        .collect();
+
+    Ok(links)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Link, ParseLinkError, Relation};
+
+    #[test]
+    fn test_from_dns_record() {
+        let link = Link::from_dns_record(
+            "v=portier1; rel=https://portier.io/specs/auth/1.0/idp; href=https://idp.example.com",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(link.rel, Relation::Portier);
+        assert_eq!(link.href.as_str(), "https://idp.example.com/");
+
+        assert!(Link::from_dns_record("v=spf1 -all").is_none());
+        assert!(matches!(
+            Link::from_dns_record("v=portier1; href=https://idp.example.com"),
+            Some(Err(ParseLinkError::Missing("rel")))
+        ));
+        assert!(matches!(
+            Link::from_dns_record("v=portier1; rel=unknown; href=https://idp.example.com"),
+            Some(Err(ParseLinkError::InvalidRelation(_)))
+        ));
+    }
+}